@@ -1,39 +1,465 @@
-use invoice::InvoiceIndex;
-use std::error::Error;
-use std::fmt;
+use error::{Error, Result};
+use invoice::{Date, InvoiceIndex, Kind};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use {Dimension, Format, Outputs};
+
+fn wrong(message: &str) -> Error {
+    Error::Arguments(message.to_string())
+}
+
+/// Parses `arg` as the `what` argument.
+fn number<T: FromStr>(arg: &str, what: &str) -> Result<T> {
+    arg.parse().map_err(|_| wrong(&format!("invalid {} `{}`", what, arg)))
+}
+
+pub const USAGE: &str = "\
+Usage: generate-invoice [--data-dir <dir>] <command> [<args>]
+
+Commands:
+    generate      Render invoices to HTML, PDF and JSON
+    list          List the invoices of a year
+    show          Print a summary of an invoice
+    validate      Check the CSV files for mistakes
+    import-csv    Copy the CSV files into an SQLite database
+    import-hours  Create invoices from a time-tracking log
+    recurring     Create the recurring invoices that are due
+    outstanding   List the invoices that are not paid in full
+    reminders     Write reminders for overdue invoices
+    report        Add up the invoices by client, business or period
+    help          Print help for a command
+
+The data is read from --data-dir, which defaults to the current directory.
+
+`generate-invoice <year> <index>` is short for
+`generate-invoice generate <year> <index>`.
+";
+
+const GENERATE_USAGE: &str = "\
+Usage: generate-invoice generate [options] <year> [<indices>]
+
+Renders every invoice of <year>, or only those selected by <indices>: either
+a single index, or an inclusive range such as `3..12`, `3..` or `..12`.
+
+Options:
+    --out-dir <dir>    Write the generated files below <dir> rather than the
+                       data directory
+    --no-pdf           Only write the HTML invoice
+    --no-json          Do not write the JSON order
+    --credit-notes     Render credit notes rather than invoices
+";
+
+const LIST_USAGE: &str = "\
+Usage: generate-invoice list [--credit-notes] <year>
+";
+
+const SHOW_USAGE: &str = "\
+Usage: generate-invoice show [--credit-notes] <year> <index>
+";
+
+const VALIDATE_USAGE: &str = "\
+Usage: generate-invoice validate
+
+Checks every CSV file in the data directory and builds every invoice as
+generate does, reporting all problems found.
+";
+
+const IMPORT_CSV_USAGE: &str = "\
+Usage: generate-invoice import-csv
+
+Copies the CSV files in the data directory into a new SQLite database,
+invoices.sqlite, which is used instead of the CSV files from then on.
+";
+
+const IMPORT_HOURS_USAGE: &str = "\
+Usage: generate-invoice import-hours [options] <log>
+
+Adds an invoice per client for the hours in <log>, with a line item per
+project and month, priced at the hourly-rate set in invoices.toml. <log> is a
+CSV file with the columns date, client, project, hours and note, or a
+detailed CSV export from Toggl or Clockify.
+
+Options:
+    --business <id>    The business sending the invoices; may be left out if
+                       there is only one
+    --date <date>      The date of the invoices, as YYYY-MM-DD; defaults to
+                       today
+";
+
+const RECURRING_USAGE: &str = "\
+Usage: generate-invoice recurring [--until <date>]
+
+Adds every invoice scheduled in recurring.csv up to and including <date>, as
+YYYY-MM-DD, or today. Invoices that were added before, as recorded in
+recurring-issued.csv, are skipped.
+";
+
+const OUTSTANDING_USAGE: &str = "\
+Usage: generate-invoice outstanding
+
+Lists the open balance of every invoice that is not paid in full, by client,
+from the payments in payments.csv and the credit notes.
+";
+
+const REMINDERS_USAGE: &str = "\
+Usage: generate-invoice reminders [options]
+
+Writes a reminder letter for every invoice that is overdue on <date>, next to
+the invoice: a first, second or final reminder depending on how many days
+overdue it is, following reminder-days in invoices.toml. Reminders written as
+PDF are recorded in reminders-sent.csv, and skipped from then on.
+
+Options:
+    --date <date>      The date of the reminders, as YYYY-MM-DD; defaults to
+                       today
+    --out-dir <dir>    Write the reminders below <dir> rather than the data
+                       directory
+    --no-pdf           Only write the HTML letters, as drafts that are not
+                       recorded as sent
+";
+
+const REPORT_USAGE: &str = "\
+Usage: generate-invoice report [options]
+
+Adds up the invoices of every year, less the credit notes, by one or more of
+client, business, month, quarter and year, and by currency.
+
+Options:
+    --by <dimensions>  What to group by, separated by commas, such as
+                       `client,year`; defaults to year
+    --format <format>  Write a table, csv or json; defaults to table
+";
+
+/// The help text for `command`, or the general usage if there is no such
+/// command.
+pub fn usage(command: Option<&str>) -> &'static str {
+    match command {
+        Some("generate") => GENERATE_USAGE,
+        Some("list") => LIST_USAGE,
+        Some("show") => SHOW_USAGE,
+        Some("validate") => VALIDATE_USAGE,
+        Some("import-csv") => IMPORT_CSV_USAGE,
+        Some("import-hours") => IMPORT_HOURS_USAGE,
+        Some("recurring") => RECURRING_USAGE,
+        Some("outstanding") => OUTSTANDING_USAGE,
+        Some("reminders") => REMINDERS_USAGE,
+        Some("report") => REPORT_USAGE,
+        _ => USAGE,
+    }
+}
+
+#[derive(Debug)]
+pub enum Command {
+    Generate {
+        kind: Kind,
+        year: u16,
+        indices: RangeInclusive<u8>,
+        out_dir: PathBuf,
+        outputs: Outputs,
+    },
+    List {
+        kind: Kind,
+        year: u16,
+    },
+    Show {
+        index: InvoiceIndex,
+    },
+    Validate,
+    ImportCsv,
+    ImportHours {
+        log: PathBuf,
+        business: Option<String>,
+        date: Date,
+    },
+    Recurring {
+        until: Date,
+    },
+    Outstanding,
+    Reminders {
+        date: Date,
+        out_dir: PathBuf,
+        pdf: bool,
+    },
+    Report {
+        dimensions: Vec<Dimension>,
+        format: Format,
+    },
+    Help(Option<String>),
+}
 
 #[derive(Debug)]
-struct WrongArguments(&'static str);
+pub struct Invocation {
+    pub data_dir: PathBuf,
+    pub command: Command,
+}
+
+/// The arguments following the command name, split into positional
+/// arguments and `--options`.
+struct Arguments {
+    positional: Vec<String>,
+    options: Vec<(String, Option<String>)>,
+}
+
+impl Arguments {
+    /// Splits `args`; the options named in `valued` consume a value, either
+    /// as `--name=value` or as the next argument.
+    fn new<I>(args: I, valued: &[&str]) -> Result<Self>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut positional = vec![];
+        let mut options = vec![];
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if !arg.starts_with("--") {
+                positional.push(arg);
+                continue;
+            }
+
+            let (name, value) = match arg.find('=') {
+                Some(i) => (arg[2..i].to_string(), Some(arg[i + 1..].to_string())),
+                None => (arg[2..].to_string(), None),
+            };
 
-impl fmt::Display for WrongArguments {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        self.0.fmt(f)
+            let value = if valued.contains(&&*name) {
+                let value = value.or_else(|| args.next());
+                Some(value.ok_or_else(|| wrong(&format!("missing value for --{}", name)))?)
+            } else if value.is_some() {
+                return Err(wrong(&format!("--{} does not take a value", name)));
+            } else {
+                None
+            };
+
+            options.push((name, value));
+        }
+
+        Ok(Self {
+            positional,
+            options,
+        })
+    }
+
+    fn take(&mut self, name: &str) -> Option<Option<String>> {
+        let position = self.options.iter().position(|option| option.0 == name)?;
+        Some(self.options.remove(position).1)
+    }
+
+    fn value(&mut self, name: &str) -> Option<String> {
+        self.take(name).and_then(|value| value)
+    }
+
+    fn flag(&mut self, name: &str) -> bool {
+        self.take(name).is_some()
+    }
+
+    fn help(&mut self) -> bool {
+        self.flag("help")
+    }
+
+    /// Credit notes with `--credit-notes`, or invoices.
+    fn kind(&mut self) -> Kind {
+        if self.flag("credit-notes") {
+            Kind::CreditNote
+        } else {
+            Kind::Invoice
+        }
+    }
+
+    /// Checks that every argument was consumed.
+    fn finish(self) -> Result<()> {
+        if let Some((name, _)) = self.options.first() {
+            return Err(wrong(&format!("unknown option --{}", name)));
+        }
+        if !self.positional.is_empty() {
+            Err(wrong("too many arguments"))?;
+        }
+        Ok(())
+    }
+
+    fn year(&mut self) -> Result<u16> {
+        if self.positional.is_empty() {
+            Err(wrong("missing year"))?;
+        }
+        number(&self.positional.remove(0), "year")
+    }
+
+    /// An optional index or inclusive range of indices; all indices if absent.
+    fn indices(&mut self) -> Result<RangeInclusive<u8>> {
+        if self.positional.is_empty() {
+            return Ok(u8::MIN..=u8::MAX);
+        }
+
+        let arg = self.positional.remove(0);
+        let (first, last) = match arg.find("..") {
+            Some(i) => {
+                let last = arg[i + 2..].trim_start_matches('=');
+                (&arg[..i], last)
+            },
+            None => (&*arg, &*arg),
+        };
+
+        let first = if first.is_empty() {
+            u8::MIN
+        } else {
+            number(first, "index")?
+        };
+        let last = if last.is_empty() {
+            u8::MAX
+        } else {
+            number(last, "index")?
+        };
+        Ok(first..=last)
+    }
+
+    fn index(&mut self) -> Result<InvoiceIndex> {
+        let kind = self.kind();
+        let year = self.year()?;
+        if self.positional.is_empty() {
+            Err(wrong("missing index"))?;
+        }
+        let index = number(&self.positional.remove(0), "index")?;
+        Ok(InvoiceIndex {
+            kind,
+            year,
+            index,
+        })
     }
 }
 
-impl Error for WrongArguments {
-    fn description(&self) -> &str {
-        self.0
+fn parse_generate(args: &mut Arguments, data_dir: &Path) -> Result<Command> {
+    let pdf = !args.flag("no-pdf");
+    let json = !args.flag("no-json");
+    let out_dir = args.value("out-dir").map_or_else(|| data_dir.to_owned(), PathBuf::from);
+    let kind = args.kind();
+    let year = args.year()?;
+    let indices = args.indices()?;
+
+    Ok(Command::Generate {
+        kind,
+        year,
+        indices,
+        out_dir,
+        outputs: Outputs {
+            pdf,
+            json,
+        },
+    })
+}
+
+fn parse_list(args: &mut Arguments) -> Result<Command> {
+    let kind = args.kind();
+    let year = args.year()?;
+    Ok(Command::List {
+        kind,
+        year,
+    })
+}
+
+fn parse_show(args: &mut Arguments) -> Result<Command> {
+    let index = args.index()?;
+    Ok(Command::Show {
+        index,
+    })
+}
+
+fn parse_import_hours(args: &mut Arguments) -> Result<Command> {
+    let business = args.value("business");
+    let date = match args.value("date") {
+        Some(date) => number(&date, "date")?,
+        None => Date::today(),
+    };
+    if args.positional.is_empty() {
+        Err(wrong("missing time log"))?;
     }
+    let log = PathBuf::from(args.positional.remove(0));
+
+    Ok(Command::ImportHours {
+        log,
+        business,
+        date,
+    })
 }
 
-pub fn parse<I>(args: I) -> Result<InvoiceIndex, Box<dyn Error>>
+fn parse_recurring(args: &mut Arguments) -> Result<Command> {
+    let until = match args.value("until") {
+        Some(until) => number(&until, "date")?,
+        None => Date::today(),
+    };
+    Ok(Command::Recurring {
+        until,
+    })
+}
+
+fn parse_reminders(args: &mut Arguments, data_dir: &Path) -> Result<Command> {
+    let pdf = !args.flag("no-pdf");
+    let out_dir = args.value("out-dir").map_or_else(|| data_dir.to_owned(), PathBuf::from);
+    let date = match args.value("date") {
+        Some(date) => number(&date, "date")?,
+        None => Date::today(),
+    };
+    Ok(Command::Reminders {
+        date,
+        out_dir,
+        pdf,
+    })
+}
+
+fn parse_report(args: &mut Arguments) -> Result<Command> {
+    let dimensions = match args.value("by") {
+        Some(by) => by.split(',').map(str::parse).collect::<Result<_>>()?,
+        None => vec![Dimension::Year],
+    };
+    let format = match args.value("format") {
+        Some(format) => format.parse()?,
+        None => Format::Table,
+    };
+    Ok(Command::Report {
+        dimensions,
+        format,
+    })
+}
+
+/// The options that take a value, for any command.
+const VALUED_OPTIONS: &[&str] =
+    &["data-dir", "out-dir", "business", "date", "until", "by", "format"];
+
+pub fn parse<I>(args: I) -> Result<Invocation>
 where
     I: IntoIterator<Item = String>,
 {
-    let mut year = None;
-    let mut index = None;
+    let mut args = Arguments::new(args, VALUED_OPTIONS)?;
+    let data_dir = PathBuf::from(args.value("data-dir").unwrap_or_else(|| ".".to_string()));
 
-    for (i, arg) in args.into_iter().enumerate() {
-        match i {
-            0 => year = Some(arg.parse()?),
-            1 => index = Some(arg.parse()?),
-            _ => Err(WrongArguments("too many arguments"))?,
+    let command = match args.positional.first() {
+        Some(first) if first.parse::<u16>().is_ok() => "generate".to_string(),
+        Some(_) => args.positional.remove(0),
+        None => "help".to_string(),
+    };
+
+    let command = if args.help() {
+        Command::Help(Some(command))
+    } else {
+        match &*command {
+            "generate" => parse_generate(&mut args, &data_dir)?,
+            "list" => parse_list(&mut args)?,
+            "show" => parse_show(&mut args)?,
+            "validate" => Command::Validate,
+            "import-csv" => Command::ImportCsv,
+            "import-hours" => parse_import_hours(&mut args)?,
+            "recurring" => parse_recurring(&mut args)?,
+            "outstanding" => Command::Outstanding,
+            "reminders" => parse_reminders(&mut args, &data_dir)?,
+            "report" => parse_report(&mut args)?,
+            "help" | "--help" | "-h" => Command::Help(args.positional.pop()),
+            _ => return Err(wrong(&format!("unknown command `{}`", command))),
         }
-    }
+    };
+    args.finish()?;
 
-    Ok(InvoiceIndex {
-        year: year.ok_or(WrongArguments("missing year"))?,
-        index: index.ok_or(WrongArguments("missing index"))?,
+    Ok(Invocation {
+        data_dir,
+        command,
     })
 }
@@ -0,0 +1,338 @@
+use en16931::{Allowance, Document, Line, Party, TaxCategory};
+use error::Result;
+use invoice::{Date, Money};
+use xml::XmlWriter;
+use Invoice;
+
+/// The EN 16931 profile of Factur-X and ZUGFeRD.
+const GUIDELINE_ID: &str = "urn:cen.eu:en16931:2017";
+const RSM: &str = "urn:un:unece:uncefact:data:standard:CrossIndustryInvoice:100";
+const RAM: &str =
+    "urn:un:unece:uncefact:data:standard:ReusableAggregateBusinessInformationEntity:100";
+const QDT: &str = "urn:un:unece:uncefact:data:standard:QualifiedDataType:100";
+const UDT: &str = "urn:un:unece:uncefact:data:standard:UnqualifiedDataType:100";
+
+fn date(w: &mut XmlWriter, name: &str, date: Date) {
+    w.element(name, &[], |w| {
+        let text = format!("{:04}{:02}{:02}", date.year(), date.month(), date.day());
+        w.text("udt:DateTimeString", &[("format", "102")], &text);
+    });
+}
+
+fn amount(w: &mut XmlWriter, name: &str, money: Money) {
+    w.text(name, &[], &money.decimal());
+}
+
+fn write_party(w: &mut XmlWriter, name: &str, party: &Party) {
+    w.element(name, &[], |w| {
+        w.text("ram:Name", &[], party.name);
+        let details = party.details;
+        if !details.registration_number.is_empty() {
+            w.element("ram:SpecifiedLegalOrganization", &[], |w| {
+                w.text("ram:ID", &[], &details.registration_number)
+            });
+        }
+        if party.has_contact() {
+            w.element("ram:DefinedTradeContact", &[], |w| {
+                w.optional("ram:PersonName", &details.contact_name);
+                if !details.phone.is_empty() {
+                    w.element("ram:TelephoneUniversalCommunication", &[], |w| {
+                        w.text("ram:CompleteNumber", &[], &details.phone)
+                    });
+                }
+                if !details.email.is_empty() {
+                    w.element("ram:EmailURIUniversalCommunication", &[], |w| {
+                        w.text("ram:URIID", &[], &details.email)
+                    });
+                }
+            });
+        }
+        w.element("ram:PostalTradeAddress", &[], |w| {
+            w.optional("ram:PostcodeCode", &details.postal_code);
+            w.optional("ram:LineOne", party.street);
+            w.optional("ram:LineTwo", &details.address_line2);
+            w.optional("ram:CityName", party.city);
+            w.text("ram:CountryID", &[], party.country);
+            w.optional("ram:CountrySubDivisionName", &details.region);
+        });
+        if let Some((scheme, ref address)) = party.endpoint {
+            w.element("ram:URIUniversalCommunication", &[], |w| {
+                w.text("ram:URIID", &[("schemeID", scheme)], address)
+            });
+        }
+        if !party.vat.is_empty() {
+            w.element("ram:SpecifiedTaxRegistration", &[], |w| {
+                w.text("ram:ID", &[("schemeID", "VA")], &party.vat)
+            });
+        }
+    });
+}
+
+fn write_category(w: &mut XmlWriter, name: &str, category: &TaxCategory) {
+    w.element(name, &[], |w| {
+        w.text("ram:TypeCode", &[], "VAT");
+        w.text("ram:CategoryCode", &[], category.code);
+        w.text("ram:RateApplicablePercent", &[], &category.percent());
+    });
+}
+
+fn write_allowance(w: &mut XmlWriter, allowance: &Allowance) {
+    w.element("ram:SpecifiedTradeAllowanceCharge", &[], |w| {
+        w.element("ram:ChargeIndicator", &[], |w| w.text("udt:Indicator", &[], "false"));
+        amount(w, "ram:ActualAmount", allowance.amount);
+        w.text("ram:ReasonCode", &[], Allowance::DISCOUNT);
+        w.text("ram:Reason", &[], &allowance.reason);
+        if let Some(ref category) = allowance.category {
+            write_category(w, "ram:CategoryTradeTax", category);
+        }
+    });
+}
+
+fn write_line(w: &mut XmlWriter, line: &Line) {
+    w.element("ram:IncludedSupplyChainTradeLineItem", &[], |w| {
+        w.element("ram:AssociatedDocumentLineDocument", &[], |w| {
+            w.text("ram:LineID", &[], &line.id)
+        });
+        w.element("ram:SpecifiedTradeProduct", &[], |w| {
+            w.text("ram:Name", &[], &line.item.description)
+        });
+        w.element("ram:SpecifiedLineTradeAgreement", &[], |w| {
+            w.element("ram:NetPriceProductTradePrice", &[], |w| {
+                amount(w, "ram:ChargeAmount", line.price);
+                if let Some(ref base_quantity) = line.base_quantity {
+                    w.text("ram:BasisQuantity", &[("unitCode", line.unit)], base_quantity);
+                }
+            });
+        });
+        w.element("ram:SpecifiedLineTradeDelivery", &[], |w| {
+            w.text("ram:BilledQuantity", &[("unitCode", line.unit)], &line.quantity)
+        });
+        w.element("ram:SpecifiedLineTradeSettlement", &[], |w| {
+            write_category(w, "ram:ApplicableTradeTax", &line.category);
+            if let Some(ref allowance) = line.allowance {
+                write_allowance(w, allowance);
+            }
+            w.element("ram:SpecifiedTradeSettlementLineMonetarySummation", &[], |w| {
+                amount(w, "ram:LineTotalAmount", line.net_amount)
+            });
+        });
+    });
+}
+
+/// Writes `invoice` as a UN/CEFACT Cross Industry Invoice at the EN 16931
+/// profile of Factur-X and ZUGFeRD.
+pub fn generate_cii(invoice: &Invoice) -> Result<String> {
+    trace!("generate_cii");
+
+    let document = Document::new(invoice)?;
+
+    let mut w = XmlWriter::new();
+    let attributes =
+        [("xmlns:rsm", RSM), ("xmlns:ram", RAM), ("xmlns:qdt", QDT), ("xmlns:udt", UDT)];
+    w.element("rsm:CrossIndustryInvoice", &attributes, |w| {
+        w.element("rsm:ExchangedDocumentContext", &[], |w| {
+            w.element("ram:GuidelineSpecifiedDocumentContextParameter", &[], |w| {
+                w.text("ram:ID", &[], GUIDELINE_ID)
+            });
+        });
+        w.element("rsm:ExchangedDocument", &[], |w| {
+            w.text("ram:ID", &[], &document.number);
+            w.text("ram:TypeCode", &[], document.type_code);
+            date(w, "ram:IssueDateTime", document.issue_date());
+        });
+        w.element("rsm:SupplyChainTradeTransaction", &[], |w| {
+            for line in &document.lines {
+                write_line(w, line);
+            }
+            w.element("ram:ApplicableHeaderTradeAgreement", &[], |w| {
+                w.text("ram:BuyerReference", &[], &document.buyer_reference);
+                write_party(w, "ram:SellerTradeParty", &document.seller);
+                write_party(w, "ram:BuyerTradeParty", &document.buyer);
+            });
+            w.element("ram:ApplicableHeaderTradeDelivery", &[], |_| ());
+            w.element("ram:ApplicableHeaderTradeSettlement", &[], |w| {
+                if let Some(ref payment) = document.payment {
+                    w.text("ram:PaymentReference", &[], &payment.reference);
+                }
+                w.text("ram:InvoiceCurrencyCode", &[], document.currency());
+                if let Some(ref payment) = document.payment {
+                    w.element("ram:SpecifiedTradeSettlementPaymentMeans", &[], |w| {
+                        w.text("ram:TypeCode", &[], payment.means_code);
+                        w.element("ram:PayeePartyCreditorFinancialAccount", &[], |w| {
+                            w.text("ram:IBANID", &[], &payment.iban);
+                            w.text("ram:AccountName", &[], payment.account_name);
+                        });
+                        if !payment.bic.is_empty() {
+                            w.element("ram:PayeeSpecifiedCreditorFinancialInstitution", &[], |w| {
+                                w.text("ram:BICID", &[], payment.bic)
+                            });
+                        }
+                    });
+                }
+                for tax in &document.taxes {
+                    w.element("ram:ApplicableTradeTax", &[], |w| {
+                        amount(w, "ram:CalculatedAmount", tax.amount);
+                        w.text("ram:TypeCode", &[], "VAT");
+                        if let Some((_, reason)) = tax.category.exemption {
+                            w.text("ram:ExemptionReason", &[], reason);
+                        }
+                        amount(w, "ram:BasisAmount", tax.base);
+                        w.text("ram:CategoryCode", &[], tax.category.code);
+                        if let Some((code, _)) = tax.category.exemption {
+                            w.text("ram:ExemptionReasonCode", &[], code);
+                        }
+                        w.text("ram:RateApplicablePercent", &[], &tax.category.percent());
+                    });
+                }
+                for allowance in &document.allowances {
+                    write_allowance(w, allowance);
+                }
+                if let Some(ref payment) = document.payment {
+                    w.element("ram:SpecifiedTradePaymentTerms", &[], |w| {
+                        w.text("ram:Description", &[], &payment.terms);
+                        date(w, "ram:DueDateDateTime", payment.due_date);
+                    });
+                }
+                w.element("ram:SpecifiedTradeSettlementHeaderMonetarySummation", &[], |w| {
+                    amount(w, "ram:LineTotalAmount", document.line_total);
+                    if let Some(allowance_total) = document.allowance_total {
+                        amount(w, "ram:AllowanceTotalAmount", allowance_total);
+                    }
+                    amount(w, "ram:TaxBasisTotalAmount", document.tax_basis_total);
+                    let currency = [("currencyID", document.currency())];
+                    w.text("ram:TaxTotalAmount", &currency, &document.tax_total.decimal());
+                    amount(w, "ram:GrandTotalAmount", document.grand_total);
+                    amount(w, "ram:DuePayableAmount", document.grand_total);
+                });
+                if let Some(ref preceding) = document.preceding_invoice {
+                    w.element("ram:InvoiceReferencedDocument", &[], |w| {
+                        w.text("ram:IssuerAssignedID", &[], preceding)
+                    });
+                }
+            });
+        });
+    });
+    Ok(w.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use en16931::tests::invoice;
+    use invoice::{Currency, Discount, Kind};
+
+    /// The CII of `invoice`, without the indentation.
+    fn cii(invoice: &Invoice) -> Result<String> {
+        Ok(generate_cii(invoice)?.lines().map(str::trim).collect())
+    }
+
+    fn tax(amount: &str, base: &str, code: &str, percent: &str) -> String {
+        format!(
+            "<ram:ApplicableTradeTax><ram:CalculatedAmount>{}</ram:CalculatedAmount>\
+<ram:TypeCode>VAT</ram:TypeCode><ram:BasisAmount>{}</ram:BasisAmount>\
+<ram:CategoryCode>{}</ram:CategoryCode><ram:RateApplicablePercent>{}</ram:RateApplicablePercent>\
+</ram:ApplicableTradeTax>",
+            amount, base, code, percent
+        )
+    }
+
+    #[test]
+    fn invoice_with_two_rates() {
+        let mut invoice = invoice(&[(10000, "21"), (5000, "6")], None);
+        invoice.items[0].quantity = Some("2".parse().unwrap());
+        invoice.items[0].unit = Some("hours".to_string());
+        invoice.items[0].unit_price = Some(Money::new(5000, Currency::Eur));
+        let cii = cii(&invoice).unwrap();
+        let expected = [
+            "<ram:GuidelineSpecifiedDocumentContextParameter>\
+<ram:ID>urn:cen.eu:en16931:2017</ram:ID>",
+            "<rsm:ExchangedDocument><ram:ID>2018-01</ram:ID><ram:TypeCode>380</ram:TypeCode>\
+<ram:IssueDateTime><udt:DateTimeString format=\"102\">20180131</udt:DateTimeString>",
+            "<ram:LineID>1</ram:LineID></ram:AssociatedDocumentLineDocument>\
+<ram:SpecifiedTradeProduct><ram:Name>Work</ram:Name></ram:SpecifiedTradeProduct>\
+<ram:SpecifiedLineTradeAgreement><ram:NetPriceProductTradePrice>\
+<ram:ChargeAmount>50.00</ram:ChargeAmount></ram:NetPriceProductTradePrice>\
+</ram:SpecifiedLineTradeAgreement><ram:SpecifiedLineTradeDelivery>\
+<ram:BilledQuantity unitCode=\"HUR\">2</ram:BilledQuantity>",
+            "<ram:CategoryCode>S</ram:CategoryCode><ram:RateApplicablePercent>21\
+</ram:RateApplicablePercent></ram:ApplicableTradeTax>\
+<ram:SpecifiedTradeSettlementLineMonetarySummation><ram:LineTotalAmount>100.00\
+</ram:LineTotalAmount>",
+            "<ram:SellerTradeParty><ram:Name>Me</ram:Name>",
+            "<ram:URIID schemeID=\"9925\">BE0987654321</ram:URIID>\
+</ram:URIUniversalCommunication><ram:SpecifiedTaxRegistration>\
+<ram:ID schemeID=\"VA\">BE0987654321</ram:ID>",
+            "<ram:BuyerTradeParty><ram:Name>Acme</ram:Name>",
+            "<ram:ID schemeID=\"VA\">BE0123456749</ram:ID>",
+            "<ram:PaymentReference>2018-01</ram:PaymentReference>\
+<ram:InvoiceCurrencyCode>EUR</ram:InvoiceCurrencyCode>\
+<ram:SpecifiedTradeSettlementPaymentMeans><ram:TypeCode>30</ram:TypeCode>\
+<ram:PayeePartyCreditorFinancialAccount><ram:IBANID>BE68539007547034</ram:IBANID>",
+            &(tax("3.00", "50.00", "S", "6") + &tax("21.00", "100.00", "S", "21")),
+            "<ram:SpecifiedTradePaymentTerms><ram:Description>net 30 days</ram:Description>\
+<ram:DueDateDateTime><udt:DateTimeString format=\"102\">20180302</udt:DateTimeString>",
+            "<ram:SpecifiedTradeSettlementHeaderMonetarySummation>\
+<ram:LineTotalAmount>150.00</ram:LineTotalAmount>\
+<ram:TaxBasisTotalAmount>150.00</ram:TaxBasisTotalAmount>\
+<ram:TaxTotalAmount currencyID=\"EUR\">24.00</ram:TaxTotalAmount>\
+<ram:GrandTotalAmount>174.00</ram:GrandTotalAmount>\
+<ram:DuePayableAmount>174.00</ram:DuePayableAmount>",
+        ];
+        for expected in &expected {
+            assert!(cii.contains(expected), "{} not in {}", expected, cii);
+        }
+        assert_eq!(cii.matches("<ram:IncludedSupplyChainTradeLineItem>").count(), 2);
+    }
+
+    #[test]
+    fn credit_note_with_discount() {
+        let discount = Discount::Fixed(Money::new(1000, Currency::Eur));
+        let mut credit_note = invoice(&[(10000, "21")], Some(discount));
+        credit_note.index.kind = Kind::CreditNote;
+        credit_note.metadata.credits = Some("2018-01".parse().unwrap());
+        let cii = cii(&credit_note).unwrap();
+        let expected = [
+            "<ram:ID>2018-CN01</ram:ID><ram:TypeCode>381</ram:TypeCode>",
+            &tax("18.90", "90.00", "S", "21"),
+            "<ram:SpecifiedTradeAllowanceCharge><ram:ChargeIndicator>\
+<udt:Indicator>false</udt:Indicator></ram:ChargeIndicator>\
+<ram:ActualAmount>10.00</ram:ActualAmount><ram:ReasonCode>95</ram:ReasonCode>\
+<ram:Reason>Discount</ram:Reason><ram:CategoryTradeTax><ram:TypeCode>VAT</ram:TypeCode>\
+<ram:CategoryCode>S</ram:CategoryCode>",
+            "<ram:LineTotalAmount>100.00</ram:LineTotalAmount>\
+<ram:AllowanceTotalAmount>10.00</ram:AllowanceTotalAmount>\
+<ram:TaxBasisTotalAmount>90.00</ram:TaxBasisTotalAmount>",
+            "<ram:DuePayableAmount>108.90</ram:DuePayableAmount>\
+</ram:SpecifiedTradeSettlementHeaderMonetarySummation><ram:InvoiceReferencedDocument>\
+<ram:IssuerAssignedID>2018-01</ram:IssuerAssignedID></ram:InvoiceReferencedDocument>",
+        ];
+        for expected in &expected {
+            assert!(cii.contains(expected), "{} not in {}", expected, cii);
+        }
+        assert!(!cii.contains("<ram:SpecifiedTradeSettlementPaymentMeans>"));
+        assert!(!cii.contains("<ram:DueDateDateTime>"));
+    }
+
+    #[test]
+    fn exemptions() {
+        let mut reverse = invoice(&[(10000, "0")], None);
+        reverse.metadata.client.vatpolicy = "Reverse charge".to_string();
+        let reverse = cii(&reverse).unwrap();
+        let expected = "<ram:CalculatedAmount>0.00</ram:CalculatedAmount>\
+<ram:TypeCode>VAT</ram:TypeCode><ram:ExemptionReason>Reverse charge</ram:ExemptionReason>\
+<ram:BasisAmount>100.00</ram:BasisAmount><ram:CategoryCode>AE</ram:CategoryCode>\
+<ram:ExemptionReasonCode>VATEX-EU-AE</ram:ExemptionReasonCode>";
+        assert!(reverse.contains(expected), "{}", reverse);
+
+        let mut unregistered = invoice(&[(10000, "0")], None);
+        unregistered.metadata.business.vat.clear();
+        unregistered.metadata.business.details.email = "me@example.com".to_string();
+        let unregistered = cii(&unregistered).unwrap();
+        let expected = "<ram:ExemptionReason>Not subject to VAT</ram:ExemptionReason>\
+<ram:BasisAmount>100.00</ram:BasisAmount><ram:CategoryCode>O</ram:CategoryCode>\
+<ram:ExemptionReasonCode>VATEX-EU-O</ram:ExemptionReasonCode>";
+        assert!(unregistered.contains(expected), "{}", unregistered);
+        // Neither party's VAT number is given (BR-O-02).
+        assert!(!unregistered.contains("<ram:SpecifiedTaxRegistration>"), "{}", unregistered);
+    }
+}
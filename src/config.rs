@@ -0,0 +1,220 @@
+use error::{Error, Result};
+use invoice::{Amount, Currency, PaymentTerms, VatRate};
+use serde::de::{self, Deserialize, Deserializer};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Settings that can be overridden per business and per client. Fields that
+/// are `None` fall back to the next, less specific level.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Overrides {
+    /// Short for `payment_terms` of net that many days.
+    pub payment_days: Option<i64>,
+    pub payment_terms: Option<PaymentTerms>,
+    pub ventilation_code: Option<String>,
+    pub currency: Option<Currency>,
+    pub vat_rate: Option<VatRate>,
+    pub hourly_rate: Option<Amount>,
+    pub ubl: Option<bool>,
+    pub factur_x: Option<bool>,
+}
+
+impl Overrides {
+    /// The payment terms given at this level, either way.
+    fn terms(&self) -> Option<PaymentTerms> {
+        self.payment_terms.or(self.payment_days.map(PaymentTerms::Net))
+    }
+
+    fn or(&self, fallback: &Overrides) -> Overrides {
+        Overrides {
+            // Folded into `payment_terms`, so that `payment-days` for a
+            // client wins over `payment-terms` for its business.
+            payment_days: None,
+            payment_terms: self.terms().or(fallback.terms()),
+            ventilation_code: self
+                .ventilation_code
+                .clone()
+                .or_else(|| fallback.ventilation_code.clone()),
+            currency: self.currency.or(fallback.currency),
+            vat_rate: self.vat_rate.or(fallback.vat_rate),
+            hourly_rate: self.hourly_rate.clone().or_else(|| fallback.hourly_rate.clone()),
+            ubl: self.ubl.or(fallback.ubl),
+            factur_x: self.factur_x.or(fallback.factur_x),
+        }
+    }
+}
+
+/// The settings that apply to a single invoice.
+#[derive(Clone, Debug)]
+pub struct Policy {
+    pub payment_terms: PaymentTerms,
+    pub ventilation_code: String,
+    pub currency: Currency,
+    /// The VAT rate for line items that do not specify one.
+    pub vat_rate: VatRate,
+    /// The price of an hour when importing hours from a time log.
+    pub hourly_rate: Option<Amount>,
+    /// Whether to write a Peppol UBL e-invoice next to the PDF.
+    pub ubl: bool,
+    /// Whether to embed a Factur-X CII e-invoice in the PDF, as PDF/A-3.
+    pub factur_x: bool,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            payment_terms: PaymentTerms::Net(30),
+            ventilation_code: "55".to_string(),
+            currency: Currency::default(),
+            vat_rate: VatRate::default(),
+            hourly_rate: None,
+            ubl: false,
+            factur_x: false,
+        }
+    }
+}
+
+/// The contents of `invoices.toml` at the root of the data directory.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Config {
+    /// The Prince binary used to create PDFs.
+    #[serde(default = "default_prince")]
+    pub prince: String,
+    /// The decimal separator of amounts in `data.csv`, `.` or `,`.
+    #[serde(default = "default_decimal_separator", deserialize_with = "decimal_separator")]
+    pub decimal_separator: char,
+    /// The number of days overdue from which the first, second and final
+    /// reminders are sent.
+    #[serde(default = "default_reminder_days", deserialize_with = "reminder_days")]
+    pub reminder_days: [i64; 3],
+    #[serde(flatten)]
+    pub defaults: Overrides,
+    #[serde(default)]
+    pub businesses: HashMap<String, Overrides>,
+    #[serde(default)]
+    pub clients: HashMap<String, Overrides>,
+    /// Any other top-level keys, which `Config::read` rejects: serde cannot
+    /// deny unknown fields next to a flattened struct.
+    #[serde(flatten)]
+    unknown: BTreeMap<String, toml::Value>,
+}
+
+fn default_prince() -> String {
+    "prince".to_string()
+}
+
+fn default_decimal_separator() -> char {
+    '.'
+}
+
+fn default_reminder_days() -> [i64; 3] {
+    [7, 21, 35]
+}
+
+fn reminder_days<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> ::std::result::Result<[i64; 3], D::Error> {
+    let days = <[i64; 3]>::deserialize(deserializer)?;
+    if days[0] < 1 || days[0] >= days[1] || days[1] >= days[2] {
+        return Err(de::Error::custom(format!(
+            "invalid reminder days {:?}, expected three increasing numbers of days",
+            days
+        )));
+    }
+    Ok(days)
+}
+
+fn decimal_separator<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> ::std::result::Result<char, D::Error> {
+    match char::deserialize(deserializer)? {
+        separator @ '.' | separator @ ',' => Ok(separator),
+        separator => Err(de::Error::custom(format!(
+            "invalid decimal separator `{}`, expected `.` or `,`",
+            separator
+        ))),
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            prince: default_prince(),
+            decimal_separator: default_decimal_separator(),
+            reminder_days: default_reminder_days(),
+            defaults: Overrides::default(),
+            businesses: HashMap::new(),
+            clients: HashMap::new(),
+            unknown: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Reads `invoices.toml` from `path`, or returns the defaults if there is
+    /// no such file.
+    pub fn read(path: &Path) -> Result<Self> {
+        trace!("Config::read");
+
+        let path = path.join("invoices.toml");
+        let mut content = String::new();
+        match File::open(&path).and_then(|mut file| file.read_to_string(&mut content)) {
+            Ok(_) => (),
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(error) => return Err(Error::io(path, error)),
+        }
+        Self::parse(&content).map_err(|error| Error::Config {
+            path,
+            error,
+        })
+    }
+
+    fn parse(content: &str) -> ::std::result::Result<Self, toml::de::Error> {
+        let config: Self = toml::from_str(content)?;
+        if let Some(key) = config.unknown.keys().next() {
+            return Err(de::Error::custom(format!("unknown field `{}`", key)));
+        }
+        Ok(config)
+    }
+
+    /// The policy for invoices from `business` to `client`: client settings
+    /// take precedence over business settings, which take precedence over
+    /// the top-level defaults.
+    pub fn policy(&self, business: &str, client: &str) -> Policy {
+        let none = Overrides::default();
+        let client = self.clients.get(client).unwrap_or(&none);
+        let business = self.businesses.get(business).unwrap_or(&none);
+        let overrides = client.or(business).or(&self.defaults);
+
+        let default = Policy::default();
+        Policy {
+            payment_terms: overrides.terms().unwrap_or(default.payment_terms),
+            ventilation_code: overrides.ventilation_code.unwrap_or(default.ventilation_code),
+            currency: overrides.currency.unwrap_or(default.currency),
+            vat_rate: overrides.vat_rate.unwrap_or(default.vat_rate),
+            hourly_rate: overrides.hourly_rate,
+            ubl: overrides.ubl.unwrap_or(default.ubl),
+            factur_x: overrides.factur_x.unwrap_or(default.factur_x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_settings() {
+        let config = Config::parse("vat-rate = 6\n[clients.acme]\nubl = true\n").unwrap();
+        assert_eq!(config.policy("", "acme").vat_rate, "6".parse().unwrap());
+        assert!(config.policy("", "acme").ubl);
+        for content in &["vat-rat = 6\n", "[clients.acme]\nubll = true\n"] {
+            let error = Config::parse(content).unwrap_err().to_string();
+            assert!(error.contains("unknown field"), "{}", error);
+        }
+    }
+}
@@ -1,307 +1,886 @@
-use csv_parser::{self, Reader};
-use invoice::{Business, Client, Date, Invoice, InvoiceData, InvoiceIndex, LineItem, Money};
-use std::collections::HashMap;
-use std::error::Error;
-use std::fmt;
-use std::fs::File;
+use config::Config;
+use csv_parser::{self, Reader, Writer};
+use error::{Error, Result};
+use invoice::{
+    Amount, Business, Client, ContactDetails, Currency, Date, Discount, InvoiceIndex, Kind,
+    Quantity, VatRate,
+};
+use recurring::{Frequency, Schedule};
+use source::{DataSource, InvoiceRecord, LineItemRecord, PaymentRecord};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
 use std::io::Read;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+/// An error while reading a CSV file, before it is attributed to the file.
+enum ReadError {
+    Csv(csv_parser::Error),
+    /// A record that parsed but does not make sense.
+    Invalid {
+        line: Option<u64>,
+        message: String,
+    },
+}
+
+impl From<csv_parser::Error> for ReadError {
+    fn from(error: csv_parser::Error) -> Self {
+        ReadError::Csv(error)
+    }
+}
+
+/// Reads the CSV file at `path` with `read`, attributing any error to the
+/// file.
+fn read_file<T, F, E>(path: PathBuf, read: F) -> Result<T>
+where
+    F: FnOnce(File) -> ::std::result::Result<T, E>,
+    E: Into<ReadError>,
+{
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(error) => return Err(Error::io(path, error)),
+    };
+    read(file).map_err(|error| match error.into() {
+        ReadError::Csv(error) => Error::csv(path, error),
+        ReadError::Invalid {
+            line,
+            message,
+        } => Error::InvalidRecord {
+            path,
+            line,
+            message,
+        },
+    })
+}
 
-#[derive(Debug)]
-struct MissingData(&'static str);
+/// The columns of a line item, shared by `data.csv` and `recurring.csv`.
+#[derive(Debug, Deserialize)]
+struct SerializedLineItem {
+    /// The amount as written by hand, such as `1234.56`.
+    #[serde(default)]
+    price: Option<String>,
+    /// The amount in cents, as older files give it.
+    #[serde(default)]
+    amount: Option<String>,
+    #[serde(default)]
+    quantity: Option<Quantity>,
+    #[serde(default)]
+    unit: Option<String>,
+    #[serde(default)]
+    unit_price: Option<String>,
+    #[serde(default)]
+    vat_rate: Option<VatRate>,
+    #[serde(default)]
+    discount: Option<String>,
+    services: String,
+    attachment: String,
+}
 
-impl fmt::Display for MissingData {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        self.0.fmt(f)
+impl SerializedLineItem {
+    fn parse(
+        self,
+        path: &Path,
+        line: Option<u64>,
+        separator: char,
+    ) -> ::std::result::Result<LineItemRecord, String> {
+        let SerializedLineItem {
+            price,
+            amount,
+            quantity,
+            unit,
+            unit_price,
+            vat_rate,
+            discount,
+            services,
+            attachment,
+        } = self;
+
+        let attachment = if attachment.is_empty() {
+            None
+        } else {
+            Some(attachment)
+        };
+
+        let unit = unit.filter(|unit| !unit.is_empty());
+        if price.is_none() && amount.is_none() && (quantity.is_none() || unit_price.is_none()) {
+            return Err("missing price, or quantity and unit_price".to_string());
+        }
+        let parse = |amount: Option<String>| match amount {
+            Some(amount) => {
+                Amount::parse(&amount, separator).map(Some).map_err(|error| error.to_string())
+            },
+            None => Ok(None),
+        };
+        // Amounts in cents are still read, but never mistaken for prices.
+        let amount = match (parse(price)?, amount) {
+            (price, None) => price,
+            (None, Some(cents)) => match cents.trim().parse() {
+                Ok(cents) => Some(Amount::cents(cents)),
+                Err(_) => {
+                    return Err(format!(
+                        "invalid amount `{}`: `amount` is in cents, prices go in `price`",
+                        cents
+                    ))
+                },
+            },
+            (Some(_), Some(_)) => return Err("both price and amount in cents".to_string()),
+        };
+        let discount = match discount {
+            Some(discount) => {
+                Some(Discount::parse(&discount, separator).map_err(|error| error.to_string())?)
+            },
+            None => None,
+        };
+
+        Ok(LineItemRecord {
+            path: path.to_owned(),
+            line,
+            amount,
+            quantity,
+            unit,
+            unit_price: parse(unit_price)?,
+            vat_rate,
+            discount,
+            description: services,
+            attachment,
+        })
     }
 }
 
-impl Error for MissingData {
-    fn description(&self) -> &str {
-        self.0
+fn read_costs<R: Read>(
+    reader: R,
+    path: &Path,
+    separator: char,
+) -> ::std::result::Result<HashMap<u8, Vec<LineItemRecord>>, ReadError> {
+    #[derive(Debug, Deserialize)]
+    struct SerializedIndex {
+        index: u8,
     }
+
+    trace!("read_monthly_costs");
+
+    let mut reader = Reader::from_reader(reader);
+    let headers = reader.headers()?.clone();
+
+    let mut items = HashMap::new();
+    for record in reader.records() {
+        let record = record?;
+        let index = record.deserialize::<SerializedIndex>(Some(&headers))?.index;
+        let item = record.deserialize::<SerializedLineItem>(Some(&headers))?;
+        let line = record.position().map(|position| position.line());
+        let item = item.parse(path, line, separator).map_err(|message| ReadError::Invalid {
+            line,
+            message,
+        })?;
+        items.entry(index).or_insert_with(Vec::new).push(item);
+    }
+
+    Ok(items)
 }
 
-#[derive(Debug, Deserialize)]
-struct SerializedInvoiceData {
-    business: String,
-    client: String,
-    year: u16,
-    month: u8,
-    day: u8,
+fn read_invoice_data<R: Read>(
+    reader: R,
+    separator: char,
+) -> ::std::result::Result<HashMap<u8, InvoiceRecord>, ReadError> {
+    #[derive(Debug, Deserialize)]
+    struct FullSerializedInvoiceData {
+        index: u8,
+        business: String,
+        client: String,
+        year: u16,
+        month: u8,
+        day: u8,
+        #[serde(default)]
+        currency: Option<Currency>,
+        #[serde(default)]
+        credits: Option<InvoiceIndex>,
+        #[serde(default)]
+        discount: Option<String>,
+    }
+
+    trace!("read_invoice_data");
+
+    let mut reader = Reader::from_reader(reader);
+    let headers = reader.headers()?.clone();
+
+    let mut items = HashMap::new();
+    for record in reader.records() {
+        let record = record?;
+        let FullSerializedInvoiceData {
+            index,
+            business,
+            client,
+            year,
+            month,
+            day,
+            currency,
+            credits,
+            discount,
+        } = record.deserialize(Some(&headers))?;
+        let discount = match discount {
+            Some(discount) => {
+                Some(Discount::parse(&discount, separator).map_err(|error| ReadError::Invalid {
+                    line: record.position().map(|position| position.line()),
+                    message: error.to_string(),
+                })?)
+            },
+            None => None,
+        };
+
+        let item = InvoiceRecord {
+            business,
+            client,
+            year,
+            month,
+            day,
+            currency,
+            credits,
+            discount,
+        };
+        items.insert(index, item);
+    }
+
+    Ok(items)
 }
 
-struct Invoices {
-    // XXX Don't use HashMap
-    items: HashMap<u8, Vec<LineItem>>,
-    metadata: HashMap<u8, SerializedInvoiceData>,
+fn read_payments<R: Read>(
+    reader: R,
+    path: &Path,
+    separator: char,
+) -> ::std::result::Result<HashMap<u8, Vec<PaymentRecord>>, ReadError> {
+    #[derive(Debug, Deserialize)]
+    struct SerializedPayment {
+        index: u8,
+        date: String,
+        amount: String,
+        #[serde(default)]
+        method: String,
+    }
+
+    trace!("read_payments");
+
+    let mut reader = Reader::from_reader(reader);
+    let headers = reader.headers()?.clone();
+
+    let mut payments = HashMap::new();
+    for record in reader.records() {
+        let record = record?;
+        let line = record.position().map(|position| position.line());
+        let invalid = |message| ReadError::Invalid {
+            line,
+            message,
+        };
+        let SerializedPayment {
+            index,
+            date,
+            amount,
+            method,
+        } = record.deserialize(Some(&headers))?;
+        let date = date.parse().map_err(|_| invalid(format!("invalid date `{}`", date)))?;
+        let amount =
+            Amount::parse(&amount, separator).map_err(|error| invalid(error.to_string()))?;
+        let payment = PaymentRecord {
+            path: path.to_owned(),
+            line,
+            date,
+            amount,
+            method,
+        };
+        payments.entry(index).or_insert_with(Vec::new).push(payment);
+    }
+
+    Ok(payments)
 }
 
-impl Invoices {
-    fn read_costs<R: Read>(reader: R) -> Result<HashMap<u8, Vec<LineItem>>, Box<dyn Error>> {
-        #[derive(Debug, Deserialize)]
-        struct SerializedLineItem {
-            index: u8,
-            amount: i32,
-            services: String,
-            attachment: String,
-        }
+fn read_clients<R: Read>(reader: R) -> csv_parser::Result<HashMap<String, Client>> {
+    #[derive(Debug, Deserialize)]
+    struct SerializedClient {
+        id: String,
+        name: String,
+        street: String,
+        city: String,
+        country: String,
+        vat: String,
+        vatpolicy: String,
+        partyid: u32,
+        #[serde(default)]
+        currency: Option<Currency>,
+        #[serde(default)]
+        postal_code: String,
+        #[serde(default)]
+        address_line2: String,
+        #[serde(default)]
+        region: String,
+        #[serde(default)]
+        contact_name: String,
+        #[serde(default)]
+        email: String,
+        #[serde(default)]
+        phone: String,
+        #[serde(default)]
+        registration_number: String,
+    }
 
-        impl Into<(u8, LineItem)> for SerializedLineItem {
-            fn into(self) -> (u8, LineItem) {
-                let Self {
-                    index,
-                    amount,
-                    services,
-                    attachment,
-                } = self;
-
-                let attachment = if attachment.is_empty() {
-                    None
-                } else {
-                    Some(attachment)
-                };
-
-                let item = LineItem {
-                    amount: Money(amount),
-                    description: services,
-                    attachment,
-                };
-
-                (index, item)
-            }
+    impl From<SerializedClient> for (String, Client) {
+        fn from(client: SerializedClient) -> Self {
+            let SerializedClient {
+                id,
+                name,
+                street,
+                city,
+                country,
+                vat,
+                vatpolicy,
+                partyid,
+                currency,
+                postal_code,
+                address_line2,
+                region,
+                contact_name,
+                email,
+                phone,
+                registration_number,
+            } = client;
+            let client = Client {
+                id: id.clone(),
+                name,
+                street,
+                city,
+                country,
+                vat,
+                vatpolicy,
+                partyid,
+                currency,
+                details: ContactDetails {
+                    postal_code,
+                    address_line2,
+                    region,
+                    contact_name,
+                    email,
+                    phone,
+                    registration_number,
+                },
+            };
+            (id, client)
         }
+    }
 
-        trace!("read_monthly_costs");
+    trace!("read_clients");
 
-        let mut reader = Reader::from_reader(reader);
+    let mut reader = Reader::from_reader(reader);
+    reader
+        .deserialize::<SerializedClient>()
+        .map(|result| result.map(SerializedClient::into))
+        .collect()
+}
+
+fn read_businesses<R: Read>(reader: R) -> csv_parser::Result<HashMap<String, Business>> {
+    #[derive(Debug, Deserialize)]
+    struct SerializedBusiness {
+        id: String,
+        name: String,
+        street: String,
+        city: String,
+        country: String,
+        vat: String,
+        bank: String,
+        iban: String,
+        bic: String,
+        #[serde(default)]
+        postal_code: String,
+        #[serde(default)]
+        address_line2: String,
+        #[serde(default)]
+        region: String,
+        #[serde(default)]
+        contact_name: String,
+        #[serde(default)]
+        email: String,
+        #[serde(default)]
+        phone: String,
+        #[serde(default)]
+        registration_number: String,
+    }
 
-        let mut items = HashMap::new();
-        for item in reader.deserialize::<SerializedLineItem>() {
-            let item = item?;
-            let (index, item) = item.into();
-            items.entry(index).or_insert_with(Vec::new).push(item);
+    impl From<SerializedBusiness> for (String, Business) {
+        fn from(business: SerializedBusiness) -> Self {
+            let SerializedBusiness {
+                id,
+                name,
+                street,
+                city,
+                country,
+                vat,
+                bank,
+                iban,
+                bic,
+                postal_code,
+                address_line2,
+                region,
+                contact_name,
+                email,
+                phone,
+                registration_number,
+            } = business;
+            let business = Business {
+                name,
+                street,
+                city,
+                country,
+                vat,
+                bank,
+                iban,
+                bic,
+                details: ContactDetails {
+                    postal_code,
+                    address_line2,
+                    region,
+                    contact_name,
+                    email,
+                    phone,
+                    registration_number,
+                },
+            };
+            (id, business)
         }
+    }
+
+    trace!("read_businesses");
 
-        Ok(items)
+    let mut reader = Reader::from_reader(reader);
+    reader
+        .deserialize::<SerializedBusiness>()
+        .map(|result| result.map(SerializedBusiness::into))
+        .collect()
+}
+
+/// Reads the schedules in `recurring.csv`: one line per line item, with the
+/// columns of `data.csv` next to `id`, `business`, `client`, `frequency`,
+/// `start` and the optional `end` and `currency`, which must be the same on
+/// every line of a schedule.
+pub fn read_schedules(path: &Path, separator: char) -> Result<Vec<Schedule>> {
+    #[derive(Clone, Debug, PartialEq, Deserialize)]
+    struct SerializedSchedule {
+        id: String,
+        business: String,
+        client: String,
+        #[serde(default)]
+        currency: Option<Currency>,
+        frequency: Frequency,
+        start: String,
+        #[serde(default)]
+        end: Option<String>,
     }
 
-    fn read_invoice_data<R: Read>(
-        reader: R,
-    ) -> Result<HashMap<u8, SerializedInvoiceData>, Box<dyn Error>> {
-        #[derive(Debug, Deserialize)]
-        struct FullSerializedInvoiceData {
-            index: u8,
-            business: String,
-            client: String,
-            year: u16,
-            month: u8,
-            day: u8,
-        }
+    trace!("read_schedules");
 
-        impl Into<(u8, SerializedInvoiceData)> for FullSerializedInvoiceData {
-            fn into(self) -> (u8, SerializedInvoiceData) {
-                let Self {
-                    index,
-                    business,
-                    client,
-                    year,
-                    month,
-                    day,
-                } = self;
-
-                let item = SerializedInvoiceData {
-                    business,
-                    client,
-                    year,
-                    month,
-                    day,
-                };
-
-                (index, item)
+    read_file(path.to_owned(), |reader| -> ::std::result::Result<_, ReadError> {
+        let mut reader = Reader::from_reader(reader);
+        let headers = reader.headers()?.clone();
+
+        let mut schedules: Vec<(SerializedSchedule, Schedule)> = vec![];
+        for record in reader.records() {
+            let record = record?;
+            let line = record.position().map(|position| position.line());
+            let invalid = |message| ReadError::Invalid {
+                line,
+                message,
+            };
+
+            let schedule = record.deserialize::<SerializedSchedule>(Some(&headers))?;
+            let item = record.deserialize::<SerializedLineItem>(Some(&headers))?;
+            let item = item.parse(path, line, separator).map_err(invalid)?;
+            if let Some((first, existing)) =
+                schedules.iter_mut().find(|(first, _)| first.id == schedule.id)
+            {
+                if *first != schedule {
+                    let message = format!("schedule `{}` differs from its first line", schedule.id);
+                    return Err(invalid(message));
+                }
+                existing.items.push(item);
+                continue;
             }
+
+            let date = |text: &str| {
+                text.parse::<Date>().map_err(|_| invalid(format!("invalid date `{}`", text)))
+            };
+            let start = date(&schedule.start)?;
+            let end = match schedule.end {
+                Some(ref end) => Some(date(end)?),
+                None => None,
+            };
+            let parsed = Schedule {
+                id: schedule.id.clone(),
+                business: schedule.business.clone(),
+                client: schedule.client.clone(),
+                currency: schedule.currency,
+                frequency: schedule.frequency,
+                start,
+                end,
+                items: vec![item],
+            };
+            schedules.push((schedule, parsed));
         }
 
-        trace!("read_invoice_data");
+        Ok(schedules.into_iter().map(|(_, schedule)| schedule).collect())
+    })
+}
 
-        let mut reader = Reader::from_reader(reader);
+/// Reads `recurring-issued.csv`, the schedules and dates that have been
+/// issued; empty if there is no such file yet.
+pub fn read_issued(path: &Path) -> Result<HashSet<(String, Date)>> {
+    #[derive(Debug, Deserialize)]
+    struct SerializedIssued {
+        schedule: String,
+        date: String,
+    }
 
-        let items = reader
-            .deserialize::<FullSerializedInvoiceData>()
-            .map(|data| Ok(data?.into()))
-            .collect::<Result<_, Box<dyn Error>>>()?;
+    trace!("read_issued");
 
-        Ok(items)
+    if !path.exists() {
+        return Ok(HashSet::new());
     }
+    read_file(path.to_owned(), |reader| -> ::std::result::Result<_, ReadError> {
+        let mut reader = Reader::from_reader(reader);
+        let headers = reader.headers()?.clone();
+
+        let mut issued = HashSet::new();
+        for record in reader.records() {
+            let record = record?;
+            let line = record.position().map(|position| position.line());
+            let SerializedIssued {
+                schedule,
+                date,
+            } = record.deserialize(Some(&headers))?;
+            let date = date.parse().map_err(|_| ReadError::Invalid {
+                line,
+                message: format!("invalid date `{}`", date),
+            })?;
+            issued.insert((schedule, date));
+        }
+        Ok(issued)
+    })
+}
 
-    fn read(path: &Path, year: u16) -> Result<Self, Box<dyn Error>> {
-        let folder = path.join(year.to_string());
+/// Reads `reminders-sent.csv`, the invoices and levels of the reminders that
+/// have been sent; empty if there is no such file yet.
+pub fn read_sent_reminders(path: &Path) -> Result<HashSet<(InvoiceIndex, String)>> {
+    #[derive(Debug, Deserialize)]
+    struct SerializedSent {
+        invoice: InvoiceIndex,
+        level: String,
+    }
 
-        let filename = folder.join("data.csv");
-        let items = Self::read_costs(File::open(filename)?)?;
+    trace!("read_sent_reminders");
 
-        let filename = folder.join("invoices.csv");
-        let metadata = Self::read_invoice_data(File::open(filename)?)?;
+    if !path.exists() {
+        return Ok(HashSet::new());
+    }
+    read_file(path.to_owned(), |reader| -> ::std::result::Result<_, ReadError> {
+        let mut reader = Reader::from_reader(reader);
+        let headers = reader.headers()?.clone();
+
+        let mut sent = HashSet::new();
+        for record in reader.records() {
+            let SerializedSent {
+                invoice,
+                level,
+            } = record?.deserialize(Some(&headers))?;
+            sent.insert((invoice, level));
+        }
+        Ok(sent)
+    })
+}
 
-        Ok(Self {
-            items,
-            metadata,
-        })
+/// Adds `rows`, given as pairs of column and value, to the CSV file at
+/// `path`, creating it if needed and adding any columns it lacks. The file is
+/// replaced only once everything is written.
+pub fn append_rows(path: &Path, rows: &[Vec<(&str, String)>]) -> Result<()> {
+    let (mut headers, records) = if path.exists() {
+        let mut reader = Reader::from_path(path).map_err(|error| Error::csv(path, error))?;
+        let headers = reader.headers().map_err(|error| Error::csv(path, error))?;
+        let headers = headers.iter().map(String::from).collect::<Vec<_>>();
+        let records = reader
+            .records()
+            .collect::<csv_parser::Result<Vec<_>>>()
+            .map_err(|error| Error::csv(path, error))?;
+        (headers, records)
+    } else {
+        (vec![], vec![])
+    };
+    for &(column, _) in rows.iter().flatten() {
+        if !headers.iter().any(|header| header == column) {
+            headers.push(column.to_string());
+        }
     }
 
-    fn get(&self, index: u8) -> (&[LineItem], &SerializedInvoiceData) {
-        let items = self.items.get(&index).expect("Missing entry in data.csv");
-        let metadata = self.metadata.get(&index).expect("Missing entry in invoices.csv");
-        (items, metadata)
+    let temporary = path.with_extension("csv.tmp");
+    let write = || -> csv_parser::Result<()> {
+        let mut writer = Writer::from_path(&temporary)?;
+        writer.write_record(&headers)?;
+        for record in &records {
+            let missing = headers.len().saturating_sub(record.len());
+            writer.write_record(record.iter().chain(vec![""; missing]))?;
+        }
+        for row in rows {
+            writer.write_record(headers.iter().map(|header| {
+                row.iter().find(|&&(column, _)| column == header).map_or("", |(_, value)| value)
+            }))?;
+        }
+        writer.flush()?;
+        Ok(())
+    };
+    if let Err(error) = write() {
+        let _ = fs::remove_file(&temporary);
+        return Err(Error::csv(path, error));
     }
+    fs::rename(&temporary, path).map_err(|error| Error::io(path, error))
 }
 
-struct Clients {
-    clients: HashMap<String, Client>,
+/// The files in a year folder with the documents of `kind` and with their
+/// line items.
+pub fn files(kind: Kind) -> (&'static str, &'static str) {
+    match kind {
+        Kind::Invoice => ("invoices.csv", "data.csv"),
+        Kind::CreditNote => ("credit-notes.csv", "credit-note-data.csv"),
+    }
 }
 
-impl Clients {
-    fn from_reader<R: Read>(reader: R) -> Result<Self, Box<dyn Error>> {
-        #[derive(Debug, Deserialize)]
-        struct SerializedClient {
-            id: String,
-            name: String,
-            street: String,
-            city: String,
-            country: String,
-            vat: String,
-            vatpolicy: String,
-            partyid: u32,
+/// The year folders below `path`, in ascending order.
+pub fn years(path: &Path) -> Result<Vec<u16>> {
+    let entries = fs::read_dir(path).map_err(|error| Error::io(path, error))?;
+    let mut years = vec![];
+    for entry in entries {
+        let entry = entry.map_err(|error| Error::io(path, error))?;
+        let is_dir = entry.file_type().map(|file_type| file_type.is_dir()).unwrap_or(false);
+        let year = entry.file_name().to_str().and_then(|name| name.parse().ok());
+        if let (true, Some(year)) = (is_dir, year) {
+            years.push(year);
         }
+    }
+    years.sort();
+    Ok(years)
+}
 
-        impl Into<(String, Client)> for SerializedClient {
-            fn into(self) -> (String, Client) {
-                let Self {
-                    id,
-                    name,
-                    street,
-                    city,
-                    country,
-                    vat,
-                    vatpolicy,
-                    partyid,
-                } = self;
-                let client = Client {
-                    name,
-                    street,
-                    city,
-                    country,
-                    vat,
-                    vatpolicy,
-                    partyid,
-                };
-                (id, client)
-            }
+/// A data tree with `clients.csv` and `businesses.csv` at the root and
+/// `invoices.csv`, `data.csv` and optionally `payments.csv` in a folder per
+/// year.
+pub struct CsvTree {
+    path: PathBuf,
+    separator: char,
+}
+
+impl CsvTree {
+    pub fn new(path: &Path, config: &Config) -> Self {
+        Self {
+            path: path.to_owned(),
+            separator: config.decimal_separator,
         }
+    }
+}
 
-        trace!("Clients::from_reader");
+impl DataSource for CsvTree {
+    fn clients(&self) -> Result<HashMap<String, Client>> {
+        read_file(self.path.join("clients.csv"), read_clients)
+    }
 
-        let mut reader = Reader::from_reader(reader);
-        let clients = reader
-            .deserialize::<SerializedClient>()
-            .map(|result| result.map(SerializedClient::into))
-            .collect::<csv_parser::Result<_>>()?;
-        Ok(Self {
-            clients,
-        })
+    fn businesses(&self) -> Result<HashMap<String, Business>> {
+        read_file(self.path.join("businesses.csv"), read_businesses)
     }
 
-    fn get(&self, key: &str) -> Option<&Client> {
-        self.clients.get(key)
+    fn years(&self) -> Result<Vec<u16>> {
+        years(&self.path)
     }
-}
 
-struct Businesses {
-    businesses: HashMap<String, Business>,
-}
+    fn invoices(&self, kind: Kind, year: u16) -> Result<HashMap<u8, InvoiceRecord>> {
+        let path = self.path.join(year.to_string()).join(files(kind).0);
+        // Most years have no credit notes.
+        if kind == Kind::CreditNote && !path.exists() {
+            return Ok(HashMap::new());
+        }
+        read_file(path, |reader| read_invoice_data(reader, self.separator))
+    }
 
-impl Businesses {
-    fn from_reader<R: Read>(reader: R) -> Result<Self, Box<dyn Error>> {
-        #[derive(Debug, Deserialize)]
-        struct SerializedBusiness {
-            id: String,
-            name: String,
-            street: String,
-            city: String,
-            country: String,
-            vat: String,
-            bank: String,
-            iban: String,
-            bic: String,
+    fn line_items(&self, kind: Kind, year: u16) -> Result<HashMap<u8, Vec<LineItemRecord>>> {
+        let path = self.path.join(year.to_string()).join(files(kind).1);
+        if kind == Kind::CreditNote && !path.exists() {
+            return Ok(HashMap::new());
         }
+        read_file(path.clone(), |reader| read_costs(reader, &path, self.separator))
+    }
 
-        impl Into<(String, Business)> for SerializedBusiness {
-            fn into(self) -> (String, Business) {
-                let Self {
-                    id,
-                    name,
-                    street,
-                    city,
-                    country,
-                    vat,
-                    bank,
-                    iban,
-                    bic,
-                } = self;
-                let business = Business {
-                    name,
-                    street,
-                    city,
-                    country,
-                    vat,
-                    bank,
-                    iban,
-                    bic,
-                };
-                (id, business)
-            }
+    fn payments(&self, year: u16) -> Result<HashMap<u8, Vec<PaymentRecord>>> {
+        let path = self.path.join(year.to_string()).join("payments.csv");
+        if !path.exists() {
+            return Ok(HashMap::new());
+        }
+        read_file(path.clone(), |reader| read_payments(reader, &path, self.separator))
+    }
+
+    fn add_invoice(
+        &self,
+        index: InvoiceIndex,
+        invoice: &InvoiceRecord,
+        items: &[LineItemRecord],
+    ) -> Result<()> {
+        let folder = self.path.join(index.year.to_string());
+        fs::create_dir_all(&folder).map_err(|error| Error::io(&folder, error))?;
+        let (invoices, data) = files(index.kind);
+
+        let mut row = vec![
+            ("index", index.index.to_string()),
+            ("business", invoice.business.clone()),
+            ("client", invoice.client.clone()),
+            ("year", invoice.year.to_string()),
+            ("month", invoice.month.to_string()),
+            ("day", invoice.day.to_string()),
+        ];
+        if let Some(currency) = invoice.currency {
+            row.push(("currency", currency.to_string()));
+        }
+        if let Some(credits) = invoice.credits {
+            row.push(("credits", credits.to_string()));
+        }
+        // Amounts are written back with the separator they are read with.
+        let separator = |text: String| text.replace('.', &self.separator.to_string());
+        if let Some(ref discount) = invoice.discount {
+            row.push(("discount", separator(discount.to_string())));
         }
 
-        trace!("Clients::from_reader");
+        let amount = |amount: &Option<Amount>| {
+            separator(amount.as_ref().map(Amount::to_string).unwrap_or_default())
+        };
+        let rows = items
+            .iter()
+            .map(|item| {
+                let mut row = vec![
+                    ("index", index.index.to_string()),
+                    ("price", amount(&item.amount)),
+                    ("services", item.description.clone()),
+                    ("attachment", item.attachment.clone().unwrap_or_default()),
+                ];
+                if let Some(quantity) = item.quantity {
+                    row.push(("quantity", quantity.to_string()));
+                }
+                if let Some(ref unit) = item.unit {
+                    row.push(("unit", unit.clone()));
+                }
+                if item.unit_price.is_some() {
+                    row.push(("unit_price", amount(&item.unit_price)));
+                }
+                if let Some(vat_rate) = item.vat_rate {
+                    row.push(("vat_rate", vat_rate.to_string()));
+                }
+                if let Some(ref discount) = item.discount {
+                    row.push(("discount", separator(discount.to_string())));
+                }
+                row
+            })
+            .collect::<Vec<_>>();
+        // Line items without an invoice are left out, and `next_index` skips
+        // their index, so an invoice is only added once its items are.
+        append_rows(&folder.join(data), &rows)?;
+        append_rows(&folder.join(invoices), &[row])
+    }
+}
 
-        let mut reader = Reader::from_reader(reader);
-        let businesses = reader
-            .deserialize::<SerializedBusiness>()
-            .map(|result| result.map(SerializedBusiness::into))
-            .collect::<csv_parser::Result<_>>()?;
-        Ok(Self {
-            businesses,
-        })
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    /// A client and a business for the invoices of the test data trees.
+    const CLIENTS: &str = "id,name,street,city,country,vat,vatpolicy,partyid
+acme,Acme Corp,1 Main Street,Brussels,Belgium,BE0123456749,,1
+";
+    const BUSINESSES: &str = "id,name,street,city,country,vat,bank,iban,bic
+me,My Business,2 High Street,Ghent,Belgium,BE0987654321,Bank,BE68539007547034,GKCCBEBB
+";
+
+    /// Writes a data tree named `name` in the temporary directory with
+    /// `files`, given as pairs of path and contents, and the clients and
+    /// businesses above unless `files` has its own.
+    pub fn tree(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let path = env::temp_dir().join(format!("invoices-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&path);
+        let defaults = [("clients.csv", CLIENTS), ("businesses.csv", BUSINESSES)];
+        for &(file, content) in defaults.iter().chain(files) {
+            let file = path.join(file);
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            fs::write(file, content).unwrap();
+        }
+        path
     }
 
-    fn get(&self, key: &str) -> Option<&Business> {
-        self.businesses.get(key)
+    #[test]
+    fn parties_with_and_without_details() {
+        let clients = read_clients(CLIENTS.as_bytes()).unwrap();
+        let acme = &clients["acme"];
+        assert_eq!((acme.name.as_str(), acme.partyid, acme.currency), ("Acme Corp", 1, None));
+        assert_eq!(acme.details.postal_code, "");
+        assert_eq!(acme.details.email, "");
+        let businesses = read_businesses(BUSINESSES.as_bytes()).unwrap();
+        assert_eq!(businesses["me"].details.registration_number, "");
+
+        let clients = "id,name,street,city,country,vat,vatpolicy,partyid,postal_code,\
+address_line2,region,contact_name,email,phone,registration_number
+acme,Acme Corp,1 Main Street,Brussels,Belgium,,,1,1000,Floor 2,Brussels-Capital,Jane Doe,\
+jane@acme.example,+32 2 123 45 67,0123.456.749
+";
+        let details = &read_clients(clients.as_bytes()).unwrap()["acme"].details;
+        assert_eq!(
+            (details.postal_code.as_str(), details.address_line2.as_str()),
+            ("1000", "Floor 2")
+        );
+        assert_eq!(
+            (details.region.as_str(), details.contact_name.as_str()),
+            ("Brussels-Capital", "Jane Doe")
+        );
+        assert_eq!(
+            (details.email.as_str(), details.phone.as_str()),
+            ("jane@acme.example", "+32 2 123 45 67")
+        );
+        assert_eq!(details.registration_number, "0123.456.749");
+
+        let businesses = "id,name,street,city,country,vat,bank,iban,bic,postal_code
+me,My Business,2 High Street,Ghent,Belgium,,Bank,BE68539007547034,,9000
+";
+        let details = &read_businesses(businesses.as_bytes()).unwrap()["me"].details;
+        assert_eq!((details.postal_code.as_str(), details.region.as_str()), ("9000", ""));
     }
-}
 
-pub fn read_invoice(path: &Path, index: InvoiceIndex) -> Result<Invoice, Box<dyn Error>> {
-    let clients = Clients::from_reader(File::open(path.join("clients.csv"))?)?;
-    let businesses = Businesses::from_reader(File::open(path.join("businesses.csv"))?)?;
-
-    let data = Invoices::read(path, index.year)?;
-    let (costs, metadata) = data.get(index.index);
-
-    let &SerializedInvoiceData {
-        ref business,
-        ref client,
-        year,
-        month,
-        day,
-    } = metadata;
-    let metadata = InvoiceData {
-        business: businesses.get(business).ok_or(MissingData("Missing business"))?.clone(),
-        client: clients.get(client).ok_or(MissingData("Missing client"))?.clone(),
-        date: Date::new(year, month, day).ok_or(MissingData("Invalid date"))?,
-    };
+    fn amounts(name: &str, data: &str) -> Result<Vec<Option<Amount>>> {
+        let path = tree(name, &[("2018/data.csv", data)]);
+        let items = CsvTree::new(&path, &Config::default()).line_items(Kind::Invoice, 2018);
+        let _ = fs::remove_dir_all(&path);
+        Ok(items?.remove(&1).unwrap().into_iter().map(|item| item.amount).collect())
+    }
 
-    Ok(Invoice {
-        index,
-        metadata: metadata,
-        items: costs.to_vec(),
-    })
+    #[test]
+    fn prices_and_amounts_in_cents() {
+        let data = "index,amount,services,attachment
+1,8000,Consulting,
+";
+        assert_eq!(amounts("cents", data).unwrap(), [Some(Amount::cents(8000))]);
+
+        let data = "index,amount,price,quantity,unit_price,services,attachment
+1,,80.00,,,Consulting,
+1,8000,,,,Consulting,
+1,,,2,40.00,Consulting,
+";
+        let eighty = Amount::parse("80.00", '.').unwrap();
+        assert_eq!(amounts("prices", data).unwrap(), [Some(eighty.clone()), Some(eighty), None]);
+
+        let data = "index,amount,services,attachment
+1,80.00,Consulting,
+";
+        let error = amounts("decimal-cents", data).unwrap_err().to_string();
+        assert!(error.ends_with("`amount` is in cents, prices go in `price`"), "{}", error);
+
+        let data = "index,amount,price,services,attachment
+1,8000,80.00,Consulting,
+";
+        let error = amounts("both", data).unwrap_err().to_string();
+        assert!(error.ends_with("data.csv:2: both price and amount in cents"), "{}", error);
+    }
 }
@@ -0,0 +1,525 @@
+use error::{Error, Result};
+use invoice::{ContactDetails, Date, Invoice, Kind, LineItem, Money, VatRate};
+
+/// The ISO 3166 codes of the countries that can be written out in
+/// `clients.csv` and `businesses.csv`, by lowercase name.
+const COUNTRIES: &[(&str, &str)] = &[
+    ("australia", "AU"),
+    ("austria", "AT"),
+    ("belgium", "BE"),
+    ("belgië", "BE"),
+    ("belgique", "BE"),
+    ("bulgaria", "BG"),
+    ("canada", "CA"),
+    ("croatia", "HR"),
+    ("cyprus", "CY"),
+    ("czech republic", "CZ"),
+    ("czechia", "CZ"),
+    ("denmark", "DK"),
+    ("estonia", "EE"),
+    ("finland", "FI"),
+    ("france", "FR"),
+    ("germany", "DE"),
+    ("deutschland", "DE"),
+    ("greece", "GR"),
+    ("hungary", "HU"),
+    ("iceland", "IS"),
+    ("ireland", "IE"),
+    ("italy", "IT"),
+    ("japan", "JP"),
+    ("latvia", "LV"),
+    ("liechtenstein", "LI"),
+    ("lithuania", "LT"),
+    ("luxembourg", "LU"),
+    ("malta", "MT"),
+    ("netherlands", "NL"),
+    ("the netherlands", "NL"),
+    ("nederland", "NL"),
+    ("new zealand", "NZ"),
+    ("norway", "NO"),
+    ("poland", "PL"),
+    ("portugal", "PT"),
+    ("romania", "RO"),
+    ("singapore", "SG"),
+    ("slovakia", "SK"),
+    ("slovenia", "SI"),
+    ("spain", "ES"),
+    ("sweden", "SE"),
+    ("switzerland", "CH"),
+    ("united kingdom", "GB"),
+    ("uk", "GB"),
+    ("united states", "US"),
+    ("united states of america", "US"),
+    ("usa", "US"),
+];
+
+/// The Peppol electronic address schemes for VAT numbers, by the country
+/// prefix of the VAT number.
+const VAT_SCHEMES: &[(&str, &str)] = &[
+    ("AT", "9914"),
+    ("BE", "9925"),
+    ("BG", "9926"),
+    ("CH", "9927"),
+    ("CY", "9928"),
+    ("CZ", "9929"),
+    ("DE", "9930"),
+    ("EE", "9931"),
+    ("EL", "9933"),
+    ("ES", "9920"),
+    ("FR", "9957"),
+    ("GB", "9932"),
+    ("HR", "9934"),
+    ("HU", "9910"),
+    ("IE", "9935"),
+    ("IT", "9906"),
+    ("LI", "9936"),
+    ("LT", "9937"),
+    ("LU", "9938"),
+    ("LV", "9939"),
+    ("MT", "9943"),
+    ("NL", "9944"),
+    ("PL", "9945"),
+    ("PT", "9946"),
+    ("RO", "9947"),
+    ("SI", "9949"),
+    ("SK", "9950"),
+];
+
+/// The ISO 3166 code of `country`, which may be a code already.
+fn country_code(country: &str) -> Option<&'static str> {
+    let country = country.trim();
+    let lowercase = country.to_lowercase();
+    COUNTRIES
+        .iter()
+        .find(|&&(name, code)| name == lowercase || code.eq_ignore_ascii_case(country))
+        .map(|&(_, code)| code)
+}
+
+/// A VAT number or IBAN without the spaces and dots it is often written with.
+fn compact(vat: &str) -> String {
+    vat.chars().filter(char::is_ascii_alphanumeric).collect::<String>().to_uppercase()
+}
+
+/// The UN/ECE recommendation 20 code of a unit.
+fn unit_code(unit: Option<&str>) -> &'static str {
+    match unit.map(|unit| unit.trim().to_lowercase()).as_deref() {
+        Some("h") | Some("hr") | Some("hour") | Some("hours") => "HUR",
+        Some("d") | Some("day") | Some("days") => "DAY",
+        Some("week") | Some("weeks") => "WEE",
+        Some("month") | Some("months") => "MON",
+        Some("km") => "KMT",
+        _ => "C62",
+    }
+}
+
+/// The seller or the buyer of an invoice.
+pub struct Party<'a> {
+    /// Whose party this is, for error messages.
+    pub role: &'static str,
+    pub name: &'a str,
+    pub street: &'a str,
+    pub city: &'a str,
+    /// The ISO 3166 code of the country.
+    pub country: &'static str,
+    /// The VAT number, compacted; empty if there is none.
+    pub vat: String,
+    pub details: &'a ContactDetails,
+    /// The electronic address scheme and address where the party receives
+    /// e-invoices: the Peppol address of its VAT number, or else its email
+    /// address.
+    pub endpoint: Option<(&'static str, String)>,
+}
+
+impl<'a> Party<'a> {
+    fn new(
+        role: &'static str,
+        name: &'a str,
+        street: &'a str,
+        city: &'a str,
+        country: &str,
+        vat: &str,
+        details: &'a ContactDetails,
+    ) -> ::std::result::Result<Self, String> {
+        let code = country_code(country)
+            .ok_or_else(|| format!("unknown country `{}` for {} `{}`", country, role, name))?;
+        let vat = compact(vat);
+        let endpoint = match VAT_SCHEMES.iter().find(|&&(prefix, _)| vat.starts_with(prefix)) {
+            Some(&(_, scheme)) => Some((scheme, vat.clone())),
+            None if !details.email.is_empty() => Some(("EM", details.email.clone())),
+            None => None,
+        };
+        Ok(Self {
+            role,
+            name,
+            street,
+            city,
+            country: code,
+            vat,
+            details,
+            endpoint,
+        })
+    }
+
+    /// Whether there is a contact person, phone number or email address.
+    pub fn has_contact(&self) -> bool {
+        let details = self.details;
+        !(details.contact_name.is_empty() && details.phone.is_empty() && details.email.is_empty())
+    }
+}
+
+/// The VAT category of items at `rate`: standard rated, reverse charged if
+/// the client's VAT policy says so, zero rated if the business has a VAT
+/// number, or else not subject to VAT.
+pub struct TaxCategory<'a> {
+    /// The UNCL 5305 code, such as `S` for standard rated.
+    pub code: &'static str,
+    pub rate: VatRate,
+    /// Why no VAT is charged, for reverse charged items and items not subject
+    /// to VAT: the VATEX reason code and the reason.
+    pub exemption: Option<(&'static str, &'a str)>,
+}
+
+impl<'a> TaxCategory<'a> {
+    /// The exemption reason code for reverse charged items.
+    pub const REVERSE_CHARGE: &'static str = "VATEX-EU-AE";
+    /// The exemption reason code for items not subject to VAT.
+    pub const NOT_SUBJECT: &'static str = "VATEX-EU-O";
+
+    fn new(rate: VatRate, invoice: &'a Invoice) -> Self {
+        let client = &invoice.metadata.client;
+        // Zero rating needs the business's VAT number (BR-Z-02); without one,
+        // the business is not registered for VAT.
+        let (code, exemption) = if rate.0 > 0 {
+            ("S", None)
+        } else if client.vatpolicy.to_lowercase().contains("reverse") {
+            ("AE", Some((Self::REVERSE_CHARGE, &*client.vatpolicy)))
+        } else if !compact(&invoice.metadata.business.vat).is_empty() {
+            ("Z", None)
+        } else {
+            ("O", Some((Self::NOT_SUBJECT, "Not subject to VAT")))
+        };
+        Self {
+            code,
+            rate,
+            exemption,
+        }
+    }
+
+    /// The rate as a plain number, such as `21` or `5.5`.
+    pub fn percent(&self) -> String {
+        self.rate.to_string().trim_end_matches('%').to_string()
+    }
+}
+
+/// A discount on a line, or on the whole document at one VAT category.
+pub struct Allowance<'a> {
+    pub reason: String,
+    pub amount: Money,
+    /// The category the discount applies to, for document discounts.
+    pub category: Option<TaxCategory<'a>>,
+}
+
+impl<'a> Allowance<'a> {
+    /// The UNCL 5189 reason code for discounts.
+    pub const DISCOUNT: &'static str = "95";
+}
+
+pub struct Line<'a> {
+    /// The line number, from 1.
+    pub id: String,
+    pub item: &'a LineItem,
+    pub quantity: String,
+    pub unit: &'static str,
+    /// The price of `base_quantity` units, or else of one unit.
+    pub price: Money,
+    pub base_quantity: Option<String>,
+    /// The amount after the line's discount.
+    pub net_amount: Money,
+    pub allowance: Option<Allowance<'a>>,
+    pub category: TaxCategory<'a>,
+}
+
+impl<'a> Line<'a> {
+    fn new(invoice: &'a Invoice, number: usize, item: &'a LineItem) -> Self {
+        // Without a unit price, the amount is the price of the whole quantity.
+        let (quantity, price, base_quantity) = match (item.quantity, item.unit_price) {
+            (Some(quantity), Some(unit_price)) => (quantity.to_string(), unit_price, None),
+            (Some(quantity), None) => {
+                (quantity.to_string(), item.amount, Some(quantity.to_string()))
+            },
+            (None, _) => ("1".to_string(), item.amount, None),
+        };
+        let allowance = item.discount.as_ref().map(|discount| Allowance {
+            reason: discount.label(),
+            amount: item.discount(),
+            category: None,
+        });
+        Self {
+            id: number.to_string(),
+            item,
+            quantity,
+            unit: unit_code(item.quantity.and(item.unit.as_deref())),
+            price,
+            base_quantity,
+            net_amount: item.net_amount(),
+            allowance,
+            category: TaxCategory::new(invoice.vat_rate(item), invoice),
+        }
+    }
+}
+
+/// The VAT due in one category.
+pub struct Tax<'a> {
+    pub category: TaxCategory<'a>,
+    pub base: Money,
+    pub amount: Money,
+}
+
+/// How an invoice is to be paid, by credit transfer.
+pub struct Payment<'a> {
+    /// The UNCL 4461 code for credit transfers.
+    pub means_code: &'static str,
+    pub reference: String,
+    pub iban: String,
+    pub account_name: &'a str,
+    pub bic: &'a str,
+    pub terms: String,
+    pub due_date: Date,
+}
+
+/// An invoice or credit note in the terms of the European e-invoicing
+/// standard, EN 16931, from which each syntax is written.
+pub struct Document<'a> {
+    pub invoice: &'a Invoice,
+    pub number: String,
+    /// The UNCL 1001 document type code.
+    pub type_code: &'static str,
+    /// The buyer reference, which Peppol requires: the client's contact
+    /// person, or else the invoice number.
+    pub buyer_reference: String,
+    /// The number of the invoice a credit note cancels.
+    pub preceding_invoice: Option<String>,
+    pub seller: Party<'a>,
+    pub buyer: Party<'a>,
+    /// How to pay; `None` for credit notes.
+    pub payment: Option<Payment<'a>>,
+    pub allowances: Vec<Allowance<'a>>,
+    pub taxes: Vec<Tax<'a>>,
+    /// The total of the lines after their own discounts.
+    pub line_total: Money,
+    /// The total of the document discounts, if there are any.
+    pub allowance_total: Option<Money>,
+    pub tax_basis_total: Money,
+    pub tax_total: Money,
+    pub grand_total: Money,
+    pub lines: Vec<Line<'a>>,
+}
+
+impl<'a> Document<'a> {
+    pub fn new(invoice: &'a Invoice) -> Result<Self> {
+        let invalid = |message| Error::EInvoice {
+            invoice: invoice.index,
+            message,
+        };
+
+        let metadata = &invoice.metadata;
+        let business = &metadata.business;
+        let client = &metadata.client;
+        let seller = Party::new(
+            "business",
+            &business.name,
+            &business.street,
+            &business.city,
+            &business.country,
+            &business.vat,
+            &business.details,
+        )
+        .map_err(invalid)?;
+        let mut buyer = Party::new(
+            "client",
+            &client.name,
+            &client.street,
+            &client.city,
+            &client.country,
+            &client.vat,
+            &client.details,
+        )
+        .map_err(invalid)?;
+
+        let payment = match invoice.index.kind {
+            Kind::Invoice => Some(Payment {
+                means_code: "30",
+                reference: invoice.index.to_string(),
+                iban: compact(&business.iban),
+                account_name: &business.name,
+                bic: &business.bic,
+                terms: metadata.policy.payment_terms.to_string(),
+                due_date: invoice.due_date(),
+            }),
+            Kind::CreditNote => None,
+        };
+
+        let breakdown = invoice.vat_breakdown()?;
+        let mut allowances = vec![];
+        if let Some(ref discount) = metadata.discount {
+            for amount in &breakdown {
+                if amount.discount.amount != 0 {
+                    allowances.push(Allowance {
+                        reason: discount.label(),
+                        amount: amount.discount,
+                        category: Some(TaxCategory::new(amount.rate, invoice)),
+                    });
+                }
+            }
+        }
+        let taxes = breakdown
+            .iter()
+            .map(|amount| Tax {
+                category: TaxCategory::new(amount.rate, invoice),
+                base: amount.base,
+                amount: amount.vat,
+            })
+            .collect::<Vec<_>>();
+
+        // The business rules of EN 16931 that the data itself can break.
+        let has_category = |code| taxes.iter().any(|tax| tax.category.code == code);
+        if payment.as_ref().is_some_and(|payment| payment.iban.is_empty()) {
+            let message = "needs an IBAN to be paid by credit transfer (BR-61)";
+            return Err(invalid(format!("business `{}` {}", business.name, message)));
+        }
+        if seller.vat.is_empty() && has_category("S") {
+            let message = "needs a VAT number to charge VAT (BR-S-02)";
+            return Err(invalid(format!("business `{}` {}", business.name, message)));
+        }
+        if seller.vat.is_empty() && has_category("AE") {
+            let message = "needs a VAT number to reverse charge VAT (BR-AE-02)";
+            return Err(invalid(format!("business `{}` {}", business.name, message)));
+        }
+        if buyer.vat.is_empty() && has_category("AE") {
+            let message = "needs a VAT number to have VAT reverse charged (BR-AE-02)";
+            return Err(invalid(format!("client `{}` {}", client.name, message)));
+        }
+        // A document not subject to VAT names no VAT numbers (BR-O-02); the
+        // client's still serves as its electronic address.
+        if has_category("O") {
+            buyer.vat.clear();
+        }
+
+        Ok(Self {
+            invoice,
+            number: invoice.index.to_string(),
+            type_code: match invoice.index.kind {
+                Kind::Invoice => "380",
+                Kind::CreditNote => "381",
+            },
+            buyer_reference: if client.details.contact_name.is_empty() {
+                invoice.index.to_string()
+            } else {
+                client.details.contact_name.clone()
+            },
+            preceding_invoice: metadata.credits.map(|index| index.to_string()),
+            seller,
+            buyer,
+            payment,
+            allowances,
+            taxes,
+            line_total: invoice.subtotal()?,
+            allowance_total: match metadata.discount {
+                Some(_) => Some(invoice.discount()?),
+                None => None,
+            },
+            tax_basis_total: invoice.total_excl_vat()?,
+            tax_total: invoice.total_vat()?,
+            grand_total: invoice.total()?,
+            lines: invoice
+                .items
+                .iter()
+                .enumerate()
+                .map(|(i, item)| Line::new(invoice, i + 1, item))
+                .collect(),
+        })
+    }
+
+    pub fn kind(&self) -> Kind {
+        self.invoice.index.kind
+    }
+
+    pub fn issue_date(&self) -> Date {
+        self.invoice.metadata.date
+    }
+
+    pub fn currency(&self) -> &'static str {
+        self.invoice.metadata.currency.code()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use invoice::tests;
+    use invoice::{Discount, Invoice};
+
+    /// The invoice of `invoice::tests::invoice`, between a business and a
+    /// client in Belgium that both have a VAT number.
+    pub fn invoice(items: &[(i64, &str)], discount: Option<Discount<Money>>) -> Invoice {
+        let mut invoice = tests::invoice(items, discount);
+        let business = &mut invoice.metadata.business;
+        business.street = "2 High Street".to_string();
+        business.city = "Ghent".to_string();
+        business.country = "Belgium".to_string();
+        business.vat = "BE 0987.654.321".to_string();
+        business.iban = "BE68 5390 0754 7034".to_string();
+        business.bic = "GKCCBEBB".to_string();
+        let client = &mut invoice.metadata.client;
+        client.street = "1 Main Street".to_string();
+        client.city = "Brussels".to_string();
+        client.country = "Belgium".to_string();
+        client.vat = "BE0123456749".to_string();
+        invoice
+    }
+
+    fn categories(document: &Document) -> Vec<(&'static str, Option<&'static str>)> {
+        let taxes = document.taxes.iter();
+        taxes.map(|tax| (tax.category.code, tax.category.exemption.map(|(code, _)| code))).collect()
+    }
+
+    #[test]
+    fn vat_schemes() {
+        let details = ContactDetails::default();
+        let party = |vat| Party::new("client", "Acme", "", "", "Greece", vat, &details).unwrap();
+        assert_eq!(party("EL 123 456 789").endpoint, Some(("9933", "EL123456789".to_string())));
+        assert_eq!(party("GR123456789").endpoint, None);
+        assert_eq!(party("BE0123.456.749").endpoint, Some(("9925", "BE0123456749".to_string())));
+        assert_eq!(party("BE0123.456.749").country, "GR");
+    }
+
+    #[test]
+    fn tax_categories() {
+        let standard = invoice(&[(10000, "21"), (5000, "0")], None);
+        let document = Document::new(&standard).unwrap();
+        assert_eq!(categories(&document), [("Z", None), ("S", None)]);
+        assert_eq!(document.buyer.vat, "BE0123456749");
+
+        let mut reverse = invoice(&[(10000, "0")], None);
+        reverse.metadata.client.vatpolicy = "Reverse charge".to_string();
+        let document = Document::new(&reverse).unwrap();
+        assert_eq!(categories(&document), [("AE", Some(TaxCategory::REVERSE_CHARGE))]);
+        reverse.metadata.client.vat.clear();
+        let error = Document::new(&reverse).err().unwrap().to_string();
+        assert!(error.contains("client `Acme` needs a VAT number"), "{}", error);
+
+        // Without a VAT number, the business cannot zero rate (BR-Z-02).
+        let mut unregistered = invoice(&[(10000, "0")], None);
+        unregistered.metadata.business.vat.clear();
+        let document = Document::new(&unregistered).unwrap();
+        assert_eq!(categories(&document), [("O", Some(TaxCategory::NOT_SUBJECT))]);
+        assert_eq!(document.lines[0].category.code, "O");
+        assert_eq!(document.buyer.vat, "");
+        assert_eq!(document.buyer.endpoint, Some(("9925", "BE0123456749".to_string())));
+
+        let mut unregistered = invoice(&[(10000, "21")], None);
+        unregistered.metadata.business.vat.clear();
+        let error = Document::new(&unregistered).err().unwrap().to_string();
+        assert!(error.contains("(BR-S-02)"), "{}", error);
+    }
+}
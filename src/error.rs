@@ -0,0 +1,296 @@
+use csv_parser;
+use invoice::{CurrencyMismatch, InvoiceIndex};
+use lopdf;
+use rusqlite;
+use std::error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::result;
+use toml;
+
+/// Everything that can go wrong while reading invoice data or generating
+/// invoices.
+#[derive(Debug)]
+pub enum Error {
+    /// The command line could not be parsed.
+    Arguments(String),
+    /// A file could not be read or written.
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+    /// A CSV file could not be parsed.
+    Csv {
+        path: PathBuf,
+        error: csv_parser::Error,
+    },
+    /// A CSV record could be parsed, but its contents are invalid.
+    InvalidRecord {
+        path: PathBuf,
+        line: Option<u64>,
+        message: String,
+    },
+    /// The SQLite database could not be read or written.
+    Sqlite {
+        path: PathBuf,
+        error: rusqlite::Error,
+    },
+    /// The data source at `path` cannot be written to.
+    ReadOnly(PathBuf),
+    /// `invoices.toml` could not be parsed.
+    Config {
+        path: PathBuf,
+        error: toml::de::Error,
+    },
+    /// An invoice has no entry in `file` (`invoices.csv` or `data.csv`).
+    MissingEntry {
+        file: &'static str,
+        invoice: InvoiceIndex,
+    },
+    MissingBusiness {
+        invoice: InvoiceIndex,
+        business: String,
+    },
+    MissingClient {
+        invoice: InvoiceIndex,
+        client: String,
+    },
+    InvalidDate {
+        invoice: InvoiceIndex,
+        year: u16,
+        month: u8,
+        day: u8,
+    },
+    /// The discount on an invoice does not fit its currency.
+    InvalidDiscount {
+        invoice: InvoiceIndex,
+        message: String,
+    },
+    /// An invoice lacks what its structured e-invoice requires.
+    EInvoice {
+        invoice: InvoiceIndex,
+        message: String,
+    },
+    /// A credit note does not say which invoice it credits.
+    MissingCreditedInvoice {
+        credit_note: InvoiceIndex,
+    },
+    /// An invoice mixes amounts in different currencies.
+    CurrencyMismatch(CurrencyMismatch),
+    /// The HTML template does not match the invoice data.
+    Template(String),
+    /// Prince could not be run.
+    PdfSpawn(io::Error),
+    /// Prince ran but failed.
+    Pdf(ExitStatus),
+    /// The PDF written by Prince could not be turned into PDF/A.
+    PdfA {
+        path: PathBuf,
+        error: lopdf::Error,
+    },
+    Json(serde_json::Error),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+impl Error {
+    pub fn io<P: Into<PathBuf>>(path: P, error: io::Error) -> Self {
+        Error::Io {
+            path: path.into(),
+            error,
+        }
+    }
+
+    pub fn csv<P: Into<PathBuf>>(path: P, error: csv_parser::Error) -> Self {
+        Error::Csv {
+            path: path.into(),
+            error,
+        }
+    }
+
+    pub fn sqlite<P: Into<PathBuf>>(path: P, error: rusqlite::Error) -> Self {
+        Error::Sqlite {
+            path: path.into(),
+            error,
+        }
+    }
+
+    /// The process exit status for this error, following `sysexits.h`.
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            Error::Arguments(_) => 64,
+            Error::Csv {
+                ..
+            }
+            | Error::InvalidRecord {
+                ..
+            }
+            | Error::MissingEntry {
+                ..
+            }
+            | Error::MissingBusiness {
+                ..
+            }
+            | Error::MissingClient {
+                ..
+            }
+            | Error::InvalidDate {
+                ..
+            }
+            | Error::InvalidDiscount {
+                ..
+            }
+            | Error::EInvoice {
+                ..
+            }
+            | Error::MissingCreditedInvoice {
+                ..
+            }
+            | Error::CurrencyMismatch(_) => 65,
+            Error::Io {
+                ref error,
+                ..
+            } if error.kind() == io::ErrorKind::NotFound => 66,
+            Error::PdfSpawn(_) | Error::Pdf(_) => 69,
+            Error::ReadOnly(_) => 73,
+            Error::Template(_)
+            | Error::PdfA {
+                ..
+            }
+            | Error::Json(_) => 70,
+            Error::Io {
+                ..
+            }
+            | Error::Sqlite {
+                ..
+            } => 74,
+            Error::Config {
+                ..
+            } => 78,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Arguments(ref message) => message.fmt(f),
+            Error::Io {
+                ref path,
+                ref error,
+            } => write!(f, "{}: {}", path.display(), error),
+            Error::Csv {
+                ref path,
+                ref error,
+            } => write!(f, "{}: {}", path.display(), error),
+            Error::InvalidRecord {
+                ref path,
+                line: Some(line),
+                ref message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+            Error::InvalidRecord {
+                ref path,
+                line: None,
+                ref message,
+            } => write!(f, "{}: {}", path.display(), message),
+            Error::Sqlite {
+                ref path,
+                ref error,
+            } => write!(f, "{}: {}", path.display(), error),
+            Error::ReadOnly(ref path) => {
+                write!(f, "{}: cannot add invoices, edit the file by hand", path.display())
+            },
+            Error::Config {
+                ref path,
+                ref error,
+            } => write!(f, "{}: {}", path.display(), error),
+            Error::MissingEntry {
+                file,
+                invoice,
+            } => write!(f, "{} {}: missing entry in {}", invoice.kind, invoice, file),
+            Error::MissingBusiness {
+                invoice,
+                ref business,
+            } => write!(f, "{} {}: unknown business `{}`", invoice.kind, invoice, business),
+            Error::MissingClient {
+                invoice,
+                ref client,
+            } => write!(f, "{} {}: unknown client `{}`", invoice.kind, invoice, client),
+            Error::InvalidDate {
+                invoice,
+                year,
+                month,
+                day,
+            } => write!(
+                f,
+                "{} {}: invalid date {:04}-{:02}-{:02}",
+                invoice.kind, invoice, year, month, day
+            ),
+            Error::InvalidDiscount {
+                invoice,
+                ref message,
+            } => write!(f, "{} {}: {}", invoice.kind, invoice, message),
+            Error::EInvoice {
+                invoice,
+                ref message,
+            } => write!(f, "{} {}: {}", invoice.kind, invoice, message),
+            Error::MissingCreditedInvoice {
+                credit_note,
+            } => write!(f, "credit note {}: missing the invoice it credits", credit_note),
+            Error::CurrencyMismatch(ref error) => error.fmt(f),
+            Error::Template(ref message) => write!(f, "template error: {}", message),
+            Error::PdfSpawn(ref error) => write!(f, "failed to run prince: {}", error),
+            Error::Pdf(status) => write!(f, "failed to create PDF: prince {}", status),
+            Error::PdfA {
+                ref path,
+                ref error,
+            } => write!(f, "{}: failed to create PDF/A: {}", path.display(), error),
+            Error::Json(ref error) => write!(f, "failed to create JSON: {}", error),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io {
+                ref error,
+                ..
+            } => Some(error),
+            Error::Csv {
+                ref error,
+                ..
+            } => Some(error),
+            Error::Sqlite {
+                ref error,
+                ..
+            } => Some(error),
+            Error::Config {
+                ref error,
+                ..
+            } => Some(error),
+            Error::PdfSpawn(ref error) => Some(error),
+            Error::PdfA {
+                ref error,
+                ..
+            } => Some(error),
+            Error::Json(ref error) => Some(error),
+            Error::CurrencyMismatch(ref error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        Error::Json(error)
+    }
+}
+
+impl From<CurrencyMismatch> for Error {
+    fn from(error: CurrencyMismatch) -> Self {
+        Error::CurrencyMismatch(error)
+    }
+}
@@ -1,10 +1,12 @@
-use invoice::{Invoice, LineItem};
+use error::{Error, Result};
+use invoice::{Discount, Invoice, Money, VatAmount};
+use reminders::Reminder;
+use url_path;
 
 use kuchiki::{self, Attribute, ExpandedName, NodeRef};
 use kuchiki::traits::TendrilSink;
 use markup5ever::{LocalName, QualName};
 use std::collections::HashMap;
-use std::error::Error;
 
 fn create_element<I>(local: LocalName, attributes: I) -> NodeRef
 where
@@ -18,94 +20,497 @@ where
     NodeRef::new_element(name, attributes)
 }
 
-fn insert_output(document: &NodeRef, data: &HashMap<&str, &str>) {
+fn attribute(local: LocalName, value: String) -> (ExpandedName, Attribute) {
+    let name = ExpandedName {
+        ns: ns!(),
+        local,
+    };
+    let value = Attribute {
+        prefix: None,
+        value,
+    };
+    (name, value)
+}
+
+fn colspan(columns: u32) -> (ExpandedName, Attribute) {
+    attribute(local_name!("colspan"), columns.to_string())
+}
+
+/// A right-aligned table cell containing `text`.
+fn num_cell(text: String) -> NodeRef {
+    let class = attribute(local_name!("class"), "num".to_string());
+    let cell = create_element(local_name!("td"), Some(class));
+    cell.append(NodeRef::new_text(text));
+    cell
+}
+
+/// A row taking `amount` off for `discount`.
+fn discount_row(discount: &Discount<Money>, amount: Money) -> NodeRef {
+    let class = attribute(local_name!("class"), "discount".to_string());
+    let row = create_element(local_name!("tr"), Some(class));
+    let label_cell = create_element(local_name!("td"), Some(colspan(4)));
+    label_cell.append(NodeRef::new_text(discount.label()));
+    row.append(label_cell);
+    row.append(num_cell((-amount).to_string()));
+    row
+}
+
+fn insert_output(document: &NodeRef, data: &HashMap<&str, String>) -> Result<()> {
     trace!("insert_output");
 
     for output in document.select("output").expect("hard-coded selector") {
         let attributes = output.attributes.borrow();
-        let field =
-            attributes.get("data-field").expect("output element without data-field attribute.");
-        let content = *data.get(field).expect("output element with unknown data-field attribute.");
-        output.as_node().append(NodeRef::new_text(content));
+        let field = attributes.get("data-field").ok_or_else(|| {
+            Error::Template("output element without data-field attribute".to_string())
+        })?;
+        let content = data.get(field).ok_or_else(|| {
+            Error::Template(format!("output element with unknown data-field `{}`", field))
+        })?;
+        output.as_node().append(NodeRef::new_text(content.clone()));
     }
+
+    Ok(())
+}
+
+/// A link to the file attached to a line item; `folder` is where the file is
+/// if it is not next to the generated HTML.
+fn attachment_link(attachment: &str, folder: Option<&str>) -> NodeRef {
+    let href = match folder {
+        Some(folder) => format!("{}/{}", folder, url_path(attachment)),
+        None => url_path(attachment),
+    };
+    let attributes = vec![
+        attribute(local_name!("class"), "attachment".to_string()),
+        attribute(local_name!("href"), href),
+    ];
+    let link = create_element(local_name!("a"), attributes);
+    link.append(NodeRef::new_text(attachment.to_string()));
+    link
 }
 
-fn insert_items(document: &NodeRef, items: &[LineItem]) -> Result<(), Box<dyn Error>> {
+fn insert_items(document: &NodeRef, invoice: &Invoice, folder: Option<&str>) -> Result<()> {
     trace!("insert_items");
 
     let mut items_bodies = document.select(".items").expect("hard-coded selector");
-    let items_body = items_bodies.next().expect("missing .items element");
-    for item in items {
+    let items_body =
+        items_bodies.next().ok_or_else(|| Error::Template("missing .items element".to_string()))?;
+    for item in &invoice.items {
         let row = create_element(local_name!("tr"), None);
         let description_cell = create_element(local_name!("td"), None);
         description_cell.append(NodeRef::new_text(item.description.clone()));
+        if let Some(ref attachment) = item.attachment {
+            description_cell.append(NodeRef::new_text(" "));
+            description_cell.append(attachment_link(attachment, folder));
+        }
         row.append(description_cell);
 
-        let class_name = ExpandedName {
-            ns: ns!(),
-            local: local_name!("class"),
-        };
-        let class_value = Attribute {
-            prefix: None,
-            value: "num".to_string(),
+        let quantity = match (item.quantity, &item.unit) {
+            (Some(quantity), Some(unit)) => format!("{} {}", quantity, unit),
+            (Some(quantity), None) => quantity.to_string(),
+            (None, _) => String::new(),
         };
-        let amount_cell = create_element(local_name!("td"), Some((class_name, class_value)));
-        amount_cell.append(NodeRef::new_text(item.amount.to_string()));
-        row.append(amount_cell);
+        let rate = item.unit_price.map(|price| price.to_string()).unwrap_or_default();
+
+        let vat_rate = invoice.vat_rate(item).to_string();
+
+        for text in [quantity, rate, vat_rate, item.amount.to_string()] {
+            row.append(num_cell(text));
+        }
 
         items_body.as_node().append(row);
+
+        if let Some(ref discount) = item.discount {
+            items_body.as_node().append(discount_row(discount, item.discount()));
+        }
     }
 
-    assert!(items_bodies.next().is_none());
+    if items_bodies.next().is_some() {
+        return Err(Error::Template("more than one .items element".to_string()));
+    }
+
+    Ok(())
+}
+
+/// Inserts a row for the invoice discount, if any, before the `.subtotal` row
+/// of the table footer.
+fn insert_discount(document: &NodeRef, invoice: &Invoice) -> Result<()> {
+    trace!("insert_discount");
 
+    let discount = match invoice.metadata.discount {
+        Some(ref discount) => discount,
+        None => return Ok(()),
+    };
+    let subtotal = document
+        .select_first("tfoot .subtotal")
+        .map_err(|()| Error::Template("missing tfoot .subtotal element".to_string()))?;
+    subtotal.as_node().insert_before(discount_row(discount, invoice.discount()?));
     Ok(())
 }
 
+/// Inserts a row for each VAT rate before the `.total` row of the table
+/// footer.
+fn insert_vat(document: &NodeRef, breakdown: &[VatAmount]) -> Result<()> {
+    trace!("insert_vat");
+
+    let total = document
+        .select_first("tfoot .total")
+        .map_err(|()| Error::Template("missing tfoot .total element".to_string()))?;
+    for amount in breakdown {
+        let row = create_element(local_name!("tr"), None);
+        let label = format!("VAT {} on {}", amount.rate, amount.base);
+        let label_cell = create_element(local_name!("td"), Some(colspan(4)));
+        label_cell.append(NodeRef::new_text(label));
+        row.append(label_cell);
+        row.append(num_cell(amount.vat.to_string()));
+        total.as_node().insert_before(row);
+    }
+
+    Ok(())
+}
+
+/// Replaces the text of the document's title and of its heading.
+fn set_title(document: &NodeRef, title: &str) {
+    trace!("set_title");
+
+    let elements = document.select("title, h1").expect("hard-coded selector");
+    for element in elements.collect::<Vec<_>>() {
+        let node = element.as_node();
+        for child in node.children().collect::<Vec<_>>() {
+            child.detach();
+        }
+        node.append(NodeRef::new_text(title));
+    }
+}
+
+/// Removes the elements with an `attribute` that does not list `value` among
+/// its space-separated values. At least one element must list it.
+fn select(document: &NodeRef, attribute: &str, value: &str) -> Result<()> {
+    trace!("select");
+
+    let selector = format!("[{}]", attribute);
+    let elements = document.select(&selector).expect("hard-coded attribute");
+    let mut found = false;
+    for element in elements.collect::<Vec<_>>() {
+        let listed = element
+            .attributes
+            .borrow()
+            .get(attribute)
+            .is_some_and(|values| values.split_whitespace().any(|listed| listed == value));
+        if listed {
+            found = true;
+        } else {
+            element.as_node().detach();
+        }
+    }
+    if !found {
+        return Err(Error::Template(format!("missing {}={} element", attribute, value)));
+    }
+    Ok(())
+}
+
+fn set_stylesheet(document: &NodeRef, href: &str) {
+    trace!("set_stylesheet");
+
+    for link in document.select("link[rel=stylesheet]").expect("hard-coded selector") {
+        link.attributes.borrow_mut().insert("href", href.to_string());
+    }
+}
+
+fn parse(mut html: &[u8]) -> Result<NodeRef> {
+    kuchiki::parse_html()
+        .from_utf8()
+        .read_from(&mut html)
+        .map_err(|error| Error::Template(error.to_string()))
+}
+
+/// Puts the paragraphs of `letter` in the template's `.letter` element.
+fn insert_letter(document: &NodeRef, letter: &[u8]) -> Result<()> {
+    trace!("insert_letter");
+
+    let target = document
+        .select_first(".letter")
+        .map_err(|()| Error::Template("missing .letter element".to_string()))?;
+    let body = parse(letter)?.select_first("body").expect("parsed document");
+    for child in body.as_node().children().collect::<Vec<_>>() {
+        target.as_node().append(child);
+    }
+    Ok(())
+}
+
+/// Fills in the template for the documents whose `data-kind` is `kind`, with
+/// the text of `letter` if given.
 fn substitute_template(
-    mut template: &[u8],
-    data: &HashMap<&str, &str>,
-    items: &[LineItem],
-) -> Result<NodeRef, Box<dyn Error>> {
+    kind: &str,
+    letter: Option<&[u8]>,
+    stylesheet: Option<&str>,
+    data: &HashMap<&str, String>,
+) -> Result<NodeRef> {
     trace!("substitute_template");
 
-    let document = kuchiki::parse_html().from_utf8().read_from(&mut template)?;
-    insert_output(&document, data);
-    insert_items(&document, items)?;
+    let document = parse(include_bytes!("../etc/template.html"))?;
+    select(&document, "data-kind", kind)?;
+    if let Some(letter) = letter {
+        insert_letter(&document, letter)?;
+    }
+    if let Some(href) = stylesheet {
+        set_stylesheet(&document, href);
+    }
+    insert_output(&document, data)?;
     Ok(document)
 }
 
 impl Invoice {
-    pub fn generate_invoice(&self) -> Result<NodeRef, Box<dyn Error>> {
+    /// The values of the `output` elements of every document about the
+    /// invoice, by `data-field`.
+    fn fields(&self) -> Result<HashMap<&'static str, String>> {
+        let client = &self.metadata.client;
+        let business = &self.metadata.business;
+        let credited_invoice = self.metadata.credits.map(|index| index.to_string());
+        let due_date = self.due_date();
+        Ok(hashmap!{
+            "total" => self.total()?.to_string(),
+            "total-excl-vat" => self.total_excl_vat()?.to_string(),
+            "total-vat" => self.total_vat()?.to_string(),
+            "invoice-date" => self.metadata.date.to_string(),
+            "invoice-index" => self.index.to_string(),
+            "payment-terms" => self.metadata.policy.payment_terms.to_string(),
+            "due-date" => due_date.to_string(),
+            "credited-invoice" => credited_invoice.unwrap_or_default(),
+            "client-name" => client.name.clone(),
+            "client-street" => client.street.clone(),
+            "client-city" => client.city.clone(),
+            "client-country" => client.country.clone(),
+            "client-vat" => client.vat.clone(),
+            "client-vat-policy" => client.vatpolicy.clone(),
+            "client-postal-code" => client.details.postal_code.clone(),
+            "client-address-line2" => client.details.address_line2.clone(),
+            "client-region" => client.details.region.clone(),
+            "client-contact-name" => client.details.contact_name.clone(),
+            "client-email" => client.details.email.clone(),
+            "client-phone" => client.details.phone.clone(),
+            "client-registration-number" => client.details.registration_number.clone(),
+            "business-name" => business.name.clone(),
+            "business-street" => business.street.clone(),
+            "business-city" => business.city.clone(),
+            "business-country" => business.country.clone(),
+            "business-vat" => business.vat.clone(),
+            "business-bank" => business.bank.clone(),
+            "business-iban" => business.iban.clone(),
+            "business-bic" => business.bic.clone(),
+            "business-postal-code" => business.details.postal_code.clone(),
+            "business-address-line2" => business.details.address_line2.clone(),
+            "business-region" => business.details.region.clone(),
+            "business-contact-name" => business.details.contact_name.clone(),
+            "business-email" => business.details.email.clone(),
+            "business-phone" => business.details.phone.clone(),
+            "business-registration-number" => business.details.registration_number.clone(),
+        })
+    }
+
+    /// Renders the invoice; `stylesheet` replaces the template's stylesheet
+    /// link if given, and the links to attachments start with
+    /// `attachment_folder` if given.
+    pub fn generate_invoice(
+        &self,
+        stylesheet: Option<&str>,
+        attachment_folder: Option<&str>,
+    ) -> Result<NodeRef> {
         trace!("Invoice::generate");
 
-        let total = self.total().to_string();
-        let invoice_date = self.metadata.date.to_string();
-        let invoice_index = self.index.to_string();
-
-        let substitutions = hashmap!{
-            "total" => &*total,
-            "invoice-date" => &*invoice_date,
-            "invoice-index" => &*invoice_index,
-            "client-name" => &*self.metadata.client.name,
-            "client-street" => &*self.metadata.client.street,
-            "client-city" => &*self.metadata.client.city,
-            "client-country" => &*self.metadata.client.country,
-            "client-vat" => &*self.metadata.client.vat,
-            "client-vat-policy" => &*self.metadata.client.vatpolicy,
-            "business-name" => &*self.metadata.business.name,
-            "business-street" => &*self.metadata.business.street,
-            "business-city" => &*self.metadata.business.city,
-            "business-country" => &*self.metadata.business.country,
-            "business-vat" => &*self.metadata.business.vat,
-            "business-bank" => &*self.metadata.business.bank,
-            "business-iban" => &*self.metadata.business.iban,
-            "business-bic" => &*self.metadata.business.bic,
+        let kind = self.index.kind;
+        let document = substitute_template(kind.name(), None, stylesheet, &self.fields()?)?;
+        set_title(&document, kind.title());
+        insert_items(&document, self, attachment_folder)?;
+        insert_discount(&document, self)?;
+        insert_vat(&document, &self.vat_breakdown()?)?;
+        Ok(document)
+    }
+}
+
+impl Reminder {
+    /// Renders the reminder letter for its level; `stylesheet` replaces the
+    /// template's stylesheet link if given.
+    pub fn generate_reminder(&self, stylesheet: Option<&str>) -> Result<NodeRef> {
+        trace!("Reminder::generate");
+
+        let mut fields = self.balance.invoice.fields()?;
+        fields.insert("reminder-date", self.date.to_string());
+        fields.insert("days-overdue", self.days_overdue.to_string());
+        fields.insert("paid-amount", self.balance.paid.to_string());
+        fields.insert("credited-amount", self.balance.credited.to_string());
+        fields.insert("open-amount", self.balance.open.to_string());
+
+        fields.insert("reminder-title", self.level.title().to_string());
+
+        let letter = self.level.letter();
+        let document = substitute_template("reminder", Some(letter), stylesheet, &fields)?;
+        set_title(&document, self.level.title());
+        Ok(document)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use invoice::tests::invoice;
+    use invoice::{Currency, Kind};
+    use outstanding::Balance;
+    use reminders::Level;
+
+    /// The cells of the table rows matching `selector`, with their
+    /// whitespace collapsed.
+    fn rows(document: &NodeRef, selector: &str) -> Vec<Vec<String>> {
+        document
+            .select(selector)
+            .unwrap()
+            .map(|row| {
+                let cells = row.as_node().select("td").unwrap();
+                cells
+                    .map(|cell| {
+                        let text = cell.text_contents();
+                        text.split_whitespace().collect::<Vec<_>>().join(" ")
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// The whitespace-collapsed text of the elements matching `selector`.
+    fn texts(document: &NodeRef, selector: &str) -> Vec<String> {
+        document
+            .select(selector)
+            .unwrap()
+            .map(|element| {
+                let text = element.text_contents();
+                text.split_whitespace().collect::<Vec<_>>().join(" ")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn kinds() {
+        let document = invoice(&[(10000, "21")], None).generate_invoice(None, None).unwrap();
+        assert_eq!(texts(&document, "title, h1"), ["Invoice", "Invoice"]);
+        assert_eq!(
+            texts(&document, ".metadata li"),
+            ["Invoice number: 2018-01", "Date: 2018-01-31", "Due date: 2018-03-02 (net 30 days)"]
+        );
+        assert_eq!(texts(&document, "#services, #balance").len(), 1);
+        assert!(texts(&document, "p").contains(&"Payable to:".to_string()));
+
+        let mut credit_note = invoice(&[(10000, "21")], None);
+        credit_note.index.kind = Kind::CreditNote;
+        credit_note.metadata.credits = Some("2018-01".parse().unwrap());
+        let document = credit_note.generate_invoice(None, None).unwrap();
+        assert_eq!(texts(&document, "title, h1"), ["Credit note", "Credit note"]);
+        assert_eq!(
+            texts(&document, ".metadata li"),
+            ["Credit note number: 2018-CN01", "Date: 2018-01-31", "Credits invoice: 2018-01"]
+        );
+        assert_eq!(texts(&document, "#services, #balance").len(), 1);
+        assert_eq!(texts(&document, "p"), ["", "The amounts above are credited to your account."]);
+        assert!(document.select("[data-kind]").unwrap().all(|element| {
+            element.attributes.borrow().get("data-kind").unwrap().contains("credit-note")
+        }));
+    }
+
+    #[test]
+    fn reminder_levels() {
+        let letters = [
+            (Level::First, "Payment reminder", "Our records show that invoice 2018-01 was due"),
+            (Level::Second, "Second payment reminder", "Despite our earlier reminder, invoice"),
+            (Level::Final, "Final notice", "Invoice 2018-01, due on 2018-03-02, is now 40"),
+        ];
+        for &(level, title, opening) in &letters {
+            let reminder = Reminder {
+                level,
+                date: "2018-04-11".parse().unwrap(),
+                days_overdue: 40,
+                balance: Balance {
+                    invoice: invoice(&[(10000, "21")], None),
+                    paid: Money::new(2100, Currency::Eur),
+                    credited: Money::new(0, Currency::Eur),
+                    open: Money::new(10000, Currency::Eur),
+                },
+            };
+            let document = reminder.generate_reminder(None).unwrap();
+            assert_eq!(texts(&document, "title, h1"), [title, title]);
+            assert_eq!(texts(&document, "#services").len(), 0);
+            assert_eq!(rows(&document, "#balance tr").last().unwrap(), &["Open", "€100.00"]);
+            let letter = texts(&document, ".letter p");
+            assert_eq!(letter.len(), 2, "{}", title);
+            assert!(letter[0].starts_with(opening), "{}", letter[0]);
+            assert!(letter[0].contains("open amount of €100.00"), "{}", letter[0]);
+        }
+    }
+
+    #[test]
+    fn attachment_links() {
+        let mut invoice = invoice(&[(10000, "21"), (5000, "21")], None);
+        invoice.items[0].attachment = Some("receipts/taxi #1.pdf".to_string());
+        let links = |document: &NodeRef| -> Vec<(String, String)> {
+            document
+                .select("#services a.attachment")
+                .unwrap()
+                .map(|link| {
+                    let href = link.attributes.borrow().get("href").unwrap().to_string();
+                    (href, link.text_contents())
+                })
+                .collect()
         };
 
-        Ok(substitute_template(
-            include_bytes!("../etc/template.html"),
-            &substitutions,
-            &self.items,
-        )?)
+        let document = invoice.generate_invoice(None, None).unwrap();
+        let text = "receipts/taxi #1.pdf".to_string();
+        assert_eq!(links(&document), [("receipts/taxi%20%231.pdf".to_string(), text.clone())]);
+        let descriptions: Vec<_> =
+            rows(&document, ".items tr").into_iter().map(|row| row[0].clone()).collect();
+        assert_eq!(descriptions[0], format!("{} {}", invoice.items[0].description, text));
+        assert_eq!(descriptions[1], invoice.items[1].description);
+
+        let document = invoice.generate_invoice(None, Some("../data/2018")).unwrap();
+        assert_eq!(links(&document), [("../data/2018/receipts/taxi%20%231.pdf".to_string(), text)]);
+    }
+
+    #[test]
+    fn discount_rows() {
+        let discount = Discount::Fixed(Money::new(1400, Currency::Eur));
+        let mut invoice = invoice(&[(10000, "21"), (5000, "6")], Some(discount));
+        invoice.items[0].discount = Some(Discount::Percentage(1000));
+        let document = invoice.generate_invoice(None, None).unwrap();
+        assert_eq!(
+            rows(&document, ".items tr"),
+            [
+                vec!["Work", "", "", "21%", "€100.00"],
+                vec!["Discount 10%", "−€10.00"],
+                vec!["Work", "", "", "6%", "€50.00"],
+            ]
+        );
+        assert_eq!(
+            rows(&document, "tfoot tr"),
+            [
+                ["Discount", "−€14.00"],
+                ["Total excl. VAT", "€126.00"],
+                ["VAT 6% on €45.00", "€2.70"],
+                ["VAT 21% on €81.00", "€17.01"],
+                ["Total", "€145.71"],
+            ]
+        );
+    }
+
+    #[test]
+    fn vat_rows() {
+        let invoice = invoice(&[(10000, "21"), (5000, "6"), (2550, "21")], None);
+        let document = invoice.generate_invoice(None, None).unwrap();
+        assert_eq!(
+            rows(&document, "tfoot tr"),
+            [
+                ["Total excl. VAT", "€175.50"],
+                ["VAT 6% on €50.00", "€3.00"],
+                ["VAT 21% on €125.50", "€26.36"],
+                ["Total", "€204.86"],
+            ]
+        );
+        let rates = rows(&document, ".items tr").into_iter().map(|row| row[3].clone());
+        assert_eq!(rates.collect::<Vec<_>>(), ["21%", "6%", "21%"]);
     }
 }
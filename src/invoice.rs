@@ -1,34 +1,116 @@
 use chrono::Datelike;
+use config::Policy;
+use serde::de::{self, Deserialize, Deserializer};
+use std::error::Error;
 use std::fmt;
-use std::iter::Sum;
+use std::ops::Neg;
+use std::str::FromStr;
 
 #[derive(Debug)]
 pub struct Invoice {
     pub index: InvoiceIndex,
     pub metadata: InvoiceData,
     pub items: Vec<LineItem>,
+    /// The payments received for the invoice, in the order they were
+    /// recorded.
+    pub payments: Vec<Payment>,
+    /// The credit notes that cancel part of the invoice, with their totals.
+    pub credit_notes: Vec<(InvoiceIndex, Money)>,
 }
 
-#[derive(Debug)]
+/// The kinds of documents, each numbered in its own series per year.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Kind {
+    Invoice,
+    /// A document that cancels all or part of an invoice.
+    CreditNote,
+}
+
+impl Kind {
+    pub const ALL: [Kind; 2] = [Kind::Invoice, Kind::CreditNote];
+
+    /// What is written before the index in document numbers.
+    fn prefix(&self) -> &'static str {
+        match *self {
+            Kind::Invoice => "",
+            Kind::CreditNote => "CN",
+        }
+    }
+
+    /// The `data-kind` of the parts of the template for this kind.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Kind::Invoice => "invoice",
+            Kind::CreditNote => "credit-note",
+        }
+    }
+
+    /// The heading of the document.
+    pub fn title(&self) -> &'static str {
+        match *self {
+            Kind::Invoice => "Invoice",
+            Kind::CreditNote => "Credit note",
+        }
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Kind::Invoice => "invoice",
+            Kind::CreditNote => "credit note",
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct InvoiceIndex {
+    pub kind: Kind,
     pub year: u16,
     pub index: u8,
 }
 
 impl InvoiceIndex {
     pub fn filename(&self) -> String {
-        format!("{:04}/{:02}.html", self.year, self.index)
+        format!("{:04}/{}{:02}.html", self.year, self.kind.prefix(), self.index)
     }
 }
 
 impl fmt::Display for InvoiceIndex {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:04}-{:02}", self.year, self.index)
+        write!(f, "{:04}-{}{:02}", self.year, self.kind.prefix(), self.index)
     }
 }
 
+impl FromStr for InvoiceIndex {
+    type Err = InvalidNumber;
 
-#[derive(Copy, Clone, Debug)]
+    /// Parses a document number such as `2018-03`, or `2018-CN03` for a
+    /// credit note.
+    fn from_str(s: &str) -> Result<Self, InvalidNumber> {
+        let invalid = || InvalidNumber("invoice number");
+        let (year, index) = s.trim().split_once('-').ok_or_else(invalid)?;
+        let (kind, index) = match index.strip_prefix(Kind::CreditNote.prefix()) {
+            Some(index) => (Kind::CreditNote, index),
+            None => (Kind::Invoice, index),
+        };
+        Ok(Self {
+            kind,
+            year: year.parse().map_err(|_| invalid())?,
+            index: index.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for InvoiceIndex {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Date(chrono::NaiveDate);
 
 impl Date {
@@ -36,12 +118,46 @@ impl Date {
         chrono::NaiveDate::from_ymd_opt(year.into(), month.into(), day.into()).map(Self)
     }
 
+    pub fn today() -> Self {
+        Self(chrono::Local::now().date_naive())
+    }
+
+    pub fn year(&self) -> u16 {
+        self.0.year() as u16
+    }
+
+    pub fn month(&self) -> u8 {
+        self.0.month() as u8
+    }
+
+    pub fn day(&self) -> u8 {
+        self.0.day() as u8
+    }
+
     pub fn add_days(&self, days: i64) -> Self {
         Self(self.0 + chrono::Duration::days(days))
     }
 
+    /// The same day `months` later, or the last day of that month if it is
+    /// shorter.
+    pub fn add_months(&self, months: u32) -> Option<Self> {
+        self.0.checked_add_months(chrono::Months::new(months)).map(Self)
+    }
+
+    /// The last day of the same month.
+    pub fn end_of_month(&self) -> Self {
+        let first = self.0.with_day(1).expect("every month has a first day");
+        Self(first + chrono::Months::new(1) - chrono::Duration::days(1))
+    }
+
+    /// The number of days from `earlier` to this date; negative if
+    /// `earlier` is later.
+    pub fn days_since(&self, earlier: Date) -> i64 {
+        (self.0 - earlier.0).num_days()
+    }
+
     pub fn month_name(&self) -> &'static str {
-        static MONTHS: [&'static str; 12] = [
+        static MONTHS: [&str; 12] = [
             "January",
             "February",
             "March",
@@ -55,7 +171,7 @@ impl Date {
             "November",
             "December",
         ];
-        &MONTHS[self.0.month0() as usize]
+        MONTHS[self.0.month0() as usize]
     }
 }
 
@@ -65,6 +181,102 @@ impl fmt::Display for Date {
     }
 }
 
+impl FromStr for Date {
+    type Err = InvalidNumber;
+
+    /// Parses a date such as `2018-03-31`.
+    fn from_str(s: &str) -> Result<Self, InvalidNumber> {
+        chrono::NaiveDate::parse_from_str(s.trim(), "%Y-%m-%d")
+            .map(Self)
+            .map_err(|_| InvalidNumber("date"))
+    }
+}
+
+/// When an invoice must be paid.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PaymentTerms {
+    /// Within a number of days of the invoice date.
+    Net(i64),
+    /// By the end of the month that a number of days after the invoice date
+    /// falls in; by the end of the invoice's own month for zero days.
+    EndOfMonth(i64),
+    /// On the invoice date.
+    OnReceipt,
+}
+
+impl PaymentTerms {
+    /// The date by which an invoice dated `date` must be paid.
+    pub fn due_date(&self, date: Date) -> Date {
+        match *self {
+            PaymentTerms::Net(days) => date.add_days(days),
+            PaymentTerms::EndOfMonth(days) => date.add_days(days).end_of_month(),
+            PaymentTerms::OnReceipt => date,
+        }
+    }
+}
+
+impl fmt::Display for PaymentTerms {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PaymentTerms::Net(days) => write!(f, "net {} days", days),
+            PaymentTerms::EndOfMonth(0) => f.write_str("end of month"),
+            PaymentTerms::EndOfMonth(days) => write!(f, "{} days end of month", days),
+            PaymentTerms::OnReceipt => f.write_str("on receipt"),
+        }
+    }
+}
+
+impl FromStr for PaymentTerms {
+    type Err = InvalidNumber;
+
+    /// Parses terms such as `net 30`, `30 days`, `end of month`,
+    /// `30 days end of month` or `on receipt`.
+    fn from_str(s: &str) -> Result<Self, InvalidNumber> {
+        let invalid = || InvalidNumber("payment terms");
+        let s = s.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase();
+        if s == "on receipt" {
+            return Ok(PaymentTerms::OnReceipt);
+        }
+        let (days, end_of_month) = match s.strip_suffix("end of month") {
+            Some(days) => (days.trim(), true),
+            None => (&*s, false),
+        };
+        let days = days.strip_prefix("net").unwrap_or(days).trim();
+        let days = days.strip_suffix("days").unwrap_or(days).trim();
+        let days = match (days, end_of_month) {
+            ("", true) => 0,
+            _ => days.parse().ok().filter(|&days| days >= 0).ok_or_else(invalid)?,
+        };
+        Ok(if end_of_month {
+            PaymentTerms::EndOfMonth(days)
+        } else {
+            PaymentTerms::Net(days)
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for PaymentTerms {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+/// The optional parts of an address record; empty if not given.
+#[derive(Clone, Debug, Default)]
+pub struct ContactDetails {
+    pub postal_code: String,
+    pub address_line2: String,
+    /// The state, province or county.
+    pub region: String,
+    pub contact_name: String,
+    pub email: String,
+    pub phone: String,
+    /// The company registration number, such as a KBO or Companies House
+    /// number.
+    pub registration_number: String,
+}
+
 #[derive(Clone, Debug)]
 pub struct Business {
     pub name: String,
@@ -75,10 +287,13 @@ pub struct Business {
     pub bank: String,
     pub iban: String,
     pub bic: String,
+    pub details: ContactDetails,
 }
 
 #[derive(Clone, Debug)]
 pub struct Client {
+    /// The key of the client in the data source, such as its `id` column.
+    pub id: String,
     pub name: String,
     pub street: String,
     pub city: String,
@@ -86,6 +301,9 @@ pub struct Client {
     pub vat: String,
     pub vatpolicy: String,
     pub partyid: u32,
+    /// The currency the client is billed in, if not the configured default.
+    pub currency: Option<Currency>,
+    pub details: ContactDetails,
 }
 
 #[derive(Clone, Debug)]
@@ -93,43 +311,1146 @@ pub struct InvoiceData {
     pub business: Business,
     pub client: Client,
     pub date: Date,
+    pub currency: Currency,
+    pub policy: Policy,
+    /// The invoice a credit note cancels; `None` for invoices.
+    pub credits: Option<InvoiceIndex>,
+    /// The discount on the total of the line items.
+    pub discount: Option<Discount<Money>>,
 }
 
-#[derive(Copy, Clone, Debug)]
-pub struct Money(pub i32);
+/// The currencies invoices can be issued in.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Currency {
+    Aud,
+    Cad,
+    Chf,
+    Czk,
+    Dkk,
+    #[default]
+    Eur,
+    Gbp,
+    Jpy,
+    Nok,
+    Pln,
+    Sek,
+    Usd,
+}
+
+impl Currency {
+    pub const ALL: [Currency; 12] = [
+        Currency::Aud,
+        Currency::Cad,
+        Currency::Chf,
+        Currency::Czk,
+        Currency::Dkk,
+        Currency::Eur,
+        Currency::Gbp,
+        Currency::Jpy,
+        Currency::Nok,
+        Currency::Pln,
+        Currency::Sek,
+        Currency::Usd,
+    ];
+
+    /// The ISO 4217 code.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            Currency::Aud => "AUD",
+            Currency::Cad => "CAD",
+            Currency::Chf => "CHF",
+            Currency::Czk => "CZK",
+            Currency::Dkk => "DKK",
+            Currency::Eur => "EUR",
+            Currency::Gbp => "GBP",
+            Currency::Jpy => "JPY",
+            Currency::Nok => "NOK",
+            Currency::Pln => "PLN",
+            Currency::Sek => "SEK",
+            Currency::Usd => "USD",
+        }
+    }
+
+    /// The symbol written before amounts, if the currency has a common one.
+    pub fn symbol(&self) -> Option<&'static str> {
+        match *self {
+            Currency::Eur => Some("\u{20ac}"),
+            Currency::Gbp => Some("\u{a3}"),
+            Currency::Jpy => Some("\u{a5}"),
+            Currency::Usd => Some("$"),
+            _ => None,
+        }
+    }
+
+    /// The number of digits after the decimal point.
+    pub fn minor_digits(&self) -> u32 {
+        match *self {
+            Currency::Jpy => 0,
+            _ => 2,
+        }
+    }
+
+    fn minor_units(&self) -> i64 {
+        10_i64.pow(self.minor_digits())
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+impl FromStr for Currency {
+    type Err = InvalidNumber;
+
+    fn from_str(s: &str) -> Result<Self, InvalidNumber> {
+        Currency::ALL
+            .iter()
+            .cloned()
+            .find(|currency| currency.code().eq_ignore_ascii_case(s.trim()))
+            .ok_or(InvalidNumber("currency"))
+    }
+}
 
-impl Sum for Money {
-    fn sum<I: Iterator<Item = Self>>(iterator: I) -> Self {
-        Money(iterator.map(|Money(m)| m).sum())
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
     }
 }
 
+/// An attempt to add amounts in different currencies.
+#[derive(Debug)]
+pub struct CurrencyMismatch {
+    pub expected: Currency,
+    pub found: Currency,
+}
+
+impl fmt::Display for CurrencyMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cannot add an amount in {} to one in {}", self.found, self.expected)
+    }
+}
+
+impl Error for CurrencyMismatch {}
+
+/// An amount of money, in the minor unit (such as cents) of its currency.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Money {
+    pub amount: i64,
+    pub currency: Currency,
+}
+
+impl Money {
+    pub fn new(amount: i64, currency: Currency) -> Self {
+        Self {
+            amount,
+            currency,
+        }
+    }
+
+    pub fn zero(currency: Currency) -> Self {
+        Self::new(0, currency)
+    }
+
+    pub fn float(&self) -> f64 {
+        self.amount as f64 / self.currency.minor_units() as f64
+    }
+
+    pub fn checked_add(self, other: Money) -> Result<Money, CurrencyMismatch> {
+        if self.currency != other.currency {
+            return Err(CurrencyMismatch {
+                expected: self.currency,
+                found: other.currency,
+            });
+        }
+        Ok(Money::new(self.amount + other.amount, self.currency))
+    }
+
+    /// Adds up `amounts`, all of which must be in `currency`.
+    pub fn sum<I>(currency: Currency, amounts: I) -> Result<Money, CurrencyMismatch>
+    where
+        I: IntoIterator<Item = Money>,
+    {
+        amounts.into_iter().try_fold(Money::zero(currency), Money::checked_add)
+    }
+
+    /// The amount as a plain decimal number, such as `-1234.56`.
+    pub fn decimal(&self) -> String {
+        let sign = if self.amount < 0 {
+            "-"
+        } else {
+            ""
+        };
+        let units = self.currency.minor_units();
+        let whole = (self.amount / units).abs();
+        let digits = self.currency.minor_digits() as usize;
+        if digits == 0 {
+            return format!("{}{}", sign, whole);
+        }
+        format!("{}{}.{:0width$}", sign, whole, (self.amount % units).abs(), width = digits)
+    }
+}
+
+impl Neg for Money {
+    type Output = Money;
+
+    fn neg(self) -> Money {
+        Money::new(-self.amount, self.currency)
+    }
+}
+
+/// Writes the amount after its currency symbol, or its code and a space, with
+/// a minus sign rather than a hyphen, such as `−€1234.56`.
 impl fmt::Display for Money {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let sign = if self.0 < 0 {
-            "\u{2212}"
+        let decimal = self.decimal();
+        let (sign, digits) = match decimal.strip_prefix('-') {
+            Some(digits) => ("\u{2212}", digits),
+            None => ("", &*decimal),
+        };
+        match self.currency.symbol() {
+            Some(symbol) => write!(f, "{}{}{}", sign, symbol, digits),
+            None => write!(f, "{}{}\u{a0}{}", sign, self.currency.code(), digits),
+        }
+    }
+}
+
+/// Why a value in `data.csv` is not a valid amount.
+#[derive(Debug)]
+pub struct InvalidAmount(String);
+
+impl fmt::Display for InvalidAmount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for InvalidAmount {}
+
+/// An exact decimal amount as written by hand, such as `1234.56`,
+/// `1.234,56` or `€ 1 234,56`, before the currency of its invoice is known.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Amount {
+    /// The amount in units of `10^-places`.
+    value: i64,
+    places: u32,
+    /// The currency written next to the amount, if any.
+    currency: Option<Currency>,
+}
+
+impl Amount {
+    /// The most decimals an amount may have, far more than any currency, so
+    /// that scaling by a power of ten cannot overflow.
+    const MAX_PLACES: usize = 9;
+
+    /// An amount of `cents` hundredths, as the `amount` column of `data.csv`
+    /// used to give it.
+    pub fn cents(cents: i64) -> Self {
+        Self {
+            value: cents,
+            places: 2,
+            currency: None,
+        }
+    }
+
+    /// Parses `s`, whose decimal separator is `separator` (`.` or `,`).
+    ///
+    /// Digits before the decimal separator may be grouped by thousands with
+    /// spaces, apostrophes or the other one of `.` and `,`. A single group
+    /// separator without decimals, as in `1,234`, could just as well be a
+    /// mistyped decimal separator, so it is rejected.
+    pub fn parse(s: &str, separator: char) -> Result<Self, InvalidAmount> {
+        let invalid = |reason: &str| InvalidAmount(format!("invalid amount `{}`: {}", s, reason));
+
+        let first = s.find(|c: char| c.is_ascii_digit()).ok_or_else(|| invalid("no digits"))?;
+        let last = s.rfind(|c: char| c.is_ascii_digit()).expect("found a first digit");
+        let (prefix, number, suffix) = (&s[..first], &s[first..=last], &s[last + 1..]);
+
+        let is_sign = |c: char| c == '-' || c == '\u{2212}';
+        let negative = match prefix.matches(is_sign).count() {
+            0 => false,
+            1 => true,
+            _ => return Err(invalid("more than one sign")),
+        };
+        let prefix = prefix.replace(|c: char| is_sign(c) || c.is_whitespace(), "");
+        let suffix = suffix.trim();
+        let marker = match (&*prefix, suffix) {
+            ("", "") => None,
+            (marker, "") | ("", marker) => Some(marker),
+            _ => return Err(invalid("currency on both sides")),
+        };
+        let currency = match marker {
+            Some(marker) => Some(
+                Currency::ALL
+                    .iter()
+                    .cloned()
+                    .find(|currency| {
+                        currency.symbol() == Some(marker)
+                            || currency.code().eq_ignore_ascii_case(marker)
+                    })
+                    .ok_or_else(|| invalid("unknown currency"))?,
+            ),
+            None => None,
+        };
+
+        let mut parts = number.split(separator);
+        let whole = parts.next().expect("split yields at least one part");
+        let fraction = parts.next();
+        if parts.next().is_some() {
+            return Err(invalid("more than one decimal separator"));
+        }
+        let fraction = fraction.unwrap_or("");
+        let wrong_separator = || {
+            let message = format!("the decimal separator is `{}`", separator);
+            invalid(&message)
+        };
+        if !fraction.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(wrong_separator());
+        }
+        if fraction.len() > Self::MAX_PLACES {
+            return Err(invalid("too many decimals"));
+        }
+
+        let groups = whole.split(|c: char| !c.is_ascii_digit()).collect::<Vec<_>>();
+        let mut grouping = whole.chars().filter(|c| !c.is_ascii_digit());
+        if let Some(group_separator) = grouping.next() {
+            let punctuation = group_separator == '.' || group_separator == ',';
+            let known = punctuation || group_separator == '\'' || group_separator.is_whitespace();
+            if !known || grouping.any(|c| c != group_separator) {
+                return Err(wrong_separator());
+            }
+            let thousands = (1..=3).contains(&groups[0].len())
+                && groups[1..].iter().all(|group| group.len() == 3);
+            if !thousands && punctuation {
+                return Err(wrong_separator());
+            }
+            if !thousands {
+                return Err(invalid("digits are not grouped by thousands"));
+            }
+            if punctuation && groups.len() == 2 && fraction.is_empty() {
+                return Err(invalid("ambiguous decimal or thousands separator"));
+            }
+        }
+
+        let digits = groups.concat() + fraction;
+        let value = digits.parse::<i64>().map_err(|_| invalid("too large"))?;
+        Ok(Self {
+            value: if negative {
+                -value
+            } else {
+                value
+            },
+            places: fraction.len() as u32,
+            currency,
+        })
+    }
+
+    /// The amount as money in `currency`, which must match the currency
+    /// written next to the amount, if any.
+    pub fn in_currency(&self, currency: Currency) -> Result<Money, InvalidAmount> {
+        if let Some(written) = self.currency {
+            if written != currency {
+                let message = format!("amount in {} on an invoice in {}", written, currency);
+                return Err(InvalidAmount(message));
+            }
+        }
+
+        let digits = currency.minor_digits();
+        let amount = if self.places > digits {
+            let scale = 10_i64.pow(self.places - digits);
+            if self.value % scale != 0 {
+                let message =
+                    format!("more than {} decimals for an amount in {}", digits, currency);
+                return Err(InvalidAmount(message));
+            }
+            self.value / scale
+        } else {
+            let scale = 10_i64.pow(digits - self.places);
+            self.value
+                .checked_mul(scale)
+                .ok_or_else(|| InvalidAmount("amount too large".to_string()))?
+        };
+        Ok(Money::new(amount, currency))
+    }
+}
+
+impl<'de> Deserialize<'de> for Amount {
+    /// Accepts strings such as `"80.50"` or `"€ 80"`, with `.` as the
+    /// decimal separator, as well as numbers.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = Amount;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an amount")
+            }
+
+            fn visit_str<E: de::Error>(self, s: &str) -> Result<Amount, E> {
+                Amount::parse(s, '.').map_err(E::custom)
+            }
+
+            fn visit_u64<E: de::Error>(self, n: u64) -> Result<Amount, E> {
+                self.visit_str(&n.to_string())
+            }
+
+            fn visit_i64<E: de::Error>(self, n: i64) -> Result<Amount, E> {
+                self.visit_str(&n.to_string())
+            }
+
+            fn visit_f64<E: de::Error>(self, n: f64) -> Result<Amount, E> {
+                self.visit_str(&n.to_string())
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+/// Writes the amount with `.` as the decimal separator and without grouping,
+/// so that `Amount::parse(s, '.')` gives it back.
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.value < 0 {
+            "-"
         } else {
             ""
         };
-        write!(f, "{}\u{20ac}{}.{:02}", sign, (self.0 / 100).abs(), self.0 % 100)
+        let scale = 10_i64.pow(self.places);
+        write!(f, "{}{}", sign, (self.value / scale).abs())?;
+        if self.places > 0 {
+            let fraction = (self.value % scale).abs();
+            write!(f, ".{:0width$}", fraction, width = self.places as usize)?;
+        }
+        match self.currency {
+            Some(currency) => write!(f, " {}", currency.code()),
+            None => Ok(()),
+        }
     }
 }
 
-impl Money {
-    pub fn float(&self) -> f64 {
-        f64::from(self.0) / 100.0
+/// A reduction before VAT, either of a line item or of a whole invoice.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Discount<T> {
+    /// A percentage, exact to a hundredth of a percent.
+    Percentage(i64),
+    /// A fixed amount, as an `Amount` while stored and as `Money` once the
+    /// currency of the invoice is known.
+    Fixed(T),
+}
+
+impl<T> Discount<T> {
+    const PLACES: usize = 2;
+    const SCALE: i64 = 100;
+}
+
+impl Discount<Amount> {
+    /// Parses a percentage such as `10%` or an amount such as `25,00`, whose
+    /// decimal separator is `separator`.
+    pub fn parse(s: &str, separator: char) -> Result<Self, InvalidAmount> {
+        let invalid = |reason: &str| InvalidAmount(format!("invalid discount `{}`: {}", s, reason));
+
+        if let Some(percentage) = s.trim().strip_suffix('%') {
+            let percentage = percentage.trim().replace(separator, ".");
+            return match parse_fixed(&percentage, Self::PLACES) {
+                Some(value) if (0..=100 * Self::SCALE).contains(&value) => {
+                    Ok(Discount::Percentage(value))
+                },
+                _ => Err(invalid("not a percentage between 0 and 100")),
+            };
+        }
+        let amount = Amount::parse(s, separator)?;
+        if amount.value < 0 {
+            return Err(invalid("negative"));
+        }
+        Ok(Discount::Fixed(amount))
+    }
+
+    pub fn in_currency(&self, currency: Currency) -> Result<Discount<Money>, InvalidAmount> {
+        Ok(match *self {
+            Discount::Percentage(value) => Discount::Percentage(value),
+            Discount::Fixed(ref amount) => Discount::Fixed(amount.in_currency(currency)?),
+        })
+    }
+}
+
+impl Discount<Money> {
+    /// The amount taken off `amount`, rounded to the nearest cent.
+    pub fn of(&self, amount: Money) -> Money {
+        match *self {
+            Discount::Percentage(value) => {
+                Money::new(share(amount.amount, value, 100 * Self::SCALE), amount.currency)
+            },
+            Discount::Fixed(money) => money,
+        }
+    }
+}
+
+impl<T: fmt::Display> Discount<T> {
+    /// The description of the discount on invoices: `Discount 10%`, or just
+    /// `Discount` for a fixed amount, which is shown next to it anyway.
+    pub fn label(&self) -> String {
+        match *self {
+            Discount::Percentage(_) => format!("Discount {}", self),
+            Discount::Fixed(_) => "Discount".to_string(),
+        }
+    }
+}
+
+/// Writes percentages as `10%` and amounts as they are written on their own.
+impl<T: fmt::Display> fmt::Display for Discount<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Discount::Percentage(value) => {
+                format_fixed(f, value, Self::PLACES)?;
+                "%".fmt(f)
+            },
+            Discount::Fixed(ref amount) => amount.fmt(f),
+        }
     }
 }
 
 impl Invoice {
-    pub fn total(&self) -> Money {
-        self.items.iter().map(|item| item.amount).sum()
+    /// The VAT rate that applies to `item`.
+    pub fn vat_rate(&self, item: &LineItem) -> VatRate {
+        item.vat_rate.unwrap_or(self.metadata.policy.vat_rate)
+    }
+
+    /// The taxable amount and VAT for each distinct rate, by ascending rate.
+    pub fn vat_breakdown(&self) -> Result<Vec<VatAmount>, CurrencyMismatch> {
+        let currency = self.metadata.currency;
+        let mut breakdown: Vec<VatAmount> = vec![];
+        for item in &self.items {
+            let rate = self.vat_rate(item);
+            match breakdown.iter_mut().find(|amount| amount.rate == rate) {
+                Some(amount) => amount.base = amount.base.checked_add(item.net_amount())?,
+                None => breakdown.push(VatAmount {
+                    rate,
+                    base: Money::zero(currency).checked_add(item.net_amount())?,
+                    discount: Money::zero(currency),
+                    vat: Money::zero(currency),
+                }),
+            }
+        }
+        breakdown.sort_by_key(|amount| amount.rate);
+
+        // The invoice discount is spread over the rates in proportion to
+        // their bases; the highest rate takes what is left after rounding, so
+        // that the shares add up to the discount. Without any items, it is
+        // all taken off at the default rate.
+        let subtotal = self.subtotal()?;
+        let discount = self.discount()?;
+        if breakdown.is_empty() && discount.amount != 0 {
+            breakdown.push(VatAmount {
+                rate: self.metadata.policy.vat_rate,
+                base: Money::zero(currency),
+                discount: Money::zero(currency),
+                vat: Money::zero(currency),
+            });
+        }
+        let mut left = discount.amount;
+        let count = breakdown.len();
+        for (i, amount) in breakdown.iter_mut().enumerate() {
+            let share = if i + 1 == count {
+                left
+            } else if subtotal.amount == 0 {
+                0
+            } else {
+                div_round(discount.amount * amount.base.amount, subtotal.amount)
+            };
+            left -= share;
+            amount.discount = Money::new(share, currency);
+            amount.base = Money::new(amount.base.amount - share, currency);
+            amount.vat = amount.rate.of(amount.base);
+        }
+        Ok(breakdown)
+    }
+
+    /// The total of the line items after their own discounts, before the
+    /// invoice discount and VAT.
+    pub fn subtotal(&self) -> Result<Money, CurrencyMismatch> {
+        Money::sum(self.metadata.currency, self.items.iter().map(LineItem::net_amount))
+    }
+
+    /// The amount taken off the subtotal by the invoice discount, if any.
+    pub fn discount(&self) -> Result<Money, CurrencyMismatch> {
+        let currency = self.metadata.currency;
+        match self.metadata.discount {
+            Some(ref discount) => Money::zero(currency).checked_add(discount.of(self.subtotal()?)),
+            None => Ok(Money::zero(currency)),
+        }
+    }
+
+    /// The total after all discounts, excluding VAT.
+    pub fn total_excl_vat(&self) -> Result<Money, CurrencyMismatch> {
+        let subtotal = self.subtotal()?;
+        let discount = self.discount()?;
+        Ok(Money::new(subtotal.amount - discount.amount, subtotal.currency))
+    }
+
+    pub fn total_vat(&self) -> Result<Money, CurrencyMismatch> {
+        let breakdown = self.vat_breakdown()?;
+        Money::sum(self.metadata.currency, breakdown.into_iter().map(|amount| amount.vat))
+    }
+
+    /// The amount due, including VAT.
+    pub fn total(&self) -> Result<Money, CurrencyMismatch> {
+        self.total_excl_vat()?.checked_add(self.total_vat()?)
+    }
+
+    /// The total of the payments received.
+    pub fn paid(&self) -> Result<Money, CurrencyMismatch> {
+        Money::sum(self.metadata.currency, self.payments.iter().map(|payment| payment.amount))
+    }
+
+    /// The date by which the invoice must be paid.
+    pub fn due_date(&self) -> Date {
+        self.metadata.policy.payment_terms.due_date(self.metadata.date)
+    }
+
+    /// The total of the credit notes that cancel part of the invoice.
+    pub fn credited(&self) -> Result<Money, CurrencyMismatch> {
+        Money::sum(self.metadata.currency, self.credit_notes.iter().map(|&(_, total)| total))
+    }
+
+    /// What is left to pay, counting credit notes as payments; negative if
+    /// too much was paid.
+    pub fn balance(&self) -> Result<Money, CurrencyMismatch> {
+        self.total()?.checked_add(-self.paid()?)?.checked_add(-self.credited()?)
+    }
+
+    pub fn status(&self) -> Result<PaymentStatus, CurrencyMismatch> {
+        let balance = self.balance()?;
+        Ok(if balance.amount < 0 {
+            PaymentStatus::Overpaid
+        } else if balance.amount == 0 {
+            PaymentStatus::Paid
+        } else if self.payments.is_empty() && self.credit_notes.is_empty() {
+            PaymentStatus::Unpaid
+        } else {
+            PaymentStatus::Partial
+        })
+    }
+}
+
+/// A payment received for an invoice.
+#[derive(Clone, Debug)]
+pub struct Payment {
+    pub date: Date,
+    pub amount: Money,
+    /// How the payment was made, such as "bank transfer"; may be empty.
+    pub method: String,
+}
+
+/// How much of an invoice has been paid.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PaymentStatus {
+    Unpaid,
+    Partial,
+    Paid,
+    Overpaid,
+}
+
+impl PaymentStatus {
+    /// Whether nothing is left to pay.
+    pub fn is_settled(&self) -> bool {
+        match *self {
+            PaymentStatus::Unpaid | PaymentStatus::Partial => false,
+            PaymentStatus::Paid | PaymentStatus::Overpaid => true,
+        }
+    }
+}
+
+impl fmt::Display for PaymentStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            PaymentStatus::Unpaid => "unpaid",
+            PaymentStatus::Partial => "partial",
+            PaymentStatus::Paid => "paid",
+            PaymentStatus::Overpaid => "overpaid",
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidNumber(pub &'static str);
+
+impl fmt::Display for InvalidNumber {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid {}", self.0)
+    }
+}
+
+impl Error for InvalidNumber {}
+
+/// Parses a decimal number such as `-42.5` into an integer number of units
+/// of `10^-places`. Returns `None` if `s` has more than `places` decimals.
+fn parse_fixed(s: &str, places: usize) -> Option<i64> {
+    let (negative, s) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s),
+    };
+    let (whole, fraction) = match s.find('.') {
+        Some(i) => (&s[..i], &s[i + 1..]),
+        None => (s, ""),
+    };
+    let digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+    if whole.is_empty() || !digits(whole) || !digits(fraction) || fraction.len() > places {
+        return None;
+    }
+
+    let whole = whole.parse::<i64>().ok()?;
+    let fraction = format!("{:0<width$}", fraction, width = places).parse::<i64>().ok()?;
+    let value = whole.checked_mul(10_i64.pow(places as u32))?.checked_add(fraction)?;
+    Some(if negative {
+        -value
+    } else {
+        value
+    })
+}
+
+/// Writes `value` units of `10^-places` without trailing zeros.
+fn format_fixed(f: &mut fmt::Formatter, value: i64, places: usize) -> fmt::Result {
+    let sign = if value < 0 {
+        "-"
+    } else {
+        ""
+    };
+    let scale = 10_i64.pow(places as u32);
+    let whole = (value / scale).abs();
+    let fraction = (value % scale).abs();
+    if fraction == 0 {
+        return write!(f, "{}{}", sign, whole);
+    }
+    let fraction = format!("{:0width$}", fraction, width = places);
+    write!(f, "{}{}.{}", sign, whole, fraction.trim_end_matches('0'))
+}
+
+/// The part `value / scale` of `amount`, rounded half away from zero.
+/// `value` must be between zero and `scale`, so that the part is at most
+/// `amount`; if the product overflows, `amount` is split into a multiple of
+/// `scale` and a remainder.
+fn share(amount: i64, value: i64, scale: i64) -> i64 {
+    match amount.checked_mul(value) {
+        Some(product) => div_round(product, scale),
+        None => amount / scale * value + div_round(amount % scale * value, scale),
+    }
+}
+
+/// Divides `value` by `divisor`, rounding half away from zero.
+fn div_round(value: i64, divisor: i64) -> i64 {
+    let (value, divisor) = if divisor < 0 {
+        (-value, -divisor)
+    } else {
+        (value, divisor)
+    };
+    (value + divisor / 2 * value.signum()) / divisor
+}
+
+/// A number of units, such as hours or days, exact to a thousandth.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Quantity(pub i64);
+
+impl Quantity {
+    const PLACES: usize = 3;
+    const SCALE: i64 = 1000;
+
+    pub fn float(&self) -> f64 {
+        self.0 as f64 / Self::SCALE as f64
+    }
+
+    /// The price of this many units at `unit_price`, rounded to the nearest
+    /// cent, or `None` if it is too large.
+    pub fn times(&self, unit_price: Money) -> Option<Money> {
+        let product = unit_price.amount.checked_mul(self.0)?;
+        Some(Money::new(div_round(product, Self::SCALE), unit_price.currency))
+    }
+}
+
+impl FromStr for Quantity {
+    type Err = InvalidNumber;
+
+    fn from_str(s: &str) -> Result<Self, InvalidNumber> {
+        parse_fixed(s, Self::PLACES).map(Quantity).ok_or(InvalidNumber("quantity"))
+    }
+}
+
+impl<'de> Deserialize<'de> for Quantity {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
     }
 }
 
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        format_fixed(f, self.0, Self::PLACES)
+    }
+}
+
+/// A VAT percentage, exact to a hundredth of a percent.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VatRate(pub i64);
+
+impl VatRate {
+    const PLACES: usize = 2;
+    const SCALE: i64 = 100;
+
+    pub fn percentage(&self) -> f64 {
+        self.0 as f64 / Self::SCALE as f64
+    }
+
+    /// The VAT due on `base`, rounded to the nearest cent.
+    pub fn of(&self, base: Money) -> Money {
+        Money::new(share(base.amount, self.0, 100 * Self::SCALE), base.currency)
+    }
+}
+
+impl FromStr for VatRate {
+    type Err = InvalidNumber;
+
+    fn from_str(s: &str) -> Result<Self, InvalidNumber> {
+        let s = s.trim_end_matches('%');
+        match parse_fixed(s, Self::PLACES) {
+            Some(rate) if (0..=100 * Self::SCALE).contains(&rate) => Ok(VatRate(rate)),
+            _ => Err(InvalidNumber("VAT rate")),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for VatRate {
+    /// Accepts strings such as `"21"` or `"5.5%"` as well as numbers, so
+    /// that rates can be written naturally in both CSV and TOML.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = VatRate;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a VAT percentage")
+            }
+
+            fn visit_str<E: de::Error>(self, s: &str) -> Result<VatRate, E> {
+                s.parse().map_err(E::custom)
+            }
+
+            fn visit_u64<E: de::Error>(self, n: u64) -> Result<VatRate, E> {
+                self.visit_str(&n.to_string())
+            }
+
+            fn visit_i64<E: de::Error>(self, n: i64) -> Result<VatRate, E> {
+                self.visit_str(&n.to_string())
+            }
+
+            fn visit_f64<E: de::Error>(self, n: f64) -> Result<VatRate, E> {
+                self.visit_str(&n.to_string())
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+impl fmt::Display for VatRate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        format_fixed(f, self.0, Self::PLACES)?;
+        "%".fmt(f)
+    }
+}
+
+/// The VAT due at a single rate.
+#[derive(Copy, Clone, Debug)]
+pub struct VatAmount {
+    pub rate: VatRate,
+    /// The total of the line items at this rate after all discounts,
+    /// excluding VAT.
+    pub base: Money,
+    /// The share of the invoice discount taken off at this rate.
+    pub discount: Money,
+    pub vat: Money,
+}
+
 #[derive(Clone, Debug)]
 pub struct LineItem {
     pub description: String,
+    /// The amount before the item's discount.
     pub amount: Money,
+    /// The number of units billed, if the item is billed per unit.
+    pub quantity: Option<Quantity>,
+    /// The unit of `quantity`, such as "h" or "days".
+    pub unit: Option<String>,
+    pub unit_price: Option<Money>,
+    /// The VAT rate, if it differs from the client's default.
+    pub vat_rate: Option<VatRate>,
+    pub discount: Option<Discount<Money>>,
     pub attachment: Option<String>,
 }
+
+impl LineItem {
+    /// The amount taken off by the item's discount, if any.
+    pub fn discount(&self) -> Money {
+        match self.discount {
+            Some(ref discount) => discount.of(self.amount),
+            None => Money::zero(self.amount.currency),
+        }
+    }
+
+    /// The amount after the item's discount, excluding VAT.
+    pub fn net_amount(&self) -> Money {
+        Money::new(self.amount.amount - self.discount().amount, self.amount.currency)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn eur(amount: i64) -> Money {
+        Money::new(amount, Currency::Eur)
+    }
+
+    /// An invoice with a line item of each amount in cents at its VAT rate.
+    pub fn invoice(items: &[(i64, &str)], discount: Option<Discount<Money>>) -> Invoice {
+        let items = items
+            .iter()
+            .map(|&(amount, rate)| LineItem {
+                description: "Work".to_string(),
+                amount: eur(amount),
+                quantity: None,
+                unit: None,
+                unit_price: None,
+                vat_rate: Some(rate.parse().unwrap()),
+                discount: None,
+                attachment: None,
+            })
+            .collect();
+        Invoice {
+            index: InvoiceIndex {
+                kind: Kind::Invoice,
+                year: 2018,
+                index: 1,
+            },
+            metadata: InvoiceData {
+                business: Business {
+                    name: "Me".to_string(),
+                    street: String::new(),
+                    city: String::new(),
+                    country: String::new(),
+                    vat: String::new(),
+                    bank: String::new(),
+                    iban: String::new(),
+                    bic: String::new(),
+                    details: ContactDetails::default(),
+                },
+                client: Client {
+                    id: "acme".to_string(),
+                    name: "Acme".to_string(),
+                    street: String::new(),
+                    city: String::new(),
+                    country: String::new(),
+                    vat: String::new(),
+                    vatpolicy: String::new(),
+                    partyid: 1,
+                    currency: None,
+                    details: ContactDetails::default(),
+                },
+                date: Date::new(2018, 1, 31).unwrap(),
+                currency: Currency::Eur,
+                policy: Policy::default(),
+                credits: None,
+                discount,
+            },
+            items,
+            payments: vec![],
+            credit_notes: vec![],
+        }
+    }
+
+    fn discount(s: &str) -> Discount<Money> {
+        Discount::parse(s, '.').unwrap().in_currency(Currency::Eur).unwrap()
+    }
+
+    #[test]
+    fn rounding_half_away_from_zero() {
+        assert_eq!(div_round(25, 10), 3);
+        assert_eq!(div_round(-25, 10), -3);
+        assert_eq!(div_round(24, 10), 2);
+        assert_eq!(div_round(-24, 10), -2);
+        assert_eq!(div_round(25, -10), -3);
+        assert_eq!(div_round(-25, -10), 3);
+        assert_eq!(div_round(0, -10), 0);
+    }
+
+    #[test]
+    fn percentage_and_fixed_discounts() {
+        assert_eq!(discount("10%"), Discount::Percentage(1000));
+        assert_eq!(discount("12.5 %"), Discount::Percentage(1250));
+        assert_eq!(discount("25"), Discount::Fixed(eur(2500)));
+        assert_eq!(discount("10%").of(eur(12345)), eur(1235));
+        assert_eq!(discount("10%").of(eur(-12345)), eur(-1235));
+        assert_eq!(discount("25").of(eur(12345)), eur(2500));
+        assert_eq!(discount("100%").of(eur(i64::MAX)), eur(i64::MAX));
+        assert!(Discount::parse("101%", '.').is_err());
+        assert!(Discount::parse("-5", '.').is_err());
+        assert!(Discount::parse("12,5%", '.').is_err());
+        assert_eq!(Discount::parse("12,5%", ',').unwrap(), Discount::Percentage(1250));
+    }
+
+    #[test]
+    fn discount_split_over_rates() {
+        let invoice = invoice(&[(10000, "6"), (10000, "21"), (10000, "0")], Some(discount("10")));
+        let breakdown = invoice.vat_breakdown().unwrap();
+        let shares = breakdown.iter().map(|amount| amount.discount.amount).collect::<Vec<_>>();
+        assert_eq!(shares, [333, 333, 334]);
+        let bases = breakdown.iter().map(|amount| amount.base.amount).collect::<Vec<_>>();
+        assert_eq!(bases, [9667, 9667, 9666]);
+        assert_eq!(invoice.total_excl_vat().unwrap(), eur(29000));
+        assert_eq!(invoice.total_vat().unwrap(), eur(580 + 2030));
+    }
+
+    #[test]
+    fn discount_with_negative_subtotal() {
+        let invoice = invoice(&[(-20000, "6"), (10000, "21")], Some(discount("10%")));
+        let breakdown = invoice.vat_breakdown().unwrap();
+        let shares = breakdown.iter().map(|amount| amount.discount.amount).collect::<Vec<_>>();
+        assert_eq!(shares, [-2000, 1000]);
+        assert_eq!(invoice.discount().unwrap(), eur(-1000));
+    }
+
+    #[test]
+    fn discount_without_items() {
+        let invoice = invoice(&[], Some(discount("10")));
+        let breakdown = invoice.vat_breakdown().unwrap();
+        assert_eq!(breakdown.len(), 1);
+        assert_eq!(breakdown[0].discount, eur(1000));
+        assert_eq!(invoice.total_excl_vat().unwrap(), eur(-1000));
+    }
+
+    #[test]
+    fn vat_breakdown_by_rate() {
+        let invoice = invoice(&[(10000, "21"), (5000, "6"), (2550, "21"), (1000, "0")], None);
+        let breakdown = invoice.vat_breakdown().unwrap();
+        let rates = breakdown
+            .iter()
+            .map(|amount| (amount.rate.to_string(), amount.base, amount.vat))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rates,
+            [
+                ("0%".to_string(), eur(1000), eur(0)),
+                ("6%".to_string(), eur(5000), eur(300)),
+                ("21%".to_string(), eur(12550), eur(2636)),
+            ]
+        );
+        assert_eq!(invoice.total_excl_vat().unwrap(), eur(18550));
+        assert_eq!(invoice.total_vat().unwrap(), eur(2936));
+        assert_eq!(invoice.total().unwrap(), eur(21486));
+    }
+
+    #[test]
+    fn currency_mismatch() {
+        let usd = Money::new(100, Currency::Usd);
+        let error = eur(100).checked_add(usd).unwrap_err();
+        assert_eq!(error.to_string(), "cannot add an amount in USD to one in EUR");
+        assert!(Money::sum(Currency::Eur, vec![eur(100), usd]).is_err());
+        assert_eq!(Money::sum(Currency::Usd, vec![usd, usd]).unwrap().amount, 200);
+
+        let error = Amount::parse("$80", '.').unwrap().in_currency(Currency::Eur).unwrap_err();
+        assert_eq!(error.to_string(), "amount in USD on an invoice in EUR");
+        let mut invoice = invoice(&[(100, "21")], None);
+        invoice.items[0].amount = usd;
+        assert!(invoice.total().is_err());
+    }
+
+    #[test]
+    fn quantities_and_rates() {
+        let quantity = "1.5".parse::<Quantity>().unwrap();
+        assert_eq!(quantity.times(eur(3333)), Some(eur(5000)));
+        assert_eq!(quantity.times(eur(i64::MAX / 1000)), None);
+        let rate = "21".parse::<VatRate>().unwrap();
+        assert_eq!(rate.of(eur(1050)), eur(221));
+        assert_eq!(rate.of(eur(-1050)), eur(-221));
+        assert_eq!("100".parse::<VatRate>().unwrap().of(eur(i64::MAX)), eur(i64::MAX));
+        assert!("100.01".parse::<VatRate>().is_err());
+        assert!("-1".parse::<VatRate>().is_err());
+    }
+
+    #[test]
+    fn payment_terms() {
+        assert_eq!("net 30".parse::<PaymentTerms>().unwrap(), PaymentTerms::Net(30));
+        assert_eq!("30 days".parse::<PaymentTerms>().unwrap(), PaymentTerms::Net(30));
+        assert_eq!(" Net  14 Days ".parse::<PaymentTerms>().unwrap(), PaymentTerms::Net(14));
+        assert_eq!("end of month".parse::<PaymentTerms>().unwrap(), PaymentTerms::EndOfMonth(0));
+        assert_eq!(
+            "30 days end of month".parse::<PaymentTerms>().unwrap(),
+            PaymentTerms::EndOfMonth(30)
+        );
+        assert_eq!("on receipt".parse::<PaymentTerms>().unwrap(), PaymentTerms::OnReceipt);
+        assert!("net".parse::<PaymentTerms>().is_err());
+        assert!("net -5".parse::<PaymentTerms>().is_err());
+        assert!("30 weeks".parse::<PaymentTerms>().is_err());
+    }
+
+    #[test]
+    fn due_dates() {
+        let date = Date::new(2018, 1, 31).unwrap();
+        assert_eq!(PaymentTerms::Net(30).due_date(date), Date::new(2018, 3, 2).unwrap());
+        assert_eq!(PaymentTerms::EndOfMonth(0).due_date(date), date);
+        assert_eq!(PaymentTerms::EndOfMonth(30).due_date(date), Date::new(2018, 3, 31).unwrap());
+        assert_eq!(PaymentTerms::OnReceipt.due_date(date), date);
+    }
+
+    fn euros(s: &str, separator: char) -> Result<Money, InvalidAmount> {
+        Amount::parse(s, separator)?.in_currency(Currency::Eur)
+    }
+
+    #[test]
+    fn money_formatting() {
+        assert_eq!(eur(-123456).decimal(), "-1234.56");
+        assert_eq!(eur(-123456).to_string(), "\u{2212}\u{20ac}1234.56");
+        assert_eq!(eur(5).to_string(), "\u{20ac}0.05");
+        assert_eq!(Money::new(-1500, Currency::Jpy).to_string(), "\u{2212}\u{a5}1500");
+        assert_eq!(Money::new(-5, Currency::Chf).to_string(), "\u{2212}CHF\u{a0}0.05");
+    }
+
+    #[test]
+    fn amount_with_grouping() {
+        assert_eq!(euros("1.234,56", ',').unwrap(), Money::new(123456, Currency::Eur));
+        assert_eq!(euros("1,234.56", '.').unwrap(), Money::new(123456, Currency::Eur));
+        assert_eq!(euros("€ 1 234,5", ',').unwrap(), Money::new(123450, Currency::Eur));
+        assert_eq!(euros("1'234'567", '.').unwrap(), Money::new(123456700, Currency::Eur));
+    }
+
+    #[test]
+    fn amount_with_ambiguous_separator() {
+        assert!(euros("1,234", ',').is_err());
+        assert!(Amount::parse("1.234", ',').is_err());
+        assert!(Amount::parse("1,234", '.').is_err());
+        assert_eq!(euros("1,23", ',').unwrap(), Money::new(123, Currency::Eur));
+    }
+
+    #[test]
+    fn amount_with_wrong_separator() {
+        assert!(Amount::parse("1.234.56", ',').is_err());
+        assert!(Amount::parse("12,34.5", '.').is_err());
+        assert!(Amount::parse("1,2,3", ',').is_err());
+    }
+
+    #[test]
+    fn amount_sign_and_currency() {
+        assert_eq!(euros("-80.50 EUR", '.').unwrap(), Money::new(-8050, Currency::Eur));
+        assert_eq!(euros("\u{2212}€80", '.').unwrap(), Money::new(-8000, Currency::Eur));
+        assert!(Amount::parse("--80", '.').is_err());
+        assert!(Amount::parse("€ 80 EUR", '.').is_err());
+        assert!(euros("£80", '.').is_err());
+        assert!(euros("80.505", '.').is_err());
+        assert_eq!(euros("80.500", '.').unwrap(), Money::new(8050, Currency::Eur));
+        assert_eq!(euros("0.000000000", '.').unwrap(), eur(0));
+        let error = Amount::parse("0.000000000000000000000", '.').unwrap_err().to_string();
+        assert_eq!(error, "invalid amount `0.000000000000000000000`: too many decimals");
+    }
+}
@@ -1,9 +1,11 @@
-use invoice::{Invoice, LineItem};
-use std::error::Error;
+use error::{Error, Result};
+use invoice::{Invoice, Kind, LineItem, Money, VatRate};
+use std::ffi::OsStr;
 use std::fs::File;
-use std::io::Read;
-use std::path::Path;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
 
+/// A file sent along with the order.
 #[derive(Serialize, Deserialize)]
 #[allow(non_snake_case)]
 struct OrderPDF {
@@ -12,13 +14,18 @@ struct OrderPDF {
 }
 
 impl OrderPDF {
-    fn from(path: &Path) -> Result<Self, Box<dyn Error>> {
-        let mut file = File::open(path)?;
+    fn from(path: &Path) -> Result<Self> {
+        let name = path.file_name().and_then(OsStr::to_str).ok_or_else(|| {
+            let error = io::Error::new(io::ErrorKind::InvalidData, "file name is not valid UTF-8");
+            Error::io(path, error)
+        })?;
         let mut content = vec![];
-        file.read_to_end(&mut content)?;
+        File::open(path)
+            .and_then(|mut file| file.read_to_end(&mut content))
+            .map_err(|error| Error::io(path, error))?;
         let content = base64::encode(content);
         Ok(Self {
-            FileName: path.file_name().expect("filename").to_str().expect("filename unicode").to_string(),
+            FileName: name.to_string(),
             FileContent: content,
         })
     }
@@ -34,13 +41,54 @@ struct OrderLine {
 }
 
 impl OrderLine {
-    fn from(item: &LineItem) -> Self {
+    fn from(invoice: &Invoice, item: &LineItem) -> Self {
+        let quantity = item.quantity.map_or(1., |quantity| quantity.float());
+        let unit_price = match item.unit_price {
+            Some(unit_price) => unit_price.float(),
+            None => item.amount.float() / quantity,
+        };
         Self {
             Description: item.description.clone(),
+            Quantity: quantity,
+            UnitPriceExcl: unit_price,
+            VATPercentage: invoice.vat_rate(item).percentage(),
+        }
+    }
+
+    /// A line taking `amount` off at `rate`.
+    fn discount(description: String, amount: Money, rate: VatRate) -> Self {
+        Self {
+            Description: description,
             Quantity: 1.,
-            UnitPriceExcl: item.amount.float(),
-            VATPercentage: 0.,
+            UnitPriceExcl: -amount.float(),
+            VATPercentage: rate.percentage(),
+        }
+    }
+
+    /// The lines of `invoice`. Discounts become lines of their own, right
+    /// after the item they apply to, or at the end, split by VAT rate, for
+    /// the invoice discount.
+    fn all(invoice: &Invoice) -> Result<Vec<Self>> {
+        let mut lines = vec![];
+        for item in &invoice.items {
+            lines.push(OrderLine::from(invoice, item));
+            if let Some(ref discount) = item.discount {
+                let description = format!("{}: {}", discount.label(), item.description);
+                lines.push(OrderLine::discount(
+                    description,
+                    item.discount(),
+                    invoice.vat_rate(item),
+                ));
+            }
+        }
+        if let Some(ref discount) = invoice.metadata.discount {
+            for amount in invoice.vat_breakdown()? {
+                if amount.discount.amount != 0 {
+                    lines.push(OrderLine::discount(discount.label(), amount.discount, amount.rate));
+                }
+            }
         }
+        Ok(lines)
     }
 }
 
@@ -57,38 +105,84 @@ struct Order {
     OrderDirection: String,
     CounterPartyID: u32,
     OrderPDF: OrderPDF,
+    /// The files attached to the line items.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    Attachments: Vec<OrderPDF>,
     OrderLines: Vec<OrderLine>,
     VentilationCode: String,
     Paid: bool,
     IsSent: bool,
     Currency: String,
+    /// The number of the invoice a credit note cancels.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    AboutInvoiceNumber: Option<String>,
 }
 
 impl Order {
-    fn from(invoice: &Invoice, path: &Path) -> Result<Self, Box<dyn Error>> {
+    fn from(invoice: &Invoice, path: &Path, attachments: &[PathBuf]) -> Result<Self> {
         let index = invoice.index.to_string();
         let date = invoice.metadata.date.to_string();
+        let policy = &invoice.metadata.policy;
         Ok(Order {
             OrderNumber: index.clone(),
             OrderTitle: index,
             OrderDate: date.clone(),
-            ExpiryDate: invoice.metadata.date.add_days(30).to_string(),
+            ExpiryDate: invoice.due_date().to_string(),
             LastModified: date.clone(),
             Created: date,
+            // Credit notes are sales documents too; OrderType tells them apart.
             OrderDirection: "Income".to_string(),
-            OrderType: "Invoice".to_string(),
+            OrderType: match invoice.index.kind {
+                Kind::Invoice => "Invoice",
+                Kind::CreditNote => "CreditNote",
+            }
+            .to_string(),
             CounterPartyID: invoice.metadata.client.partyid,
             OrderPDF: OrderPDF::from(path)?,
-            OrderLines: invoice.items.iter().map(OrderLine::from).collect(),
-            VentilationCode: "55".to_string(),
-            Paid: true,
+            Attachments: attachments
+                .iter()
+                .map(|path| OrderPDF::from(path))
+                .collect::<Result<_>>()?,
+            OrderLines: OrderLine::all(invoice)?,
+            VentilationCode: policy.ventilation_code.clone(),
+            Paid: invoice.status()?.is_settled(),
             IsSent: true,
-            Currency: "EUR".to_string()
+            Currency: invoice.metadata.currency.code().to_string(),
+            AboutInvoiceNumber: invoice.metadata.credits.map(|index| index.to_string()),
         })
     }
 }
 
-pub fn generate_json(invoice: &Invoice, path: &Path) -> Result<String, Box<dyn Error>> {
-    let order = Order::from(invoice, path)?;
+pub fn generate_json(invoice: &Invoice, path: &Path, attachments: &[PathBuf]) -> Result<String> {
+    let order = Order::from(invoice, path, attachments)?;
     Ok(serde_json::to_string(&order)?)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use invoice::tests::invoice;
+    use invoice::{Currency, Discount};
+
+    #[test]
+    fn discount_lines() {
+        let discount = Discount::Fixed(Money::new(1400, Currency::Eur));
+        let mut invoice = invoice(&[(10000, "21"), (5000, "6")], Some(discount));
+        invoice.items[0].discount = Some(Discount::Percentage(1000));
+        let lines = OrderLine::all(&invoice)
+            .unwrap()
+            .into_iter()
+            .map(|line| (line.Description, line.Quantity, line.UnitPriceExcl, line.VATPercentage))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                ("Work".to_string(), 1., 100., 21.),
+                ("Discount 10%: Work".to_string(), 1., -10., 21.),
+                ("Work".to_string(), 1., 50., 6.),
+                ("Discount".to_string(), 1., -5., 6.),
+                ("Discount".to_string(), 1., -9., 21.),
+            ]
+        );
+    }
+}
@@ -0,0 +1,720 @@
+use config::Config;
+use error::{Error, Result};
+use invoice::{
+    Amount, Business, Client, ContactDetails, Currency, Discount, InvoiceIndex, Kind, Quantity,
+    VatRate,
+};
+use source::{DataSource, InvoiceRecord, LineItemRecord, PaymentRecord};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The syntax of a ledger file.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    Toml,
+    Json,
+}
+
+/// A number written either as a string, which uses the configured decimal
+/// separator, or as a TOML or JSON number.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Number {
+    Text(String),
+    Integer(i64),
+    Float(f64),
+}
+
+impl Number {
+    fn text(&self, separator: char) -> (String, char) {
+        match *self {
+            Number::Text(ref text) => (text.clone(), separator),
+            Number::Integer(value) => (value.to_string(), '.'),
+            Number::Float(value) => (value.to_string(), '.'),
+        }
+    }
+
+    fn amount(&self, separator: char) -> ::std::result::Result<Amount, String> {
+        let (text, separator) = self.text(separator);
+        Amount::parse(&text, separator).map_err(|error| error.to_string())
+    }
+
+    /// A percentage such as `"10%"`, or an amount.
+    fn discount(&self, separator: char) -> ::std::result::Result<Discount<Amount>, String> {
+        let (text, separator) = self.text(separator);
+        Discount::parse(&text, separator).map_err(|error| error.to_string())
+    }
+
+    fn quantity(&self) -> ::std::result::Result<Quantity, String> {
+        let (text, _) = self.text('.');
+        text.parse().map_err(|_| format!("invalid quantity `{}`", text))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SerializedClient {
+    name: String,
+    street: String,
+    city: String,
+    country: String,
+    #[serde(default)]
+    vat: String,
+    #[serde(default)]
+    vatpolicy: String,
+    partyid: u32,
+    #[serde(default)]
+    currency: Option<Currency>,
+    #[serde(default)]
+    postal_code: String,
+    #[serde(default)]
+    address_line2: String,
+    #[serde(default)]
+    region: String,
+    #[serde(default)]
+    contact_name: String,
+    #[serde(default)]
+    email: String,
+    #[serde(default)]
+    phone: String,
+    #[serde(default)]
+    registration_number: String,
+}
+
+impl SerializedClient {
+    /// The client with key `id` in the ledger.
+    fn into_client(self, id: String) -> Client {
+        let SerializedClient {
+            name,
+            street,
+            city,
+            country,
+            vat,
+            vatpolicy,
+            partyid,
+            currency,
+            postal_code,
+            address_line2,
+            region,
+            contact_name,
+            email,
+            phone,
+            registration_number,
+        } = self;
+        Client {
+            id,
+            name,
+            street,
+            city,
+            country,
+            vat,
+            vatpolicy,
+            partyid,
+            currency,
+            details: ContactDetails {
+                postal_code,
+                address_line2,
+                region,
+                contact_name,
+                email,
+                phone,
+                registration_number,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SerializedBusiness {
+    name: String,
+    street: String,
+    city: String,
+    country: String,
+    #[serde(default)]
+    vat: String,
+    #[serde(default)]
+    bank: String,
+    iban: String,
+    bic: String,
+    #[serde(default)]
+    postal_code: String,
+    #[serde(default)]
+    address_line2: String,
+    #[serde(default)]
+    region: String,
+    #[serde(default)]
+    contact_name: String,
+    #[serde(default)]
+    email: String,
+    #[serde(default)]
+    phone: String,
+    #[serde(default)]
+    registration_number: String,
+}
+
+impl From<SerializedBusiness> for Business {
+    fn from(business: SerializedBusiness) -> Self {
+        let SerializedBusiness {
+            name,
+            street,
+            city,
+            country,
+            vat,
+            bank,
+            iban,
+            bic,
+            postal_code,
+            address_line2,
+            region,
+            contact_name,
+            email,
+            phone,
+            registration_number,
+        } = business;
+        Business {
+            name,
+            street,
+            city,
+            country,
+            vat,
+            bank,
+            iban,
+            bic,
+            details: ContactDetails {
+                postal_code,
+                address_line2,
+                region,
+                contact_name,
+                email,
+                phone,
+                registration_number,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SerializedLineItem {
+    services: String,
+    #[serde(default)]
+    amount: Option<Number>,
+    #[serde(default)]
+    quantity: Option<Number>,
+    #[serde(default)]
+    unit: Option<String>,
+    #[serde(default)]
+    unit_price: Option<Number>,
+    #[serde(default)]
+    vat_rate: Option<VatRate>,
+    #[serde(default)]
+    discount: Option<Number>,
+    #[serde(default)]
+    attachment: Option<String>,
+}
+
+impl SerializedLineItem {
+    fn parse(self, path: &Path, separator: char) -> ::std::result::Result<LineItemRecord, String> {
+        let SerializedLineItem {
+            services,
+            amount,
+            quantity,
+            unit,
+            unit_price,
+            vat_rate,
+            discount,
+            attachment,
+        } = self;
+
+        if amount.is_none() && (quantity.is_none() || unit_price.is_none()) {
+            return Err("missing amount, or quantity and unit_price".to_string());
+        }
+        let parse = |number: Option<Number>| match number {
+            Some(number) => number.amount(separator).map(Some),
+            None => Ok(None),
+        };
+
+        Ok(LineItemRecord {
+            path: path.to_owned(),
+            line: None,
+            description: services,
+            amount: parse(amount)?,
+            quantity: match quantity {
+                Some(quantity) => Some(quantity.quantity()?),
+                None => None,
+            },
+            unit,
+            unit_price: parse(unit_price)?,
+            vat_rate,
+            discount: match discount {
+                Some(discount) => Some(discount.discount(separator)?),
+                None => None,
+            },
+            attachment,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SerializedInvoice {
+    year: u16,
+    index: u8,
+    business: String,
+    client: String,
+    month: u8,
+    day: u8,
+    #[serde(default)]
+    currency: Option<Currency>,
+    #[serde(default)]
+    credits: Option<InvoiceIndex>,
+    #[serde(default)]
+    discount: Option<Number>,
+    #[serde(default)]
+    items: Vec<SerializedLineItem>,
+}
+
+impl SerializedInvoice {
+    fn parse(
+        self,
+        path: &Path,
+        separator: char,
+    ) -> ::std::result::Result<(InvoiceRecord, Vec<LineItemRecord>), String> {
+        if self.items.is_empty() {
+            return Err("no line items".to_string());
+        }
+        let discount = match self.discount {
+            Some(ref discount) => Some(discount.discount(separator)?),
+            None => None,
+        };
+        let lines = self
+            .items
+            .into_iter()
+            .map(|item| item.parse(path, separator))
+            .collect::<::std::result::Result<_, _>>()?;
+        let record = InvoiceRecord {
+            business: self.business,
+            client: self.client,
+            year: self.year,
+            month: self.month,
+            day: self.day,
+            currency: self.currency,
+            credits: self.credits,
+            discount,
+        };
+        Ok((record, lines))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SerializedPayment {
+    year: u16,
+    index: u8,
+    date: String,
+    amount: Number,
+    #[serde(default)]
+    method: String,
+}
+
+impl SerializedPayment {
+    fn parse(self, path: &Path, separator: char) -> ::std::result::Result<PaymentRecord, String> {
+        let date = self.date.parse().map_err(|_| format!("invalid date `{}`", self.date))?;
+        Ok(PaymentRecord {
+            path: path.to_owned(),
+            line: None,
+            date,
+            amount: self.amount.amount(separator)?,
+            method: self.method,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SerializedLedger {
+    #[serde(default)]
+    clients: HashMap<String, SerializedClient>,
+    #[serde(default)]
+    businesses: HashMap<String, SerializedBusiness>,
+    #[serde(default)]
+    invoices: Vec<SerializedInvoice>,
+    #[serde(default, rename = "credit-notes")]
+    credit_notes: Vec<SerializedInvoice>,
+    #[serde(default)]
+    payments: Vec<SerializedPayment>,
+}
+
+/// All clients, businesses and invoices in a single `ledger.toml` or
+/// `ledger.json` file.
+pub struct Ledger {
+    path: PathBuf,
+    clients: HashMap<String, Client>,
+    businesses: HashMap<String, Business>,
+    invoices: HashMap<(Kind, u16), HashMap<u8, InvoiceRecord>>,
+    items: HashMap<(Kind, u16), HashMap<u8, Vec<LineItemRecord>>>,
+    payments: HashMap<u16, HashMap<u8, Vec<PaymentRecord>>>,
+}
+
+impl Ledger {
+    /// The ledger file in the data directory at `path`, if there is one.
+    pub fn find(path: &Path) -> Option<(PathBuf, Format)> {
+        [("ledger.toml", Format::Toml), ("ledger.json", Format::Json)]
+            .iter()
+            .map(|&(name, format)| (path.join(name), format))
+            .find(|(file, _)| file.is_file())
+    }
+
+    pub fn read(path: PathBuf, format: Format, config: &Config) -> Result<Self> {
+        let (ledger, problems) = Self::check(path, format, config)?;
+        match problems.into_iter().next() {
+            Some(problem) => Err(problem),
+            None => Ok(ledger),
+        }
+    }
+
+    /// Reads the ledger file at `path` like `read`, but leaves out the
+    /// invoices and payments that are invalid and returns a problem for each
+    /// instead of failing on the first.
+    pub fn check(path: PathBuf, format: Format, config: &Config) -> Result<(Self, Vec<Error>)> {
+        trace!("Ledger::check");
+
+        let invalid = |message| Error::InvalidRecord {
+            path: path.clone(),
+            line: None,
+            message,
+        };
+
+        let content = fs::read_to_string(&path).map_err(|error| Error::io(&path, error))?;
+        let ledger: SerializedLedger = match format {
+            // Both error messages include the position.
+            Format::Toml => toml::from_str(&content).map_err(|error| invalid(error.to_string()))?,
+            Format::Json => {
+                serde_json::from_str(&content).map_err(|error| invalid(error.to_string()))?
+            },
+        };
+
+        let mut problems = Vec::new();
+        let mut invoices = HashMap::new();
+        let mut items = HashMap::new();
+        let mut rejected = HashSet::new();
+        let documents = ledger
+            .invoices
+            .into_iter()
+            .map(|invoice| (Kind::Invoice, invoice))
+            .chain(ledger.credit_notes.into_iter().map(|invoice| (Kind::CreditNote, invoice)));
+        for (kind, invoice) in documents {
+            let index = InvoiceIndex {
+                kind,
+                year: invoice.year,
+                index: invoice.index,
+            };
+            let year = invoices.entry((kind, index.year)).or_insert_with(HashMap::new);
+            let parsed = if year.contains_key(&index.index) {
+                Err("duplicate index".to_string())
+            } else {
+                invoice.parse(&path, config.decimal_separator)
+            };
+            match parsed {
+                Ok((record, lines)) => {
+                    year.insert(index.index, record);
+                    items
+                        .entry((kind, index.year))
+                        .or_insert_with(HashMap::new)
+                        .insert(index.index, lines);
+                },
+                Err(message) => {
+                    problems.push(invalid(format!("{} {}: {}", kind, index, message)));
+                    rejected.insert(index);
+                },
+            }
+        }
+
+        let mut payments = HashMap::new();
+        for payment in ledger.payments {
+            let index = InvoiceIndex {
+                kind: Kind::Invoice,
+                year: payment.year,
+                index: payment.index,
+            };
+            let known = invoices.get(&(Kind::Invoice, payment.year));
+            if !known.is_some_and(|year| year.contains_key(&payment.index)) {
+                // A payment for a rejected invoice adds nothing to its problem.
+                if !rejected.contains(&index) {
+                    problems.push(invalid(format!("payment for {}: unknown invoice", index)));
+                }
+                continue;
+            }
+            match payment.parse(&path, config.decimal_separator) {
+                Ok(record) => {
+                    payments
+                        .entry(index.year)
+                        .or_insert_with(HashMap::new)
+                        .entry(index.index)
+                        .or_insert_with(Vec::new)
+                        .push(record);
+                },
+                Err(message) => {
+                    problems.push(invalid(format!("payment for {}: {}", index, message)))
+                },
+            }
+        }
+
+        let ledger = Self {
+            path,
+            clients: ledger
+                .clients
+                .into_iter()
+                .map(|(id, client)| (id.clone(), client.into_client(id)))
+                .collect(),
+            businesses: ledger
+                .businesses
+                .into_iter()
+                .map(|(id, business)| (id, business.into()))
+                .collect(),
+            invoices,
+            items,
+            payments,
+        };
+        Ok((ledger, problems))
+    }
+}
+
+impl DataSource for Ledger {
+    fn clients(&self) -> Result<HashMap<String, Client>> {
+        Ok(self.clients.clone())
+    }
+
+    fn businesses(&self) -> Result<HashMap<String, Business>> {
+        Ok(self.businesses.clone())
+    }
+
+    fn years(&self) -> Result<Vec<u16>> {
+        let mut years = self.invoices.keys().map(|&(_, year)| year).collect::<Vec<_>>();
+        years.sort();
+        years.dedup();
+        Ok(years)
+    }
+
+    fn invoices(&self, kind: Kind, year: u16) -> Result<HashMap<u8, InvoiceRecord>> {
+        Ok(self.invoices.get(&(kind, year)).cloned().unwrap_or_default())
+    }
+
+    fn line_items(&self, kind: Kind, year: u16) -> Result<HashMap<u8, Vec<LineItemRecord>>> {
+        Ok(self.items.get(&(kind, year)).cloned().unwrap_or_default())
+    }
+
+    fn payments(&self, year: u16) -> Result<HashMap<u8, Vec<PaymentRecord>>> {
+        Ok(self.payments.get(&year).cloned().unwrap_or_default())
+    }
+
+    /// Ledger files are meant to be edited by hand, so they are not rewritten.
+    fn add_invoice(&self, _: InvoiceIndex, _: &InvoiceRecord, _: &[LineItemRecord]) -> Result<()> {
+        Err(Error::ReadOnly(self.path.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use csv::tests::tree;
+
+    const TOML: &str = r#"
+[clients.acme]
+name = "Acme Corp"
+street = "1 Main Street"
+city = "Brussels"
+country = "Belgium"
+partyid = 1
+postal_code = "1000"
+
+[businesses.me]
+name = "My Business"
+street = "2 High Street"
+city = "Ghent"
+country = "Belgium"
+iban = "BE68539007547034"
+bic = "GKCCBEBB"
+
+[[invoices]]
+year = 2018
+index = 1
+business = "me"
+client = "acme"
+month = 1
+day = 31
+discount = "10%"
+
+[[invoices.items]]
+services = "Development"
+quantity = 2.5
+unit = "h"
+unit_price = "80,00"
+
+[[invoices.items]]
+services = "Hosting"
+amount = 15
+
+[[payments]]
+year = 2018
+index = 1
+date = "2018-02-15"
+amount = 100.5
+"#;
+
+    const JSON: &str = r#"{
+  "clients": {
+    "acme": {
+      "name": "Acme Corp", "street": "1 Main Street", "city": "Brussels",
+      "country": "Belgium", "partyid": 1, "postal_code": "1000"
+    }
+  },
+  "businesses": {
+    "me": {
+      "name": "My Business", "street": "2 High Street", "city": "Ghent",
+      "country": "Belgium", "iban": "BE68539007547034", "bic": "GKCCBEBB"
+    }
+  },
+  "invoices": [{
+    "year": 2018, "index": 1, "business": "me", "client": "acme",
+    "month": 1, "day": 31, "discount": "10%",
+    "items": [
+      {"services": "Development", "quantity": 2.5, "unit": "h", "unit_price": "80,00"},
+      {"services": "Hosting", "amount": 15}
+    ]
+  }],
+  "payments": [{"year": 2018, "index": 1, "date": "2018-02-15", "amount": 100.5}]
+}"#;
+
+    /// Reads `content` as a ledger file in `format` with a decimal comma.
+    fn check(name: &str, format: Format, content: &str) -> Result<(Ledger, Vec<String>)> {
+        let file = match format {
+            Format::Toml => "ledger.toml",
+            Format::Json => "ledger.json",
+        };
+        let path = tree(name, &[(file, content)]);
+        let mut config = Config::default();
+        config.decimal_separator = ',';
+        let result = Ledger::check(path.join(file), format, &config);
+        let _ = fs::remove_dir_all(&path);
+        let (ledger, problems) = result?;
+        let problems = problems
+            .into_iter()
+            .map(|problem| match problem {
+                Error::InvalidRecord {
+                    message,
+                    ..
+                } => message,
+                problem => problem.to_string(),
+            })
+            .collect();
+        Ok((ledger, problems))
+    }
+
+    #[test]
+    fn toml_and_json() {
+        let toml = check("ledger-toml", Format::Toml, TOML).unwrap();
+        let json = check("ledger-json", Format::Json, JSON).unwrap();
+        for (ledger, problems) in [toml, json] {
+            assert!(problems.is_empty(), "{:?}", problems);
+            assert_eq!(ledger.clients().unwrap()["acme"].details.postal_code, "1000");
+            assert_eq!(ledger.businesses().unwrap()["me"].vat, "");
+            assert_eq!(ledger.years().unwrap(), [2018]);
+
+            let invoice = &ledger.invoices(Kind::Invoice, 2018).unwrap()[&1];
+            assert_eq!((invoice.client.as_str(), invoice.month, invoice.day), ("acme", 1, 31));
+            assert_eq!(invoice.discount, Some(Discount::Percentage(1000)));
+            let items = &ledger.line_items(Kind::Invoice, 2018).unwrap()[&1];
+            assert_eq!(items[0].quantity, Some(Quantity(2500)));
+            assert_eq!(items[0].unit_price, Some(Amount::parse("80,00", ',').unwrap()));
+            assert_eq!(items[1].amount, Some(Amount::parse("15", '.').unwrap()));
+
+            let payment = &ledger.payments(2018).unwrap()[&1][0];
+            assert_eq!(payment.date, "2018-02-15".parse().unwrap());
+            assert_eq!(payment.amount, Amount::parse("100.5", '.').unwrap());
+        }
+    }
+
+    #[test]
+    fn unknown_fields() {
+        let toml = TOML.replace("unit = \"h\"", "units = \"h\"");
+        let error = check("ledger-field-toml", Format::Toml, &toml).err().unwrap().to_string();
+        assert!(error.contains("unknown field `units`"), "{}", error);
+
+        let json = JSON.replace("\"postal_code\"", "\"postcode\"");
+        let error = check("ledger-field-json", Format::Json, &json).err().unwrap().to_string();
+        assert!(error.contains("unknown field `postcode`"), "{}", error);
+    }
+
+    #[test]
+    fn every_problem() {
+        let toml = format!(
+            "{}{}",
+            TOML,
+            r#"
+[[invoices]]
+year = 2018
+index = 1
+business = "me"
+client = "acme"
+month = 2
+day = 28
+items = [{ services = "Support", amount = 10 }]
+
+[[invoices]]
+year = 2018
+index = 2
+business = "me"
+client = "acme"
+month = 3
+day = 31
+items = [{ services = "Support" }]
+
+[[invoices]]
+year = 2018
+index = 3
+business = "me"
+client = "acme"
+month = 4
+day = 30
+items = [{ services = "Support", amount = 10 }]
+
+[[payments]]
+year = 2018
+index = 2
+date = "2018-04-15"
+amount = 10
+
+[[payments]]
+year = 2018
+index = 4
+date = "2018-05-15"
+amount = 10
+
+[[payments]]
+year = 2018
+index = 3
+date = "15/05/2018"
+amount = 10
+"#
+        );
+        let (ledger, problems) = check("ledger-problems", Format::Toml, &toml).unwrap();
+        assert_eq!(
+            problems,
+            [
+                "invoice 2018-01: duplicate index",
+                "invoice 2018-02: missing amount, or quantity and unit_price",
+                "payment for 2018-04: unknown invoice",
+                "payment for 2018-03: invalid date `15/05/2018`",
+            ]
+        );
+        let mut indices =
+            ledger.invoices(Kind::Invoice, 2018).unwrap().into_keys().collect::<Vec<_>>();
+        indices.sort();
+        assert_eq!(indices, [1, 3]);
+        assert_eq!(ledger.line_items(Kind::Invoice, 2018).unwrap()[&1].len(), 2);
+        assert!(!ledger.payments(2018).unwrap().contains_key(&3));
+    }
+}
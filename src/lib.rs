@@ -2,76 +2,307 @@ extern crate csv as csv_parser;
 
 extern crate chrono;
 extern crate kuchiki;
+extern crate rusqlite;
+extern crate toml;
 #[macro_use]
 extern crate log;
 #[macro_use]
+extern crate lopdf;
+#[macro_use]
 extern crate maplit;
 #[macro_use]
 extern crate markup5ever;
+extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 
-use invoice::Invoice;
-use std::error::Error;
-use std::fmt;
-use std::fs::File;
+use config::Config;
+use invoice::{Invoice, InvoiceIndex};
+use kuchiki::NodeRef;
+use std::ffi::OsString;
+use std::fs::{self, File};
 use std::io::Write;
+use std::ops::RangeBounds;
 use std::path::{Path, PathBuf};
-use std::process::{Command, ExitStatus};
+use std::process::Command;
 
-pub use csv::read_invoice;
+pub use error::{Error, Result};
+pub use outstanding::{outstanding, Balance};
+pub use recurring::issue_recurring;
+pub use reminders::{generate_reminder, overdue, Level, Reminder};
+pub use report::{format_report, report, Dimension, Format, Row};
+pub use source::{
+    credit_notes, import_csv, read_invoice, CreditNotes, DataSource, InvoiceRecord, LineItemRecord,
+    Year,
+};
+pub use timelog::import_hours;
+pub use validate::{validate, Problem};
 
 pub mod args;
+mod cii;
+pub mod config;
 mod csv;
+mod en16931;
+mod error;
 mod html;
 pub mod invoice;
 mod json;
+mod ledger;
+mod outstanding;
+mod pdfa;
+mod recurring;
+mod reminders;
+mod report;
+mod source;
+mod sqlite;
+mod timelog;
+mod ubl;
+mod validate;
+mod xml;
+
+/// Converts the HTML file at `path` to PDF, embedding the files in
+/// `attachments`, and following the PDF `profile` of Prince if given, such as
+/// `PDF/A-3b`.
+fn generate_pdf(
+    prince: &str,
+    path: &Path,
+    attachments: &[PathBuf],
+    profile: Option<&str>,
+) -> Result<PathBuf> {
+    trace!("generate_pdf");
 
-#[derive(Debug)]
-struct PdfCreationError(ExitStatus);
+    let output = path.with_extension("pdf");
 
-impl fmt::Display for PdfCreationError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        self.0.fmt(f)
+    let mut command = Command::new(prince);
+    command.arg(path).arg("-o").arg(&output);
+    if let Some(profile) = profile {
+        command.arg(format!("--pdf-profile={}", profile));
+    }
+    for attachment in attachments {
+        let mut arg = OsString::from("--attach=");
+        arg.push(attachment);
+        command.arg(arg);
     }
+    let mut child = command.spawn().map_err(Error::PdfSpawn)?;
+
+    let ecode = child.wait().map_err(Error::PdfSpawn)?;
+
+    if !ecode.success() {
+        return Err(Error::Pdf(ecode));
+    }
+
+    Ok(output)
 }
 
-impl Error for PdfCreationError {
-    fn description(&self) -> &str {
-        "failed to create PDF"
+/// Which files `generate_invoice` writes besides the HTML invoice.
+#[derive(Copy, Clone, Debug)]
+pub struct Outputs {
+    pub pdf: bool,
+    /// The JSON order embeds the PDF, so this has no effect without `pdf`.
+    pub json: bool,
+}
+
+impl Default for Outputs {
+    fn default() -> Self {
+        Self {
+            pdf: true,
+            json: true,
+        }
     }
 }
 
-fn generate_pdf(path: &Path) -> Result<PathBuf, Box<dyn Error>> {
-    trace!("generate_pdf");
+/// Where the CSV data is read from and where generated files are written.
+#[derive(Clone, Debug)]
+pub struct Directories {
+    pub data: PathBuf,
+    pub out: PathBuf,
+}
 
-    let output = path.with_extension("pdf");
+impl Directories {
+    pub fn new<D, O>(data: D, out: O) -> Self
+    where
+        D: Into<PathBuf>,
+        O: Into<PathBuf>,
+    {
+        Self {
+            data: data.into(),
+            out: out.into(),
+        }
+    }
 
-    let mut child = Command::new("prince").arg(path).arg("-o").arg(&output).spawn()?;
+    /// Whether generated files are written to the data directory itself.
+    fn in_place(&self) -> bool {
+        match (fs::canonicalize(&self.data), fs::canonicalize(&self.out)) {
+            (Ok(data), Ok(out)) => data == out,
+            _ => self.data == self.out,
+        }
+    }
 
-    let ecode = child.wait()?;
+    /// The stylesheet link for generated HTML, if the template's default of
+    /// `../invoice.css` does not point into the data directory.
+    fn stylesheet(&self) -> Result<Option<String>> {
+        if self.in_place() {
+            return Ok(None);
+        }
+        self.url("invoice.css").map(Some)
+    }
 
-    if !ecode.success() {
-        return Err(Box::new(PdfCreationError(ecode)));
+    /// The folder that links to the attachments of `year` start with in
+    /// generated HTML, if the HTML is not written next to them.
+    fn attachment_folder(&self, year: u16) -> Result<Option<String>> {
+        if self.in_place() {
+            return Ok(None);
+        }
+        self.url(&year.to_string()).map(Some)
     }
 
-    Ok(output)
+    /// The `file:` URL of `name` in the data directory.
+    fn url(&self, name: &str) -> Result<String> {
+        let data = fs::canonicalize(&self.data).map_err(|error| Error::io(&self.data, error))?;
+        let path = data.join(name).to_string_lossy().into_owned();
+        // Windows paths such as `\\?\C:\data` become `/C:/data`.
+        let path = if cfg!(windows) {
+            format!("/{}", path.trim_start_matches(r"\\?\").replace('\\', "/"))
+        } else {
+            path
+        };
+        Ok(format!("file://{}", url_path(&path)))
+    }
+
+    /// The files attached to the line items of `invoice`, which are relative
+    /// to the folder of its year in the data directory. Files attached to
+    /// several items are only listed once.
+    fn attachments(&self, invoice: &Invoice) -> Result<Vec<PathBuf>> {
+        let folder = self.data.join(invoice.index.year.to_string());
+        let mut attachments = vec![];
+        for attachment in invoice.items.iter().filter_map(|item| item.attachment.as_ref()) {
+            let path = folder.join(attachment);
+            fs::metadata(&path).map_err(|error| Error::io(&path, error))?;
+            if !attachments.contains(&path) {
+                attachments.push(path);
+            }
+        }
+        Ok(attachments)
+    }
+}
+
+/// Percent-encodes `path` for use in a URL, leaving its slashes alone.
+fn url_path(path: &str) -> String {
+    let mut encoded = String::new();
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' | b':' => {
+                encoded.push(char::from(byte))
+            },
+            _ => encoded += &format!("%{:02X}", byte),
+        }
+    }
+    encoded
+}
+
+impl Default for Directories {
+    fn default() -> Self {
+        Self::new(".", ".")
+    }
+}
+
+/// Writes `document` to `filename` below `directories.out`, creating its
+/// folder if needed, and returns its path.
+fn write_html(directories: &Directories, filename: &str, document: &NodeRef) -> Result<PathBuf> {
+    let path = directories.out.join(filename);
+    if let Some(folder) = path.parent() {
+        fs::create_dir_all(folder).map_err(|error| Error::io(folder, error))?;
+    }
+    document.serialize_to_file(&path).map_err(|error| Error::io(&path, error))?;
+    Ok(path)
 }
 
-pub fn generate_invoice(path: &Path, invoice: &Invoice) -> Result<(), Box<dyn Error>> {
+pub fn generate_invoice(
+    directories: &Directories,
+    config: &Config,
+    invoice: &Invoice,
+    outputs: Outputs,
+) -> Result<()> {
     trace!("do_generate_invoice");
 
-    let result = invoice.generate_invoice()?;
+    let stylesheet = directories.stylesheet()?;
+    let folder = directories.attachment_folder(invoice.index.year)?;
+    let result = invoice.generate_invoice(stylesheet.as_deref(), folder.as_deref())?;
+    let path = write_html(directories, &invoice.index.filename(), &result)?;
 
-    let path = path.join(invoice.index.filename());
-    result.serialize_to_file(&path)?;
+    if !outputs.pdf {
+        return Ok(());
+    }
+
+    let attachments = directories.attachments(invoice)?;
+    // Prince takes care of the fonts and colours PDF/A requires; the Factur-X
+    // parts are added afterwards.
+    let profile = if invoice.metadata.policy.factur_x {
+        Some("PDF/A-3b")
+    } else {
+        None
+    };
+    let pdf = generate_pdf(&config.prince, &path, &attachments, profile)?;
+
+    if invoice.metadata.policy.factur_x {
+        let cii = cii::generate_cii(invoice)?;
+        let mut ciipath = path.with_extension("").into_os_string();
+        ciipath.push("-factur-x.xml");
+        let ciipath = PathBuf::from(ciipath);
+        fs::write(&ciipath, &cii).map_err(|error| Error::io(ciipath, error))?;
+        pdfa::make_factur_x(&pdf, invoice, &cii)?;
+    }
 
-    let pdf = generate_pdf(&path)?;
+    if invoice.metadata.policy.ubl {
+        let ubl = ubl::generate_ubl(invoice, &pdf, &attachments)?;
+        let ublpath = path.with_extension("xml");
+        fs::write(&ublpath, ubl).map_err(|error| Error::io(ublpath, error))?;
+    }
 
-    let json = json::generate_json(&invoice, &pdf)?;
+    if !outputs.json {
+        return Ok(());
+    }
+
+    let json = json::generate_json(invoice, &pdf, &attachments)?;
     let jsonpath = path.with_extension("json");
-    let mut jsonfile = File::create(jsonpath)?;
-    jsonfile.write_all(json.as_ref())?;
+    File::create(&jsonpath)
+        .and_then(|mut jsonfile| jsonfile.write_all(json.as_ref()))
+        .map_err(|error| Error::io(jsonpath, error))?;
 
     Ok(())
 }
+
+/// The outcome of generating a single invoice in a batch.
+pub type GenerationResult = (InvoiceIndex, Result<()>);
+
+/// Generates every invoice of `data` whose index lies in `indices`.
+///
+/// A failure to generate one invoice does not stop the others; the result
+/// for each invoice is returned alongside its index.
+pub fn generate_invoices<R>(
+    directories: &Directories,
+    data: &Year,
+    indices: R,
+    outputs: Outputs,
+) -> Vec<GenerationResult>
+where
+    R: RangeBounds<u8>,
+{
+    trace!("generate_invoices");
+
+    data.indices()
+        .into_iter()
+        .filter(|index| indices.contains(index))
+        .map(|index| {
+            let result = data.invoice(index).and_then(|invoice| {
+                generate_invoice(directories, data.config(), &invoice, outputs)
+            });
+            let index = InvoiceIndex {
+                kind: data.kind(),
+                year: data.year(),
+                index,
+            };
+            (index, result)
+        })
+        .collect()
+}
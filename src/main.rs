@@ -1,17 +1,267 @@
 extern crate invoices;
 
+use invoices::args::{self, Command, Invocation};
+use invoices::config::Config;
+use invoices::invoice::{Date, Invoice, InvoiceIndex, Kind, Money};
+use invoices::{Directories, Error, Outputs, Result};
 use std::env;
-use std::error::Error;
+use std::ops::RangeInclusive;
 use std::path::Path;
+use std::process;
 
-pub fn generate_invoice() -> Result<(), Box<dyn Error>> {
-    let path = Path::new(".");
-    let index = invoices::args::parse(env::args().skip(1))?;
-    let invoice = invoices::read_invoice(path, index)?;
-    invoices::generate_invoice(path, &invoice)?;
+/// The exit status when some, but not all, invoices of a batch failed.
+const PARTIAL_FAILURE: i32 = 1;
+
+fn generate(
+    directories: &Directories,
+    kind: Kind,
+    year: u16,
+    indices: RangeInclusive<u8>,
+    outputs: Outputs,
+) -> Result<i32> {
+    let data = invoices::Year::read(&directories.data, kind, year)?;
+    let results = invoices::generate_invoices(directories, &data, indices, outputs);
+    if results.is_empty() {
+        return Err(Error::Arguments("no matching invoices".to_string()));
+    }
+
+    let total = results.len();
+    let mut failures = vec![];
+    for (index, result) in results {
+        match result {
+            Ok(()) => println!("{}: ok", index),
+            Err(error) => {
+                println!("{}: error: {}", index, error);
+                failures.push(error);
+            },
+        }
+    }
+
+    Ok(match failures.len() {
+        0 => 0,
+        // A single invoice fails the way any other command would.
+        n if n == total => failures[0].exit_code(),
+        _ => PARTIAL_FAILURE,
+    })
+}
+
+fn list(path: &Path, kind: Kind, year: u16) -> Result<()> {
+    let data = invoices::Year::read(path, kind, year)?;
+    for index in data.indices() {
+        let invoice = data.invoice(index)?;
+        let mut line = format!(
+            "{}  {}  {:<30}  {:>12}",
+            invoice.index,
+            invoice.metadata.date,
+            invoice.metadata.client.name,
+            invoice.total()?.to_string(),
+        );
+        if kind == Kind::Invoice {
+            line += &format!("  {}", invoice.status()?);
+        }
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+fn show(invoice: &Invoice) -> Result<()> {
+    match invoice.metadata.credits {
+        Some(credits) => {
+            println!("Credit note {}", invoice.index);
+            println!("Credits:  {}", credits);
+        },
+        None => println!("Invoice {}", invoice.index),
+    }
+    println!("Date:     {}", invoice.metadata.date);
+    println!("Business: {}", invoice.metadata.business.name);
+    println!("Client:   {}", invoice.metadata.client.name);
+    if invoice.index.kind == Kind::Invoice {
+        println!("Due:      {}", invoice.due_date());
+        println!("Status:   {}", invoice.status()?);
+    }
+    println!();
+    for item in &invoice.items {
+        let description = match (item.quantity, item.unit_price) {
+            (Some(quantity), Some(unit_price)) => format!(
+                "{} ({} {} \u{d7} {})",
+                item.description,
+                quantity,
+                item.unit.as_deref().unwrap_or(""),
+                unit_price,
+            ),
+            _ => item.description.clone(),
+        };
+        println!("  {:<50}  {:>12}", description, item.amount.to_string());
+        if let Some(ref discount) = item.discount {
+            println!("  {:<50}  {:>12}", discount.label(), (-item.discount()).to_string());
+        }
+    }
+    if let Some(ref discount) = invoice.metadata.discount {
+        println!("  {:<50}  {:>12}", discount.label(), (-invoice.discount()?).to_string());
+    }
+    println!("  {:<50}  {:>12}", "Total excl. VAT", invoice.total_excl_vat()?.to_string());
+    for amount in invoice.vat_breakdown()? {
+        let label = format!("VAT {} on {}", amount.rate, amount.base);
+        println!("  {:<50}  {:>12}", label, amount.vat.to_string());
+    }
+    println!("  {:<50}  {:>12}", "Total", invoice.total()?.to_string());
+    for payment in &invoice.payments {
+        let label = if payment.method.is_empty() {
+            format!("Paid {}", payment.date)
+        } else {
+            format!("Paid {} ({})", payment.date, payment.method)
+        };
+        println!("  {:<50}  {:>12}", label, (-payment.amount).to_string());
+    }
+    for &(credit_note, total) in &invoice.credit_notes {
+        let label = format!("Credited by {}", credit_note);
+        println!("  {:<50}  {:>12}", label, (-total).to_string());
+    }
+    if !invoice.payments.is_empty() || !invoice.credit_notes.is_empty() {
+        println!("  {:<50}  {:>12}", "Open", invoice.balance()?.to_string());
+    }
+    Ok(())
+}
+
+/// Prints the invoices that were just added.
+fn show_new(path: &Path, indices: Vec<InvoiceIndex>) -> Result<()> {
+    for index in indices {
+        show(&invoices::read_invoice(path, index)?)?;
+        println!();
+    }
     Ok(())
 }
 
+/// Prints the open invoices, grouped by client, with the total open per
+/// client and currency.
+fn outstanding(path: &Path) -> Result<()> {
+    let balances = invoices::outstanding(path)?;
+    let mut clients = balances.iter().peekable();
+    while let Some(first) = clients.peek() {
+        let name = first.invoice.metadata.client.name.clone();
+        println!("{}", name);
+        println!(
+            "  {:<10}  {:<10}  {:>12}  {:>12}  {:>12}  {:>12}",
+            "Invoice", "Date", "Total", "Paid", "Credited", "Open"
+        );
+        let mut totals: Vec<Money> = vec![];
+        while let Some(balance) =
+            clients.next_if(|balance| balance.invoice.metadata.client.name == name)
+        {
+            let invoice = &balance.invoice;
+            println!(
+                "  {:<10}  {:<10}  {:>12}  {:>12}  {:>12}  {:>12}",
+                invoice.index.to_string(),
+                invoice.metadata.date.to_string(),
+                invoice.total()?.to_string(),
+                balance.paid.to_string(),
+                balance.credited.to_string(),
+                balance.open.to_string(),
+            );
+            match totals.iter_mut().find(|total| total.currency == balance.open.currency) {
+                Some(total) => *total = total.checked_add(balance.open)?,
+                None => totals.push(balance.open),
+            }
+        }
+        for total in totals {
+            println!("  {:<64}  {:>12}", "Open", total.to_string());
+        }
+        println!();
+    }
+    Ok(())
+}
+
+/// Writes a reminder for every invoice that is overdue on `date`.
+fn reminders(directories: &Directories, date: Date, pdf: bool) -> Result<()> {
+    let config = Config::read(&directories.data)?;
+    for reminder in invoices::overdue(&directories.data, date)? {
+        let file = invoices::generate_reminder(directories, &config, &reminder, pdf)?;
+        println!(
+            "{}: {}, {} days overdue, {} open: {}",
+            reminder.balance.invoice.index,
+            reminder.level,
+            reminder.days_overdue,
+            reminder.balance.open,
+            file.display(),
+        );
+    }
+    Ok(())
+}
+
+/// The exit status when the data tree has problems (`EX_DATAERR`).
+const INVALID_DATA: i32 = 65;
+
+fn validate(path: &Path) -> Result<i32> {
+    let problems = invoices::validate(path)?;
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    if problems.is_empty() {
+        return Ok(0);
+    }
+    eprintln!("{} problems found", problems.len());
+    Ok(INVALID_DATA)
+}
+
+pub fn run() -> Result<i32> {
+    let Invocation {
+        data_dir,
+        command,
+    } = args::parse(env::args().skip(1))?;
+    let path = &*data_dir;
+    match command {
+        Command::Generate {
+            kind,
+            year,
+            indices,
+            out_dir,
+            outputs,
+        } => return generate(&Directories::new(path, out_dir), kind, year, indices, outputs),
+        Command::List {
+            kind,
+            year,
+        } => list(path, kind, year)?,
+        Command::Show {
+            index,
+        } => show(&invoices::read_invoice(path, index)?)?,
+        Command::Validate => return validate(path),
+        Command::ImportCsv => {
+            let database = invoices::import_csv(path)?;
+            println!("{}", database.display());
+        },
+        Command::ImportHours {
+            log,
+            business,
+            date,
+        } => show_new(path, invoices::import_hours(path, &log, business.as_deref(), date)?)?,
+        Command::Recurring {
+            until,
+        } => show_new(path, invoices::issue_recurring(path, until)?)?,
+        Command::Outstanding => outstanding(path)?,
+        Command::Reminders {
+            date,
+            out_dir,
+            pdf,
+        } => reminders(&Directories::new(path, out_dir), date, pdf)?,
+        Command::Report {
+            dimensions,
+            format,
+        } => {
+            let rows = invoices::report(path, &dimensions)?;
+            print!("{}", invoices::format_report(&dimensions, &rows, format)?);
+        },
+        Command::Help(command) => print!("{}", args::usage(command.as_deref())),
+    }
+    Ok(0)
+}
+
 fn main() {
-    generate_invoice().unwrap();
+    let code = match run() {
+        Ok(code) => code,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            error.exit_code()
+        },
+    };
+    process::exit(code);
 }
@@ -0,0 +1,107 @@
+use config::Config;
+use error::Result;
+use invoice::{Invoice, Kind, Money};
+use source::{self, Year};
+use std::path::Path;
+
+/// An invoice that has not been paid in full.
+#[derive(Debug)]
+pub struct Balance {
+    pub invoice: Invoice,
+    /// The total of the payments received.
+    pub paid: Money,
+    /// The total of the credit notes that cancel part of the invoice.
+    pub credited: Money,
+    /// What is left to pay.
+    pub open: Money,
+}
+
+/// The invoices in the data directory at `path` that are not paid in full,
+/// counting credit notes as paying the invoice they credit. Sorted by client
+/// name, then by number.
+pub fn outstanding(path: &Path) -> Result<Vec<Balance>> {
+    trace!("outstanding");
+
+    let config = Config::read(path)?;
+    let source = source::open(path, &config)?;
+    let credit_notes = source::credit_notes(&*source, &config)?;
+
+    let mut balances = vec![];
+    for year in source.years()? {
+        let data = Year::from_source(&*source, config.clone(), Kind::Invoice, year, &credit_notes)?;
+        for index in data.indices() {
+            let invoice = data.invoice(index)?;
+            let open = invoice.balance()?;
+            if open.amount <= 0 {
+                continue;
+            }
+            balances.push(Balance {
+                paid: invoice.paid()?,
+                credited: invoice.credited()?,
+                open,
+                invoice,
+            });
+        }
+    }
+    balances.sort_by(|a, b| {
+        let (a, b) = (&a.invoice, &b.invoice);
+        (&a.metadata.client.name, a.index).cmp(&(&b.metadata.client.name, b.index))
+    });
+    Ok(balances)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use csv::tests::tree;
+    use std::fs;
+
+    #[test]
+    fn credit_notes_of_any_year() {
+        let invoices = "index,business,client,year,month,day
+1,me,acme,2018,11,30
+2,me,acme,2018,12,31
+";
+        let data = "index,price,services,attachment
+1,100.00,Consulting,
+2,100.00,Consulting,
+";
+        let credit_notes = "index,business,client,year,month,day,credits
+1,me,acme,2019,1,15,2018-02
+";
+        let credit_note_data = "index,price,services,attachment
+1,30.00,Consulting,
+";
+        let payments = "index,date,amount
+1,2018-12-15,100.00
+";
+        let files = [
+            ("invoices.toml", "vat-rate = 0\n"),
+            ("2018/invoices.csv", invoices),
+            ("2018/data.csv", data),
+            ("2018/payments.csv", payments),
+            ("2019/invoices.csv", "index,business,client,year,month,day\n"),
+            ("2019/data.csv", "index,price,services,attachment\n"),
+            ("2019/credit-notes.csv", credit_notes),
+            ("2019/credit-note-data.csv", credit_note_data),
+        ];
+        let path = tree("outstanding", &files);
+        let balances = outstanding(&path);
+        let _ = fs::remove_dir_all(&path);
+
+        let balances = balances
+            .unwrap()
+            .into_iter()
+            .map(|balance| {
+                let Balance {
+                    invoice,
+                    paid,
+                    credited,
+                    open,
+                } = balance;
+                format!("{}: paid {}, credited {}, open {}", invoice.index, paid, credited, open)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(balances, ["2018-02: paid €0.00, credited €30.00, open €70.00"]);
+    }
+}
@@ -0,0 +1,527 @@
+use chrono::{DateTime, Utc};
+use error::{Error, Result};
+use invoice::Kind;
+use lopdf::{self, Dictionary, Document, Object, ObjectId, Stream, StringFormat};
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use xml::escape;
+use Invoice;
+
+/// The name under which Factur-X expects the invoice XML.
+const FACTUR_X: &str = "factur-x.xml";
+const FACTUR_X_NAMESPACE: &str = "urn:factur-x:pdfa:CrossIndustryDocument:invoice:1p0#";
+const SRGB: &str = "sRGB IEC61966-2.1";
+const CREATOR: &str = "generate-invoice";
+
+/// The document information of the PDF, which PDF/A requires to match its
+/// XMP metadata.
+struct Info {
+    title: String,
+    author: String,
+    creator: String,
+    producer: String,
+    /// The PDF and XMP forms of the creation and modification date.
+    date: (String, String),
+}
+
+impl Info {
+    fn new(invoice: &Invoice, document: &Document, now: DateTime<Utc>) -> Self {
+        let producer = document
+            .trailer
+            .get(b"Info")
+            .and_then(|info| document.dereference(info))
+            .and_then(|(_, info)| info.as_dict())
+            .and_then(|info| info.get(b"Producer"))
+            .ok()
+            .and_then(text);
+        let kind = match invoice.index.kind {
+            Kind::Invoice => "Invoice",
+            Kind::CreditNote => "Credit note",
+        };
+        Self {
+            title: format!("{} {}", kind, invoice.index),
+            author: invoice.metadata.business.name.clone(),
+            creator: CREATOR.to_string(),
+            producer: producer.unwrap_or_else(|| CREATOR.to_string()),
+            date: (
+                now.format("D:%Y%m%d%H%M%S+00'00'").to_string(),
+                now.format("%Y-%m-%dT%H:%M:%S+00:00").to_string(),
+            ),
+        }
+    }
+
+    fn dictionary(&self) -> Dictionary {
+        dictionary! {
+            "Title" => text_string(&self.title),
+            "Author" => text_string(&self.author),
+            "Creator" => text_string(&self.creator),
+            "Producer" => text_string(&self.producer),
+            "CreationDate" => Object::string_literal(&*self.date.0),
+            "ModDate" => Object::string_literal(&*self.date.0),
+        }
+    }
+
+    /// An XMP packet that declares PDF/A-3b and Factur-X conformance.
+    fn xmp(&self) -> String {
+        format!(
+            r#"<?xpacket begin="{bom}" id="W5M0MpCehiHzreSzNTczkc9d"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/">
+  <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+    <rdf:Description rdf:about="" xmlns:pdfaid="http://www.aiim.org/pdfa/ns/id/">
+      <pdfaid:part>3</pdfaid:part>
+      <pdfaid:conformance>B</pdfaid:conformance>
+    </rdf:Description>
+    <rdf:Description rdf:about="" xmlns:dc="http://purl.org/dc/elements/1.1/">
+      <dc:format>application/pdf</dc:format>
+      <dc:title><rdf:Alt><rdf:li xml:lang="x-default">{title}</rdf:li></rdf:Alt></dc:title>
+      <dc:creator><rdf:Seq><rdf:li>{author}</rdf:li></rdf:Seq></dc:creator>
+    </rdf:Description>
+    <rdf:Description rdf:about="" xmlns:xmp="http://ns.adobe.com/xap/1.0/">
+      <xmp:CreatorTool>{creator}</xmp:CreatorTool>
+      <xmp:CreateDate>{date}</xmp:CreateDate>
+      <xmp:ModifyDate>{date}</xmp:ModifyDate>
+    </rdf:Description>
+    <rdf:Description rdf:about="" xmlns:pdf="http://ns.adobe.com/pdf/1.3/">
+      <pdf:Producer>{producer}</pdf:Producer>
+    </rdf:Description>
+    <rdf:Description rdf:about="" xmlns:fx="{fx}">
+      <fx:DocumentType>INVOICE</fx:DocumentType>
+      <fx:DocumentFileName>{filename}</fx:DocumentFileName>
+      <fx:Version>1.0</fx:Version>
+      <fx:ConformanceLevel>EN 16931</fx:ConformanceLevel>
+    </rdf:Description>
+    <rdf:Description rdf:about=""
+        xmlns:pdfaExtension="http://www.aiim.org/pdfa/ns/extension/"
+        xmlns:pdfaSchema="http://www.aiim.org/pdfa/ns/schema#"
+        xmlns:pdfaProperty="http://www.aiim.org/pdfa/ns/property#">
+      <pdfaExtension:schemas>
+        <rdf:Bag>
+          <rdf:li rdf:parseType="Resource">
+            <pdfaSchema:schema>Factur-X PDFA Extension Schema</pdfaSchema:schema>
+            <pdfaSchema:namespaceURI>{fx}</pdfaSchema:namespaceURI>
+            <pdfaSchema:prefix>fx</pdfaSchema:prefix>
+            <pdfaSchema:property>
+              <rdf:Seq>
+                {properties}
+              </rdf:Seq>
+            </pdfaSchema:property>
+          </rdf:li>
+        </rdf:Bag>
+      </pdfaExtension:schemas>
+    </rdf:Description>
+  </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end="w"?>"#,
+            bom = '\u{feff}',
+            title = escape(&self.title),
+            author = escape(&self.author),
+            creator = escape(&self.creator),
+            producer = escape(&self.producer),
+            date = self.date.1,
+            fx = FACTUR_X_NAMESPACE,
+            filename = FACTUR_X,
+            properties = [
+                ("DocumentFileName", "The name of the embedded XML document"),
+                ("DocumentType", "The type of the hybrid document in capital letters"),
+                ("Version", "The version of the standard of the embedded XML document"),
+                ("ConformanceLevel", "The conformance level of the embedded XML document"),
+            ]
+            .iter()
+            .map(|&(name, description)| format!(
+                "<rdf:li rdf:parseType=\"Resource\">\
+                 <pdfaProperty:name>{}</pdfaProperty:name>\
+                 <pdfaProperty:valueType>Text</pdfaProperty:valueType>\
+                 <pdfaProperty:category>external</pdfaProperty:category>\
+                 <pdfaProperty:description>{}</pdfaProperty:description>\
+                 </rdf:li>",
+                name, description
+            ))
+            .collect::<Vec<_>>()
+            .join("\n                "),
+        )
+    }
+}
+
+/// Decodes a PDF text string, which is either UTF-16 with a byte order mark
+/// or, close enough, Latin-1.
+fn text(object: &Object) -> Option<String> {
+    let bytes = object.as_str().ok()?;
+    if bytes.starts_with(&[0xfe, 0xff]) {
+        let units = bytes[2..].chunks(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]]));
+        String::from_utf16(&units.collect::<Vec<_>>()).ok()
+    } else {
+        Some(bytes.iter().map(|&byte| char::from(byte)).collect())
+    }
+}
+
+fn text_string(text: &str) -> Object {
+    if text.is_ascii() {
+        return Object::string_literal(text);
+    }
+    let mut bytes = vec![0xfe, 0xff];
+    for unit in text.encode_utf16() {
+        bytes.extend_from_slice(&unit.to_be_bytes());
+    }
+    Object::String(bytes, StringFormat::Hexadecimal)
+}
+
+/// The file specifications in the `EmbeddedFiles` name tree, by name.
+fn embedded_files(document: &Document) -> lopdf::Result<Vec<(Vec<u8>, Object)>> {
+    fn walk(
+        document: &Document,
+        node: &Object,
+        files: &mut Vec<(Vec<u8>, Object)>,
+    ) -> lopdf::Result<()> {
+        let node = document.dereference(node)?.1.as_dict()?;
+        if let Ok(names) = node.get(b"Names") {
+            for pair in document.dereference(names)?.1.as_array()?.chunks(2) {
+                if let [ref name, ref spec] = *pair {
+                    files.push((name.as_str()?.to_vec(), spec.clone()));
+                }
+            }
+        }
+        if let Ok(kids) = node.get(b"Kids") {
+            for kid in document.dereference(kids)?.1.as_array()? {
+                walk(document, kid, files)?;
+            }
+        }
+        Ok(())
+    }
+
+    let mut files = vec![];
+    let names = document.catalog()?.get(b"Names").and_then(|names| document.dereference(names));
+    if let Ok((_, names)) = names {
+        if let Ok(tree) = names.as_dict()?.get(b"EmbeddedFiles") {
+            walk(document, tree, &mut files)?;
+        }
+    }
+    Ok(files)
+}
+
+/// Adds the associated file entries that PDF/A-3 requires to the files
+/// embedded by Prince, which are the attachments of the line items.
+fn describe_attachment(document: &mut Document, spec: ObjectId) -> lopdf::Result<()> {
+    let spec = document.get_dictionary_mut(spec)?;
+    if !spec.has(b"AFRelationship") {
+        spec.set("AFRelationship", "Supplement");
+    }
+    if !spec.has(b"UF") {
+        if let Ok(name) = spec.get(b"F").cloned() {
+            spec.set("UF", name);
+        }
+    }
+    let file = match spec.get(b"EF").and_then(Object::as_dict).and_then(|ef| ef.get(b"F")) {
+        Ok(&Object::Reference(file)) => file,
+        _ => return Ok(()),
+    };
+    let file = &mut document.get_object_mut(file)?.as_stream_mut()?.dict;
+    if !file.has(b"Subtype") {
+        file.set("Subtype", "application/octet-stream");
+    }
+    Ok(())
+}
+
+/// An ICC profile for sRGB, with the primaries adapted to the D50 white
+/// point of the profile connection space and a sampled tone curve.
+fn srgb_profile() -> Vec<u8> {
+    fn s15_fixed16(value: f64) -> [u8; 4] {
+        ((value * 65536.0).round() as i32).to_be_bytes()
+    }
+
+    fn xyz(x: f64, y: f64, z: f64) -> Vec<u8> {
+        let mut tag = b"XYZ \0\0\0\0".to_vec();
+        for &value in &[x, y, z] {
+            tag.extend_from_slice(&s15_fixed16(value));
+        }
+        tag
+    }
+
+    let mut description = b"desc\0\0\0\0".to_vec();
+    description.extend_from_slice(&(SRGB.len() as u32 + 1).to_be_bytes());
+    description.extend_from_slice(SRGB.as_bytes());
+    description.extend_from_slice(&[0; 1 + 4 + 4 + 2 + 1 + 67]);
+    let copyright = b"text\0\0\0\0No copyright, use freely\0".to_vec();
+    let mut curve = b"curv\0\0\0\0".to_vec();
+    curve.extend_from_slice(&1024u32.to_be_bytes());
+    for i in 0..1024 {
+        let value = f64::from(i) / 1023.0;
+        let linear = if value <= 0.04045 {
+            value / 12.92
+        } else {
+            ((value + 0.055) / 1.055).powf(2.4)
+        };
+        curve.extend_from_slice(&((linear * 65535.0).round() as u16).to_be_bytes());
+    }
+
+    let tags: &[(&[u8; 4], &[u8])] = &[
+        (b"desc", &description),
+        (b"cprt", &copyright),
+        (b"wtpt", &xyz(0.9642, 1.0, 0.8249)),
+        (b"rXYZ", &xyz(0.4361, 0.2225, 0.0139)),
+        (b"gXYZ", &xyz(0.3851, 0.7169, 0.0971)),
+        (b"bXYZ", &xyz(0.1431, 0.0606, 0.7141)),
+        (b"rTRC", &curve),
+        (b"gTRC", &curve),
+        (b"bTRC", &curve),
+    ];
+
+    let mut table = (tags.len() as u32).to_be_bytes().to_vec();
+    let mut data = vec![];
+    let start = 128 + 4 + 12 * tags.len();
+    for &(signature, tag) in tags {
+        table.extend_from_slice(signature);
+        table.extend_from_slice(&((start + data.len()) as u32).to_be_bytes());
+        table.extend_from_slice(&(tag.len() as u32).to_be_bytes());
+        data.extend_from_slice(tag);
+        while data.len() % 4 != 0 {
+            data.push(0);
+        }
+    }
+
+    let mut header = vec![];
+    header.extend_from_slice(&((start + data.len()) as u32).to_be_bytes());
+    header.extend_from_slice(&[0; 4]);
+    header.extend_from_slice(&[2, 0x10, 0, 0]);
+    header.extend_from_slice(b"mntrRGB XYZ ");
+    for &field in &[2000u16, 1, 1, 0, 0, 0] {
+        header.extend_from_slice(&field.to_be_bytes());
+    }
+    header.extend_from_slice(b"acsp");
+    header.extend_from_slice(&[0; 28]);
+    for &value in &[0.9642, 1.0, 0.8249] {
+        header.extend_from_slice(&s15_fixed16(value));
+    }
+    header.resize(128, 0);
+
+    let mut profile = header;
+    profile.extend(table);
+    profile.extend(data);
+    profile
+}
+
+/// Stores `object` in place of the object `existing`, if there is one, so
+/// that what Prince wrote is not left behind unused.
+fn replace<T: Into<Object>>(
+    document: &mut Document,
+    existing: Option<ObjectId>,
+    object: T,
+) -> ObjectId {
+    match existing {
+        Some(id) => {
+            document.objects.insert(id, object.into());
+            id
+        },
+        None => document.add_object(object),
+    }
+}
+
+fn convert(document: &mut Document, invoice: &Invoice, xml: &str) -> lopdf::Result<()> {
+    let info = Info::new(invoice, document, Utc::now());
+    document.version = "1.7".to_string();
+
+    let file = document.add_object(Stream::new(
+        dictionary! {
+            "Type" => "EmbeddedFile",
+            "Subtype" => "text/xml",
+            "Params" => dictionary! {
+                "Size" => xml.len() as i64,
+                "ModDate" => Object::string_literal(&*info.date.0),
+            },
+        },
+        xml.as_bytes().to_vec(),
+    ));
+    let spec = document.add_object(dictionary! {
+        "Type" => "Filespec",
+        "F" => Object::string_literal(FACTUR_X),
+        "UF" => Object::string_literal(FACTUR_X),
+        "Desc" => Object::string_literal("Factur-X invoice"),
+        "AFRelationship" => "Alternative",
+        "EF" => dictionary! {
+            "F" => file,
+            "UF" => file,
+        },
+    });
+
+    let mut files = vec![(FACTUR_X.as_bytes().to_vec(), spec)];
+    for (name, object) in embedded_files(document)? {
+        if name == FACTUR_X.as_bytes() {
+            continue;
+        }
+        let spec = match object {
+            Object::Reference(spec) => spec,
+            object => document.add_object(object),
+        };
+        describe_attachment(document, spec)?;
+        files.push((name, spec));
+    }
+    files.sort();
+    let mut names = vec![];
+    for &(ref name, spec) in &files {
+        names.push(Object::String(name.clone(), StringFormat::Literal));
+        names.push(Object::Reference(spec));
+    }
+    let associated = files.iter().map(|&(_, spec)| Object::Reference(spec)).collect::<Vec<_>>();
+
+    let mut name_trees = match document.catalog()?.get(b"Names") {
+        Ok(names) => document.dereference(names)?.1.as_dict()?.clone(),
+        Err(_) => Dictionary::new(),
+    };
+    name_trees.set("EmbeddedFiles", dictionary! { "Names" => names });
+
+    let metadata = Stream::new(
+        dictionary! {
+            "Type" => "Metadata",
+            "Subtype" => "XML",
+        },
+        info.xmp().into_bytes(),
+    );
+    let existing = document.catalog()?.get(b"Metadata").and_then(Object::as_reference).ok();
+    let metadata = replace(document, existing, metadata.with_compression(false));
+    // Prince already adds an output intent for its PDF/A profiles.
+    let output_intent = if document.catalog()?.has(b"OutputIntents") {
+        None
+    } else {
+        let profile = document.add_object(Stream::new(dictionary! { "N" => 3 }, srgb_profile()));
+        Some(dictionary! {
+            "Type" => "OutputIntent",
+            "S" => "GTS_PDFA1",
+            "OutputConditionIdentifier" => Object::string_literal(SRGB),
+            "Info" => Object::string_literal(SRGB),
+            "DestOutputProfile" => profile,
+        })
+    };
+
+    let catalog = document.catalog_mut()?;
+    catalog.set("Names", name_trees);
+    catalog.set("AF", associated);
+    catalog.set("Metadata", metadata);
+    if let Some(output_intent) = output_intent {
+        catalog.set("OutputIntents", vec![output_intent.into()]);
+    }
+
+    let existing = document.trailer.get(b"Info").and_then(Object::as_reference).ok();
+    let info = replace(document, existing, info.dictionary());
+    document.trailer.set("Info", info);
+    if !document.trailer.has(b"ID") {
+        let mut hasher = DefaultHasher::new();
+        xml.hash(&mut hasher);
+        let first = hasher.finish();
+        invoice.index.hash(&mut hasher);
+        let mut id = first.to_be_bytes().to_vec();
+        id.extend_from_slice(&hasher.finish().to_be_bytes());
+        let id = Object::String(id, StringFormat::Hexadecimal);
+        document.trailer.set("ID", vec![id.clone(), id]);
+    }
+    Ok(())
+}
+
+/// Turns the PDF at `path` into a PDF/A-3b Factur-X invoice, with `xml`
+/// embedded as `factur-x.xml`.
+pub fn make_factur_x(path: &Path, invoice: &Invoice, xml: &str) -> Result<()> {
+    trace!("make_factur_x");
+
+    let failed = |error| Error::PdfA {
+        path: path.to_owned(),
+        error,
+    };
+    let input = fs::read(path).map_err(|error| Error::io(path, error))?;
+    let mut document = Document::load_mem(&input).map_err(failed)?;
+    convert(&mut document, invoice, xml).map_err(failed)?;
+    let mut output = vec![];
+    document.save_to(&mut output).expect("writing to memory");
+    fs::write(path, output).map_err(|error| Error::io(path, error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use invoice::tests::invoice;
+    use std::{env, process};
+
+    /// A one-page PDF with an attached file, as Prince writes it.
+    fn minimal_pdf() -> Vec<u8> {
+        let mut document = Document::with_version("1.4");
+        let pages = document.new_object_id();
+        let page = document.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages,
+            "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+        });
+        let kids = dictionary! {
+            "Type" => "Pages",
+            "Kids" => vec![page.into()],
+            "Count" => 1,
+        };
+        document.objects.insert(pages, kids.into());
+        let receipt = Stream::new(dictionary! { "Type" => "EmbeddedFile" }, b"%PDF".to_vec());
+        let receipt = document.add_object(receipt);
+        let spec = document.add_object(dictionary! {
+            "Type" => "Filespec",
+            "F" => Object::string_literal("receipt.pdf"),
+            "EF" => dictionary! { "F" => receipt },
+        });
+        let catalog = document.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages,
+            "Names" => dictionary! {
+                "EmbeddedFiles" => dictionary! {
+                    "Names" => vec![Object::string_literal("receipt.pdf"), spec.into()],
+                },
+            },
+        });
+        document.trailer.set("Root", catalog);
+        let mut output = vec![];
+        document.save_to(&mut output).expect("writing to memory");
+        output
+    }
+
+    #[test]
+    fn factur_x_round_trip() {
+        let path = env::temp_dir().join(format!("factur-x-{}.pdf", process::id()));
+        fs::write(&path, minimal_pdf()).unwrap();
+        let xml = "<rsm:CrossIndustryInvoice/>";
+        let result = make_factur_x(&path, &invoice(&[(10000, "21")], None), xml);
+        let output = fs::read(&path);
+        let _ = fs::remove_file(&path);
+        result.unwrap();
+
+        let document = Document::load_mem(&output.unwrap()).unwrap();
+        let resolve = |object| document.dereference(object).unwrap().1;
+        let catalog = document.catalog().unwrap();
+
+        let associated = catalog.get(b"AF").unwrap().as_array().unwrap();
+        let specs =
+            associated.iter().map(|spec| resolve(spec).as_dict().unwrap()).collect::<Vec<_>>();
+        let relationships = specs
+            .iter()
+            .map(|spec| {
+                let name = spec.get(b"F").unwrap().as_str().unwrap();
+                (name, spec.get(b"AFRelationship").unwrap().as_name().unwrap())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            relationships,
+            [
+                (&b"factur-x.xml"[..], &b"Alternative"[..]),
+                (&b"receipt.pdf"[..], &b"Supplement"[..])
+            ]
+        );
+        let file = specs[0].get(b"EF").unwrap().as_dict().unwrap().get(b"F").unwrap();
+        assert_eq!(resolve(file).as_stream().unwrap().content, xml.as_bytes());
+
+        let intents = catalog.get(b"OutputIntents").unwrap().as_array().unwrap();
+        let intent = intents[0].as_dict().unwrap();
+        assert_eq!(intent.get(b"S").unwrap().as_name().unwrap(), b"GTS_PDFA1");
+        let profile = resolve(intent.get(b"DestOutputProfile").unwrap());
+        assert_eq!(&profile.as_stream().unwrap().content[36..40], b"acsp");
+
+        let metadata = resolve(catalog.get(b"Metadata").unwrap()).as_stream().unwrap();
+        let xmp = String::from_utf8(metadata.content.clone()).unwrap();
+        assert!(xmp.contains("<pdfaid:part>3</pdfaid:part>"));
+        assert!(xmp.contains("<pdfaid:conformance>B</pdfaid:conformance>"));
+        assert!(xmp.contains("<fx:DocumentFileName>factur-x.xml</fx:DocumentFileName>"));
+        assert!(xmp.contains("Invoice 2018-01"));
+    }
+}
@@ -0,0 +1,239 @@
+use config::Config;
+use csv;
+use error::{Error, Result};
+use invoice::{Currency, Date, InvoiceIndex, Kind};
+use source::{self, DataSource, InvoiceRecord, LineItemRecord};
+use std::path::Path;
+
+/// How often a recurring invoice is issued.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Frequency {
+    Weekly,
+    Monthly,
+    Quarterly,
+    Yearly,
+}
+
+/// An invoice that is issued at a fixed interval, from `recurring.csv`.
+#[derive(Clone, Debug)]
+pub struct Schedule {
+    pub id: String,
+    pub business: String,
+    pub client: String,
+    pub currency: Option<Currency>,
+    pub frequency: Frequency,
+    pub start: Date,
+    /// The last day an invoice may be issued on, if the schedule ends.
+    pub end: Option<Date>,
+    pub items: Vec<LineItemRecord>,
+}
+
+impl Schedule {
+    /// The date of the `n`th invoice, counting from zero. Counting from the
+    /// start each time keeps invoices on the 31st from drifting after a short
+    /// month.
+    fn occurrence(&self, n: u32) -> Option<Date> {
+        match self.frequency {
+            Frequency::Weekly => Some(self.start.add_days(7 * i64::from(n))),
+            Frequency::Monthly => self.start.add_months(n),
+            Frequency::Quarterly => self.start.add_months(3 * n),
+            Frequency::Yearly => self.start.add_months(12 * n),
+        }
+    }
+
+    /// The dates of the invoices due up to and including `until`.
+    pub fn dates(&self, until: Date) -> Vec<Date> {
+        let last = match self.end {
+            Some(end) if end < until => end,
+            _ => until,
+        };
+        (0..)
+            .map(|n| self.occurrence(n))
+            .take_while(|date| date.is_some_and(|date| date <= last))
+            .flatten()
+            .collect()
+    }
+}
+
+/// Reads the schedules in `recurring.csv` in the data directory at `path`,
+/// checking that their businesses and clients are in `source`.
+pub fn read_schedules(
+    path: &Path,
+    config: &Config,
+    source: &dyn DataSource,
+) -> Result<Vec<Schedule>> {
+    let file = path.join("recurring.csv");
+    let schedules = csv::read_schedules(&file, config.decimal_separator)?;
+
+    let clients = source.clients()?;
+    let businesses = source.businesses()?;
+    for schedule in &schedules {
+        let message = if !businesses.contains_key(&schedule.business) {
+            format!("unknown business `{}`", schedule.business)
+        } else if !clients.contains_key(&schedule.client) {
+            format!("unknown client `{}`", schedule.client)
+        } else {
+            continue;
+        };
+        return Err(Error::InvalidRecord {
+            path: file,
+            line: None,
+            message: format!("schedule `{}`: {}", schedule.id, message),
+        });
+    }
+    Ok(schedules)
+}
+
+/// The invoice in `source` that `schedule` issued on `date`, if one was
+/// added but could not be recorded in `recurring-issued.csv`: one of the same
+/// business and client, on that date, with the same line items.
+fn issued_invoice(
+    source: &dyn DataSource,
+    schedule: &Schedule,
+    date: Date,
+) -> Result<Option<InvoiceIndex>> {
+    let year = date.year();
+    if !source.years()?.contains(&year) {
+        return Ok(None);
+    }
+    let items = source.line_items(Kind::Invoice, year)?;
+    let descriptions = |items: &[LineItemRecord]| {
+        items.iter().map(|item| item.description.clone()).collect::<Vec<_>>()
+    };
+    let index = source.invoices(Kind::Invoice, year)?.into_iter().find(|(index, invoice)| {
+        invoice.business == schedule.business
+            && invoice.client == schedule.client
+            && (invoice.year, invoice.month, invoice.day) == (year, date.month(), date.day())
+            && items.get(index).map(|items| descriptions(items))
+                == Some(descriptions(&schedule.items))
+    });
+    Ok(index.map(|(index, _)| InvoiceIndex {
+        kind: Kind::Invoice,
+        year,
+        index,
+    }))
+}
+
+/// Adds the invoices of every schedule in `recurring.csv` in the data
+/// directory at `path` that are due up to and including `until`, with the
+/// next free indices of their years. Issued invoices are recorded in
+/// `recurring-issued.csv`, so that they are not issued again; an invoice that
+/// was added but not recorded is recorded instead of being added again.
+/// Returns the indices of the new invoices.
+pub fn issue_recurring(path: &Path, until: Date) -> Result<Vec<InvoiceIndex>> {
+    trace!("issue_recurring");
+
+    let config = Config::read(path)?;
+    let source = source::open(path, &config)?;
+    let schedules = read_schedules(path, &config, &*source)?;
+    let log = path.join("recurring-issued.csv");
+    let issued = csv::read_issued(&log)?;
+
+    let mut due = schedules
+        .iter()
+        .flat_map(|schedule| schedule.dates(until).into_iter().map(move |date| (date, schedule)))
+        .filter(|&(date, schedule)| !issued.contains(&(schedule.id.clone(), date)))
+        .collect::<Vec<_>>();
+    // Issue older invoices first, so that their indices follow their dates.
+    due.sort_by(|a, b| (a.0, &a.1.id).cmp(&(b.0, &b.1.id)));
+
+    let mut indices = vec![];
+    for (date, schedule) in due {
+        let year = date.year();
+        let index = match issued_invoice(&*source, schedule, date)? {
+            Some(index) => index,
+            None => {
+                let index = source::next_index(&*source, Kind::Invoice, year)?;
+                let record = InvoiceRecord {
+                    business: schedule.business.clone(),
+                    client: schedule.client.clone(),
+                    year,
+                    month: date.month(),
+                    day: date.day(),
+                    currency: schedule.currency,
+                    credits: None,
+                    discount: None,
+                };
+                source.add_invoice(index, &record, &schedule.items)?;
+                indices.push(index);
+                index
+            },
+        };
+        let row = vec![
+            ("schedule", schedule.id.clone()),
+            ("date", date.to_string()),
+            ("year", year.to_string()),
+            ("index", index.index.to_string()),
+        ];
+        csv::append_rows(&log, &[row])?;
+    }
+    Ok(indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use csv::tests::tree;
+    use csv::CsvTree;
+    use std::fs;
+
+    const RECURRING: &str = "id,business,client,frequency,start,end,services,price,attachment
+retainer,me,acme,monthly,2018-01-31,2018-12-31,Retainer,1500.00,
+retainer,me,acme,monthly,2018-01-31,2018-12-31,Hosting,25.00,
+";
+
+    fn date(text: &str) -> Date {
+        text.parse().unwrap()
+    }
+
+    fn indices(indices: &[u8]) -> Vec<InvoiceIndex> {
+        indices
+            .iter()
+            .map(|&index| InvoiceIndex {
+                kind: Kind::Invoice,
+                year: 2018,
+                index,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn issue_once() {
+        let path = tree("recurring", &[("recurring.csv", RECURRING)]);
+        let first = issue_recurring(&path, date("2018-03-15"));
+        let second = issue_recurring(&path, date("2018-03-15"));
+        // As if the log could not be written after the invoices were added.
+        fs::remove_file(path.join("recurring-issued.csv")).unwrap();
+        let unrecorded = issue_recurring(&path, date("2018-03-15"));
+        let issued = csv::read_issued(&path.join("recurring-issued.csv"));
+        // As if invoices.csv could not be written after the line items.
+        let orphan = vec![
+            ("index", "3".to_string()),
+            ("price", "1500.00".to_string()),
+            ("services", "Retainer".to_string()),
+        ];
+        csv::append_rows(&path.join("2018").join("data.csv"), &[orphan]).unwrap();
+        let later = issue_recurring(&path, date("2018-04-15"));
+        let source = CsvTree::new(&path, &Config::default());
+        let invoices = source.invoices(Kind::Invoice, 2018);
+        let items = source.line_items(Kind::Invoice, 2018);
+        let _ = fs::remove_dir_all(&path);
+
+        assert_eq!(first.unwrap(), indices(&[1, 2]));
+        assert_eq!(second.unwrap(), []);
+        assert_eq!(unrecorded.unwrap(), []);
+        assert_eq!(issued.unwrap().len(), 2);
+        assert_eq!(later.unwrap(), indices(&[4]));
+
+        let mut dates = invoices
+            .unwrap()
+            .into_iter()
+            .map(|(index, invoice)| (index, invoice.month, invoice.day))
+            .collect::<Vec<_>>();
+        dates.sort();
+        assert_eq!(dates, [(1, 1, 31), (2, 2, 28), (4, 3, 31)]);
+        let items = items.unwrap();
+        assert_eq!((items[&3].len(), items[&4].len()), (1, 2));
+    }
+}
@@ -0,0 +1,169 @@
+use config::Config;
+use csv;
+use error::Result;
+use invoice::Date;
+use outstanding::{outstanding, Balance};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use {generate_pdf, write_html, Directories};
+
+/// The file in the data directory that records the reminders sent.
+const SENT: &str = "reminders-sent.csv";
+
+/// How insistent a reminder is.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Level {
+    First,
+    Second,
+    Final,
+}
+
+impl Level {
+    const ALL: [Level; 3] = [Level::First, Level::Second, Level::Final];
+
+    /// The name of the level, as recorded in `reminders-sent.csv`.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Level::First => "first",
+            Level::Second => "second",
+            Level::Final => "final",
+        }
+    }
+
+    /// The text of the letter, from `etc/reminder-<name>.html`.
+    pub fn letter(&self) -> &'static [u8] {
+        match *self {
+            Level::First => include_bytes!("../etc/reminder-first.html"),
+            Level::Second => include_bytes!("../etc/reminder-second.html"),
+            Level::Final => include_bytes!("../etc/reminder-final.html"),
+        }
+    }
+
+    /// The heading of the letter.
+    pub fn title(&self) -> &'static str {
+        match *self {
+            Level::First => "Payment reminder",
+            Level::Second => "Second payment reminder",
+            Level::Final => "Final notice",
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Level::First => "first reminder",
+            Level::Second => "second reminder",
+            Level::Final => "final reminder",
+        })
+    }
+}
+
+/// An overdue invoice, and the reminder its lateness calls for.
+#[derive(Debug)]
+pub struct Reminder {
+    pub level: Level,
+    /// The date of the reminder.
+    pub date: Date,
+    pub days_overdue: i64,
+    pub balance: Balance,
+}
+
+impl Reminder {
+    /// The file name of the reminder, next to that of the invoice.
+    pub fn filename(&self) -> String {
+        let invoice = self.balance.invoice.index.filename();
+        format!("{}-{}-reminder.html", invoice.trim_end_matches(".html"), self.level.name())
+    }
+}
+
+/// The invoices in the data directory at `path` that are overdue on `date`,
+/// by client name and number. How many days overdue an invoice is decides the
+/// level of its reminder, following `reminder-days` in `invoices.toml`;
+/// invoices that are not late enough for a first reminder, or whose reminder
+/// of that level was already sent, are left out.
+pub fn overdue(path: &Path, date: Date) -> Result<Vec<Reminder>> {
+    trace!("overdue");
+
+    let config = Config::read(path)?;
+    let sent = csv::read_sent_reminders(&path.join(SENT))?;
+    let mut reminders = vec![];
+    for balance in outstanding(path)? {
+        let days_overdue = date.days_since(balance.invoice.due_date());
+        let level = Level::ALL
+            .iter()
+            .zip(&config.reminder_days)
+            .rev()
+            .find(|&(_, &days)| days_overdue >= days)
+            .map(|(&level, _)| level)
+            .filter(|level| !sent.contains(&(balance.invoice.index, level.name().to_string())));
+        if let Some(level) = level {
+            reminders.push(Reminder {
+                level,
+                date,
+                days_overdue,
+                balance,
+            });
+        }
+    }
+    Ok(reminders)
+}
+
+/// Renders `reminder` below `directories.out`, and converts it to PDF if
+/// `pdf` is set. Once its PDF is written, the reminder is recorded in
+/// `reminders-sent.csv`, so that it is not sent again; an HTML-only draft is
+/// not. Returns the path of the PDF, or of the HTML without `pdf`.
+pub fn generate_reminder(
+    directories: &Directories,
+    config: &Config,
+    reminder: &Reminder,
+    pdf: bool,
+) -> Result<PathBuf> {
+    trace!("generate_reminder");
+
+    let stylesheet = directories.stylesheet()?;
+    let document = reminder.generate_reminder(stylesheet.as_deref())?;
+    let path = write_html(directories, &reminder.filename(), &document)?;
+    if !pdf {
+        return Ok(path);
+    }
+    let path = generate_pdf(&config.prince, &path, &[], None)?;
+    let row = vec![
+        ("invoice", reminder.balance.invoice.index.to_string()),
+        ("level", reminder.level.name().to_string()),
+        ("date", reminder.date.to_string()),
+    ];
+    csv::append_rows(&directories.data.join(SENT), &[row])?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use csv::tests::tree;
+    use invoice::tests::invoice;
+    use invoice::{Currency, Money};
+    use std::fs;
+
+    #[test]
+    fn drafts_are_not_sent() {
+        let path = tree("reminder-drafts", &[]);
+        let reminder = Reminder {
+            level: Level::First,
+            date: "2018-03-12".parse().unwrap(),
+            days_overdue: 10,
+            balance: Balance {
+                invoice: invoice(&[(10000, "21")], None),
+                paid: Money::new(0, Currency::Eur),
+                credited: Money::new(0, Currency::Eur),
+                open: Money::new(12100, Currency::Eur),
+            },
+        };
+        let directories = Directories::new(&path, &path);
+        let html = generate_reminder(&directories, &Config::default(), &reminder, false).unwrap();
+        assert_eq!(html, path.join("2018/01-first-reminder.html"));
+        assert!(html.exists());
+        assert!(!path.join(SENT).exists());
+        fs::remove_dir_all(path).unwrap();
+    }
+}
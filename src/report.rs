@@ -0,0 +1,394 @@
+use config::Config;
+use csv_parser::Writer;
+use error::{Error, Result};
+use invoice::{Currency, Invoice, Kind, Money};
+use source::{self, CreditNotes, Year};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// What the invoices of a report can be grouped by.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Dimension {
+    Client,
+    Business,
+    Month,
+    Quarter,
+    Year,
+}
+
+impl Dimension {
+    pub const ALL: [Dimension; 5] = [
+        Dimension::Client,
+        Dimension::Business,
+        Dimension::Month,
+        Dimension::Quarter,
+        Dimension::Year,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Dimension::Client => "client",
+            Dimension::Business => "business",
+            Dimension::Month => "month",
+            Dimension::Quarter => "quarter",
+            Dimension::Year => "year",
+        }
+    }
+
+    /// The group `invoice` belongs to, such as `2018-Q1` for the quarter.
+    /// Clients are grouped by their id, which unlike their name is unique.
+    fn key(&self, invoice: &Invoice) -> String {
+        let date = invoice.metadata.date;
+        match *self {
+            Dimension::Client => invoice.metadata.client.id.clone(),
+            Dimension::Business => invoice.metadata.business.name.clone(),
+            Dimension::Month => format!("{:04}-{:02}", date.year(), date.month()),
+            Dimension::Quarter => format!("{:04}-Q{}", date.year(), (date.month() - 1) / 3 + 1),
+            Dimension::Year => format!("{:04}", date.year()),
+        }
+    }
+}
+
+impl fmt::Display for Dimension {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Dimension {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let dimension =
+            Dimension::ALL.iter().cloned().find(|dimension| dimension.name() == s.trim());
+        dimension.ok_or_else(|| {
+            let names = Dimension::ALL.iter().map(Dimension::name).collect::<Vec<_>>();
+            Error::Arguments(format!(
+                "unknown report dimension `{}`, expected one of {}",
+                s,
+                names.join(", ")
+            ))
+        })
+    }
+}
+
+/// How a report is written.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    Table,
+    Csv,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "table" => Ok(Format::Table),
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            _ => Err(Error::Arguments(format!(
+                "unknown report format `{}`, expected table, csv or json",
+                s
+            ))),
+        }
+    }
+}
+
+/// The invoices and credit notes of one group, in one currency.
+#[derive(Debug)]
+pub struct Row {
+    /// The key of the group for each dimension of the report.
+    pub group: Vec<String>,
+    pub invoices: usize,
+    pub credit_notes: usize,
+    pub total_excl_vat: Money,
+    pub total_vat: Money,
+    pub total: Money,
+}
+
+impl Row {
+    fn new(group: Vec<String>, currency: Currency) -> Self {
+        Self {
+            group,
+            invoices: 0,
+            credit_notes: 0,
+            total_excl_vat: Money::zero(currency),
+            total_vat: Money::zero(currency),
+            total: Money::zero(currency),
+        }
+    }
+
+    /// Adds `invoice` to the totals, or takes it off for a credit note.
+    fn add(&mut self, invoice: &Invoice) -> Result<()> {
+        let (excl_vat, vat, total) =
+            (invoice.total_excl_vat()?, invoice.total_vat()?, invoice.total()?);
+        let (excl_vat, vat, total) = match invoice.index.kind {
+            Kind::Invoice => {
+                self.invoices += 1;
+                (excl_vat, vat, total)
+            },
+            Kind::CreditNote => {
+                self.credit_notes += 1;
+                (-excl_vat, -vat, -total)
+            },
+        };
+        self.total_excl_vat = self.total_excl_vat.checked_add(excl_vat)?;
+        self.total_vat = self.total_vat.checked_add(vat)?;
+        self.total = self.total.checked_add(total)?;
+        Ok(())
+    }
+
+    /// Adds the totals of `other` to these.
+    fn merge(&mut self, other: &Row) -> Result<()> {
+        self.invoices += other.invoices;
+        self.credit_notes += other.credit_notes;
+        self.total_excl_vat = self.total_excl_vat.checked_add(other.total_excl_vat)?;
+        self.total_vat = self.total_vat.checked_add(other.total_vat)?;
+        self.total = self.total.checked_add(other.total)?;
+        Ok(())
+    }
+
+    fn currency(&self) -> Currency {
+        self.total.currency
+    }
+}
+
+/// The totals of every invoice in the data directory at `path`, less those of
+/// the credit notes, grouped by `dimensions` and currency. Sorted by group.
+pub fn report(path: &Path, dimensions: &[Dimension]) -> Result<Vec<Row>> {
+    trace!("report");
+
+    let config = Config::read(path)?;
+    let source = source::open(path, &config)?;
+    // The totals do not depend on what credits an invoice.
+    let credit_notes = CreditNotes::new();
+
+    let mut rows = BTreeMap::new();
+    for year in source.years()? {
+        for &kind in &Kind::ALL {
+            let data = Year::from_source(&*source, config.clone(), kind, year, &credit_notes)?;
+            for index in data.indices() {
+                let invoice = data.invoice(index)?;
+                let group = dimensions.iter().map(|dimension| dimension.key(&invoice));
+                let group = group.collect::<Vec<_>>();
+                let currency = invoice.metadata.currency;
+                rows.entry((group.clone(), currency.code()))
+                    .or_insert_with(|| Row::new(group, currency))
+                    .add(&invoice)?;
+            }
+        }
+    }
+    Ok(rows.into_values().collect())
+}
+
+/// Writes `rows` as a table, with the overall totals per currency at the end.
+fn table(dimensions: &[Dimension], rows: &[Row]) -> Result<String> {
+    let mut widths = dimensions.iter().map(|dimension| dimension.name().len()).collect::<Vec<_>>();
+    // The first column also holds the label of the totals.
+    if let Some(first) = widths.first_mut() {
+        *first = (*first).max("Total".len());
+    }
+    for row in rows {
+        for (width, key) in widths.iter_mut().zip(&row.group) {
+            *width = (*width).max(key.chars().count());
+        }
+    }
+
+    let line = |group: &[String], counts: [String; 2], amounts: [String; 3]| {
+        let mut line = String::new();
+        for (key, &width) in group.iter().zip(&widths) {
+            line += &format!("{:<width$}  ", key, width = width);
+        }
+        line += &format!("{:>8}  {:>12}", counts[0], counts[1]);
+        for amount in &amounts {
+            line += &format!("  {:>14}", amount);
+        }
+        line.trim_end().to_string() + "\n"
+    };
+
+    let headers = dimensions
+        .iter()
+        .map(|dimension| {
+            let name = dimension.name();
+            name[..1].to_uppercase() + &name[1..]
+        })
+        .collect::<Vec<_>>();
+    let mut output = line(
+        &headers,
+        ["Invoices".to_string(), "Credit notes".to_string()],
+        ["Excl. VAT".to_string(), "VAT".to_string(), "Total".to_string()],
+    );
+
+    let mut totals: Vec<Row> = vec![];
+    for row in rows {
+        output += &line(
+            &row.group,
+            [row.invoices.to_string(), row.credit_notes.to_string()],
+            [row.total_excl_vat.to_string(), row.total_vat.to_string(), row.total.to_string()],
+        );
+        let total = match totals.iter_mut().find(|total| total.currency() == row.currency()) {
+            Some(total) => total,
+            None => {
+                totals.push(Row::new(vec![], row.currency()));
+                totals.last_mut().expect("just pushed")
+            },
+        };
+        total.merge(row)?;
+    }
+
+    // A total only adds something if there is more than one row to add up.
+    if rows.len() > totals.len() {
+        for total in totals {
+            let mut group = vec![String::new(); dimensions.len()];
+            if let Some(first) = group.first_mut() {
+                *first = "Total".to_string();
+            }
+            output += &line(
+                &group,
+                [total.invoices.to_string(), total.credit_notes.to_string()],
+                [
+                    total.total_excl_vat.to_string(),
+                    total.total_vat.to_string(),
+                    total.total.to_string(),
+                ],
+            );
+        }
+    }
+    Ok(output)
+}
+
+/// Writes `rows` as CSV, with plain decimal amounts and their currency.
+fn csv(dimensions: &[Dimension], rows: &[Row]) -> Result<String> {
+    let mut writer = Writer::from_writer(vec![]);
+    let mut headers = dimensions.iter().map(Dimension::name).collect::<Vec<_>>();
+    headers.extend(&[
+        "currency",
+        "invoices",
+        "credit_notes",
+        "total_excl_vat",
+        "total_vat",
+        "total",
+    ]);
+    writer.write_record(&headers).expect("writing to memory");
+    for row in rows {
+        let mut record = row.group.clone();
+        record.extend(vec![
+            row.currency().code().to_string(),
+            row.invoices.to_string(),
+            row.credit_notes.to_string(),
+            row.total_excl_vat.decimal(),
+            row.total_vat.decimal(),
+            row.total.decimal(),
+        ]);
+        writer.write_record(&record).expect("writing to memory");
+    }
+    let output = writer.into_inner().expect("writing to memory");
+    Ok(String::from_utf8(output).expect("CSV of strings"))
+}
+
+/// Writes `rows` as a JSON array of objects, with the dimensions as keys and
+/// the amounts as decimal strings, which unlike numbers are exact.
+fn json(dimensions: &[Dimension], rows: &[Row]) -> Result<String> {
+    let rows = rows
+        .iter()
+        .map(|row| {
+            let mut object = serde_json::Map::new();
+            for (dimension, key) in dimensions.iter().zip(&row.group) {
+                object.insert(dimension.name().to_string(), serde_json::Value::from(key.clone()));
+            }
+            object.insert("currency".to_string(), serde_json::Value::from(row.currency().code()));
+            object.insert("invoices".to_string(), serde_json::Value::from(row.invoices));
+            object.insert("credit_notes".to_string(), serde_json::Value::from(row.credit_notes));
+            let amounts = [
+                ("total_excl_vat", row.total_excl_vat),
+                ("total_vat", row.total_vat),
+                ("total", row.total),
+            ];
+            for &(name, amount) in &amounts {
+                object.insert(name.to_string(), serde_json::Value::from(amount.decimal()));
+            }
+            serde_json::Value::Object(object)
+        })
+        .collect::<Vec<_>>();
+    Ok(serde_json::to_string_pretty(&rows)?)
+}
+
+/// Writes the `rows` of a report by `dimensions` in `format`.
+pub fn format_report(dimensions: &[Dimension], rows: &[Row], format: Format) -> Result<String> {
+    match format {
+        Format::Table => table(dimensions, rows),
+        Format::Csv => csv(dimensions, rows),
+        Format::Json => json(dimensions, rows),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use csv::tests::tree;
+    use std::fs;
+
+    #[test]
+    fn totals_by_client_and_quarter() {
+        // Two clients by the same name, told apart by their id.
+        let clients = "id,name,street,city,country,vat,vatpolicy,partyid
+acme,Acme Corp,1 Main Street,Brussels,Belgium,,,1
+acme2,Acme Corp,5 Side Street,Antwerp,Belgium,,,2
+";
+        let invoices = "index,business,client,year,month,day
+1,me,acme,2018,1,31
+2,me,acme2,2018,2,28
+3,me,acme,2018,4,30
+";
+        let data = "index,price,services,attachment
+1,100.00,Consulting,
+2,50.00,Consulting,
+3,150.00,Consulting,
+3,50.00,Travel,
+";
+        let credit_notes = "index,business,client,year,month,day,credits
+1,me,acme,2018,3,15,2018-01
+";
+        let credit_note_data = "index,price,services,attachment
+1,30.00,Consulting,
+";
+        let files = [
+            ("invoices.toml", "vat-rate = 21\n"),
+            ("clients.csv", clients),
+            ("2018/invoices.csv", invoices),
+            ("2018/data.csv", data),
+            ("2018/credit-notes.csv", credit_notes),
+            ("2018/credit-note-data.csv", credit_note_data),
+        ];
+        let path = tree("report", &files);
+        let dimensions = [Dimension::Client, Dimension::Quarter];
+        let rows = report(&path, &dimensions);
+        let _ = fs::remove_dir_all(&path);
+        let rows = rows.unwrap();
+
+        assert_eq!(
+            format_report(&dimensions, &rows, Format::Csv).unwrap(),
+            "client,quarter,currency,invoices,credit_notes,total_excl_vat,total_vat,total
+acme,2018-Q1,EUR,1,1,70.00,14.70,84.70
+acme,2018-Q2,EUR,1,0,200.00,42.00,242.00
+acme2,2018-Q1,EUR,1,0,50.00,10.50,60.50
+"
+        );
+        let table = format_report(&dimensions, &rows, Format::Table).unwrap();
+        let total = table.lines().last().unwrap().split_whitespace().collect::<Vec<_>>();
+        assert_eq!(total, ["Total", "3", "1", "€320.00", "€67.20", "€387.20"]);
+    }
+
+    #[test]
+    fn unknown_dimension_and_format() {
+        let error = "week".parse::<Dimension>().unwrap_err().to_string();
+        assert!(error.contains("unknown report dimension `week`"), "{}", error);
+        assert!(error.contains("client, business, month, quarter, year"), "{}", error);
+        let error = "xml".parse::<Format>().unwrap_err().to_string();
+        assert!(error.contains("unknown report format `xml`"), "{}", error);
+    }
+}
@@ -0,0 +1,555 @@
+use config::Config;
+use csv::{self, CsvTree};
+use error::{Error, Result};
+use invoice::{
+    Amount, Business, Client, Currency, Date, Discount, Invoice, InvoiceData, InvoiceIndex, Kind,
+    LineItem, Money, Payment, Quantity, VatRate,
+};
+use ledger::Ledger;
+use sqlite::Database;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// An invoice as stored, referring to its business and client by id.
+#[derive(Clone, Debug)]
+pub struct InvoiceRecord {
+    pub business: String,
+    pub client: String,
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub currency: Option<Currency>,
+    /// The invoice a credit note cancels.
+    pub credits: Option<InvoiceIndex>,
+    pub discount: Option<Discount<Amount>>,
+}
+
+/// A line item as stored, before the currency of its invoice is known.
+#[derive(Clone, Debug)]
+pub struct LineItemRecord {
+    /// The file the item was read from, to report amounts that do not fit the
+    /// currency.
+    pub path: PathBuf,
+    pub line: Option<u64>,
+    pub description: String,
+    pub amount: Option<Amount>,
+    pub quantity: Option<Quantity>,
+    pub unit: Option<String>,
+    pub unit_price: Option<Amount>,
+    pub vat_rate: Option<VatRate>,
+    pub discount: Option<Discount<Amount>>,
+    pub attachment: Option<String>,
+}
+
+impl LineItemRecord {
+    fn in_currency(&self, currency: Currency) -> Result<LineItem> {
+        let invalid = |message: String| Error::InvalidRecord {
+            path: self.path.clone(),
+            line: self.line,
+            message,
+        };
+        let money = |amount: &Amount| {
+            amount.in_currency(currency).map_err(|error| invalid(error.to_string()))
+        };
+        let unit_price = match self.unit_price {
+            Some(ref unit_price) => Some(money(unit_price)?),
+            None => None,
+        };
+        let price = match (self.quantity, unit_price) {
+            (Some(quantity), Some(unit_price)) => Some(
+                quantity
+                    .times(unit_price)
+                    .ok_or_else(|| invalid("quantity times unit_price is too large".to_string()))?,
+            ),
+            _ => None,
+        };
+        let amount = match (&self.amount, price) {
+            (Some(amount), Some(price)) => {
+                let amount = money(amount)?;
+                if amount != price {
+                    let message =
+                        format!("amount {} is not quantity times unit_price, {}", amount, price);
+                    return Err(invalid(message));
+                }
+                amount
+            },
+            (Some(amount), None) => money(amount)?,
+            (None, Some(price)) => price,
+            (None, None) => {
+                return Err(invalid("missing amount, or quantity and unit_price".to_string()))
+            },
+        };
+        let discount = match self.discount {
+            Some(ref discount) => {
+                Some(discount.in_currency(currency).map_err(|error| invalid(error.to_string()))?)
+            },
+            None => None,
+        };
+        if let Some(Discount::Fixed(discount)) = discount {
+            if discount.amount > amount.amount {
+                return Err(invalid(format!("discount {} exceeds the amount", discount)));
+            }
+        }
+
+        Ok(LineItem {
+            description: self.description.clone(),
+            amount,
+            quantity: self.quantity,
+            unit: self.unit.clone(),
+            unit_price,
+            vat_rate: self.vat_rate,
+            discount,
+            attachment: self.attachment.clone(),
+        })
+    }
+}
+
+/// A payment as stored, before the currency of its invoice is known.
+#[derive(Clone, Debug)]
+pub struct PaymentRecord {
+    /// The file the payment was read from, to report amounts that do not fit
+    /// the currency.
+    pub path: PathBuf,
+    pub line: Option<u64>,
+    pub date: Date,
+    pub amount: Amount,
+    pub method: String,
+}
+
+impl PaymentRecord {
+    fn in_currency(&self, currency: Currency) -> Result<Payment> {
+        let amount = self.amount.in_currency(currency).map_err(|error| Error::InvalidRecord {
+            path: self.path.clone(),
+            line: self.line,
+            message: error.to_string(),
+        })?;
+        Ok(Payment {
+            date: self.date,
+            amount,
+            method: self.method.clone(),
+        })
+    }
+}
+
+/// A store of clients, businesses and invoices.
+pub trait DataSource {
+    fn clients(&self) -> Result<HashMap<String, Client>>;
+
+    fn businesses(&self) -> Result<HashMap<String, Business>>;
+
+    /// The years that have invoices or credit notes, in ascending order.
+    fn years(&self) -> Result<Vec<u16>>;
+
+    /// The documents of `kind` in `year`, by index.
+    fn invoices(&self, kind: Kind, year: u16) -> Result<HashMap<u8, InvoiceRecord>>;
+
+    /// The line items of the documents of `kind` in `year`, by index.
+    fn line_items(&self, kind: Kind, year: u16) -> Result<HashMap<u8, Vec<LineItemRecord>>>;
+
+    /// The payments received for the invoices of `year`, by index.
+    fn payments(&self, year: u16) -> Result<HashMap<u8, Vec<PaymentRecord>>>;
+
+    /// Stores a new document at `index`, which must not be taken yet.
+    fn add_invoice(
+        &self,
+        index: InvoiceIndex,
+        invoice: &InvoiceRecord,
+        items: &[LineItemRecord],
+    ) -> Result<()>;
+}
+
+/// Opens the data in the directory at `path`: a ledger file if there is one,
+/// else an SQLite database if there is one, or the CSV tree otherwise.
+pub fn open(path: &Path, config: &Config) -> Result<Box<dyn DataSource>> {
+    trace!("source::open");
+
+    if let Some((file, format)) = Ledger::find(path) {
+        return Ok(Box::new(Ledger::read(file, format, config)?));
+    }
+    if let Some(file) = Database::find(path) {
+        return Ok(Box::new(Database::open(file)?));
+    }
+    Ok(Box::new(CsvTree::new(path, config)))
+}
+
+/// The index after the last document of `kind` in `year` in `source`, or
+/// after the last line items, if some were written without their document.
+pub fn next_index(source: &dyn DataSource, kind: Kind, year: u16) -> Result<InvoiceIndex> {
+    let last = if source.years()?.contains(&year) {
+        let items = source.line_items(kind, year)?;
+        source.invoices(kind, year)?.keys().chain(items.keys()).max().cloned()
+    } else {
+        None
+    };
+    let index = match last {
+        Some(last) => last
+            .checked_add(1)
+            .ok_or_else(|| Error::Arguments(format!("no {} index left in {}", kind, year)))?,
+        None => 1,
+    };
+    Ok(InvoiceIndex {
+        kind,
+        year,
+        index,
+    })
+}
+
+/// Copies the CSV tree in the directory at `path` into a new SQLite
+/// database, `invoices.sqlite` in the same directory, and returns its path.
+pub fn import_csv(path: &Path) -> Result<PathBuf> {
+    trace!("import_csv");
+
+    let config = Config::read(path)?;
+    let database = path.join("invoices.sqlite");
+    Database::import(&database, &CsvTree::new(path, &config))?;
+    Ok(database)
+}
+
+/// The data needed to build every invoice, or every credit note, of a single
+/// year.
+pub struct Year {
+    kind: Kind,
+    year: u16,
+    config: Config,
+    clients: HashMap<String, Client>,
+    businesses: HashMap<String, Business>,
+    items: HashMap<u8, Vec<LineItemRecord>>,
+    invoices: HashMap<u8, InvoiceRecord>,
+    payments: HashMap<u8, Vec<PaymentRecord>>,
+    credit_notes: HashMap<u8, Vec<(InvoiceIndex, Money)>>,
+}
+
+/// The credit notes that cancel part of an invoice, with their totals, by the
+/// invoice they credit.
+pub type CreditNotes = HashMap<InvoiceIndex, Vec<(InvoiceIndex, Money)>>;
+
+/// Builds every credit note in `source`, of all years, once for all the
+/// invoices they may credit.
+pub fn credit_notes(source: &dyn DataSource, config: &Config) -> Result<CreditNotes> {
+    trace!("credit_notes");
+
+    let none = CreditNotes::new();
+    let mut credit_notes = CreditNotes::new();
+    for year in source.years()? {
+        let data = Year::from_source(source, config.clone(), Kind::CreditNote, year, &none)?;
+        for index in data.indices() {
+            let credit_note = data.invoice(index)?;
+            if let Some(credits) = credit_note.metadata.credits {
+                let total = credit_note.total()?;
+                credit_notes.entry(credits).or_default().push((credit_note.index, total));
+            }
+        }
+    }
+    Ok(credit_notes)
+}
+
+impl Year {
+    pub fn read(path: &Path, kind: Kind, year: u16) -> Result<Self> {
+        trace!("Year::read");
+
+        let config = Config::read(path)?;
+        let source = open(path, &config)?;
+        let credit_notes = match kind {
+            Kind::Invoice => credit_notes(&*source, &config)?,
+            Kind::CreditNote => CreditNotes::new(),
+        };
+        Self::from_source(&*source, config, kind, year, &credit_notes)
+    }
+
+    /// The documents of `kind` in `year` in `source`. Invoices are credited
+    /// by the matching entries of `credit_notes`, which callers read once
+    /// with `credit_notes()` for every year they build.
+    pub fn from_source(
+        source: &dyn DataSource,
+        config: Config,
+        kind: Kind,
+        year: u16,
+        credit_notes: &CreditNotes,
+    ) -> Result<Self> {
+        // Payments and credit notes only apply to invoices.
+        let (payments, credit_notes) = match kind {
+            Kind::Invoice => {
+                let credit_notes = credit_notes
+                    .iter()
+                    .filter(|&(credited, _)| {
+                        credited.kind == Kind::Invoice && credited.year == year
+                    })
+                    .map(|(credited, credit_notes)| (credited.index, credit_notes.clone()))
+                    .collect();
+                (source.payments(year)?, credit_notes)
+            },
+            Kind::CreditNote => (HashMap::new(), HashMap::new()),
+        };
+        Ok(Self {
+            kind,
+            year,
+            config,
+            clients: source.clients()?,
+            businesses: source.businesses()?,
+            items: source.line_items(kind, year)?,
+            invoices: source.invoices(kind, year)?,
+            payments,
+            credit_notes,
+        })
+    }
+
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    pub fn year(&self) -> u16 {
+        self.year
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// The indices of the documents of this year, in ascending order.
+    pub fn indices(&self) -> Vec<u8> {
+        let mut indices = self.invoices.keys().cloned().collect::<Vec<_>>();
+        indices.sort();
+        indices
+    }
+
+    pub fn invoice(&self, index: u8) -> Result<Invoice> {
+        let index = InvoiceIndex {
+            kind: self.kind,
+            year: self.year,
+            index,
+        };
+        let missing = |file| Error::MissingEntry {
+            file,
+            invoice: index,
+        };
+        let (invoices, data) = csv::files(self.kind);
+        let metadata = self.invoices.get(&index.index).ok_or_else(|| missing(invoices))?;
+        let items = self.items.get(&index.index).ok_or_else(|| missing(data))?;
+
+        let InvoiceRecord {
+            ref business,
+            ref client,
+            year,
+            month,
+            day,
+            currency,
+            credits,
+            ref discount,
+        } = *metadata;
+        let credits = match (self.kind, credits) {
+            (Kind::Invoice, _) => None,
+            (Kind::CreditNote, Some(credits)) => Some(credits),
+            (Kind::CreditNote, None) => {
+                return Err(Error::MissingCreditedInvoice {
+                    credit_note: index,
+                })
+            },
+        };
+        let policy = self.config.policy(business, client);
+        let business = self
+            .businesses
+            .get(business)
+            .ok_or_else(|| Error::MissingBusiness {
+                invoice: index,
+                business: business.clone(),
+            })?
+            .clone();
+        let client = self
+            .clients
+            .get(client)
+            .ok_or_else(|| Error::MissingClient {
+                invoice: index,
+                client: client.clone(),
+            })?
+            .clone();
+        // The invoice's own currency wins over the client's, which wins over
+        // the configured one.
+        let currency = currency.or(client.currency).unwrap_or(policy.currency);
+        let discount = match *discount {
+            Some(ref discount) => {
+                Some(discount.in_currency(currency).map_err(|error| Error::InvalidDiscount {
+                    invoice: index,
+                    message: error.to_string(),
+                })?)
+            },
+            None => None,
+        };
+        let metadata = InvoiceData {
+            business,
+            client,
+            date: Date::new(year, month, day).ok_or(Error::InvalidDate {
+                invoice: index,
+                year,
+                month,
+                day,
+            })?,
+            currency,
+            policy,
+            credits,
+            discount,
+        };
+
+        let payments = self.payments.get(&index.index).map_or(&[][..], Vec::as_slice);
+        let invoice = Invoice {
+            index,
+            metadata,
+            items: items.iter().map(|item| item.in_currency(currency)).collect::<Result<_>>()?,
+            payments: payments
+                .iter()
+                .map(|payment| payment.in_currency(currency))
+                .collect::<Result<_>>()?,
+            credit_notes: self.credit_notes.get(&index.index).cloned().unwrap_or_default(),
+        };
+        if let Some(Discount::Fixed(discount)) = invoice.metadata.discount {
+            if discount.amount > invoice.subtotal()?.amount {
+                return Err(Error::InvalidDiscount {
+                    invoice: index,
+                    message: format!("discount {} exceeds the subtotal", discount),
+                });
+            }
+        }
+        Ok(invoice)
+    }
+}
+
+pub fn read_invoice(path: &Path, index: InvoiceIndex) -> Result<Invoice> {
+    Year::read(path, index.kind, index.year)?.invoice(index.index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use csv::tests::tree;
+    use std::fs;
+
+    fn item(amount: Option<&str>, quantity: &str, unit_price: &str) -> LineItemRecord {
+        LineItemRecord {
+            path: PathBuf::from("data.csv"),
+            line: Some(2),
+            description: "Development".to_string(),
+            amount: amount.map(|amount| Amount::parse(amount, '.').unwrap()),
+            quantity: Some(quantity.parse().unwrap()),
+            unit: Some("h".to_string()),
+            unit_price: Some(Amount::parse(unit_price, '.').unwrap()),
+            vat_rate: None,
+            discount: None,
+            attachment: None,
+        }
+    }
+
+    #[test]
+    fn amount_of_quantity_and_unit_price() {
+        let euros = |cents| Money::new(cents, Currency::Eur);
+        let derived = item(None, "2.5", "80.00").in_currency(Currency::Eur).unwrap();
+        assert_eq!(derived.amount, euros(20000));
+        let given = item(Some("200"), "2.5", "80.00").in_currency(Currency::Eur).unwrap();
+        assert_eq!(given.amount, euros(20000));
+
+        let error = item(Some("250.00"), "2.5", "80.00").in_currency(Currency::Eur).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "data.csv:2: amount €250.00 is not quantity times unit_price, €200.00"
+        );
+        let error = item(None, "1000", "92233720368547.75").in_currency(Currency::Eur).unwrap_err();
+        assert_eq!(error.to_string(), "data.csv:2: quantity times unit_price is too large");
+    }
+
+    #[test]
+    fn credit_note_numbers_and_references() {
+        let invoices_csv = "index,business,client,year,month,day
+1,me,acme,2018,1,31
+2,me,acme,2018,2,28
+";
+        let data = "index,price,services,attachment
+1,100.00,Consulting,
+2,100.00,Consulting,
+";
+        let credit_notes = "index,business,client,year,month,day,credits
+1,me,acme,2018,3,1,2018-01
+2,me,acme,2018,3,2,2018-01
+";
+        let credit_note_data = "index,price,services,attachment
+1,40.00,Consulting,
+2,10.00,Consulting,
+";
+        let files = [
+            ("2018/invoices.csv", invoices_csv),
+            ("2018/data.csv", data),
+            ("2018/credit-notes.csv", credit_notes),
+            ("2018/credit-note-data.csv", credit_note_data),
+        ];
+        let path = tree("credit-notes", &files);
+        let source = CsvTree::new(&path, &Config::default());
+        let next_invoice = next_index(&source, Kind::Invoice, 2018);
+        let next_credit_note = next_index(&source, Kind::CreditNote, 2018);
+        let invoices = Year::read(&path, Kind::Invoice, 2018);
+        let credit_notes = Year::read(&path, Kind::CreditNote, 2018);
+        let _ = fs::remove_dir_all(&path);
+
+        let credit_note: InvoiceIndex = "2018-CN01".parse().unwrap();
+        assert_eq!(
+            (credit_note.kind, credit_note.year, credit_note.index),
+            (Kind::CreditNote, 2018, 1)
+        );
+        assert_eq!(
+            (credit_note.to_string(), credit_note.filename()),
+            ("2018-CN01".to_string(), "2018/CN01.html".to_string())
+        );
+        assert_eq!(next_invoice.unwrap().to_string(), "2018-03");
+        assert_eq!(next_credit_note.unwrap().to_string(), "2018-CN03");
+
+        let credited = Some("2018-01".parse().unwrap());
+        assert_eq!(credit_notes.unwrap().invoice(1).unwrap().metadata.credits, credited);
+        let invoices = invoices.unwrap();
+        let euros = |cents| Money::new(cents, Currency::Eur);
+        let cn = |index: &str| index.parse::<InvoiceIndex>().unwrap();
+        assert_eq!(
+            invoices.invoice(1).unwrap().credit_notes,
+            [(cn("2018-CN01"), euros(4000)), (cn("2018-CN02"), euros(1000))]
+        );
+        assert_eq!(invoices.invoice(2).unwrap().credit_notes, []);
+
+        let credit_notes = "index,business,client,year,month,day
+1,me,acme,2018,3,1
+";
+        let files = [
+            ("2018/invoices.csv", invoices_csv),
+            ("2018/data.csv", data),
+            ("2018/credit-notes.csv", credit_notes),
+            ("2018/credit-note-data.csv", credit_note_data),
+        ];
+        let path = tree("credit-notes-missing", &files);
+        let credit_notes = Year::read(&path, Kind::CreditNote, 2018);
+        let _ = fs::remove_dir_all(&path);
+        let error = credit_notes.unwrap().invoice(1).unwrap_err().to_string();
+        assert_eq!(error, "credit note 2018-CN01: missing the invoice it credits");
+    }
+
+    #[test]
+    fn currencies() {
+        let clients = "id,name,street,city,country,vat,vatpolicy,partyid,currency
+acme,Acme Corp,1 Main Street,New York,United States,,,1,USD
+";
+        let invoices = "index,business,client,year,month,day,currency
+1,me,acme,2018,1,31,
+2,me,acme,2018,2,28,EUR
+3,me,acme,2018,3,31,
+";
+        let data = "index,price,services,attachment
+1,$80.00,Consulting,
+2,80.00,Consulting,
+3,€80.00,Consulting,
+";
+        let files =
+            [("clients.csv", clients), ("2018/invoices.csv", invoices), ("2018/data.csv", data)];
+        let path = tree("currencies", &files);
+        let year = Year::read(&path, Kind::Invoice, 2018);
+        let _ = fs::remove_dir_all(&path);
+        let year = year.unwrap();
+
+        assert_eq!(year.invoice(1).unwrap().total().unwrap().currency, Currency::Usd);
+        assert_eq!(year.invoice(2).unwrap().total().unwrap().currency, Currency::Eur);
+        let error = year.invoice(3).unwrap_err().to_string();
+        assert!(error.ends_with("data.csv:4: amount in EUR on an invoice in USD"), "{}", error);
+    }
+}
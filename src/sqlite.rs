@@ -0,0 +1,645 @@
+use error::{Error, Result};
+use invoice::{Amount, Business, Client, ContactDetails, Currency, Discount, InvoiceIndex, Kind};
+use rusqlite::types::Type;
+use rusqlite::{self, Connection, OpenFlags, Row};
+use source::{DataSource, InvoiceRecord, LineItemRecord, PaymentRecord};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+const SCHEMA: &str = "
+    CREATE TABLE clients (
+        id TEXT PRIMARY KEY,
+        name TEXT NOT NULL,
+        street TEXT NOT NULL,
+        city TEXT NOT NULL,
+        country TEXT NOT NULL,
+        vat TEXT NOT NULL,
+        vatpolicy TEXT NOT NULL,
+        partyid INTEGER NOT NULL,
+        currency TEXT,
+        postal_code TEXT NOT NULL,
+        address_line2 TEXT NOT NULL,
+        region TEXT NOT NULL,
+        contact_name TEXT NOT NULL,
+        email TEXT NOT NULL,
+        phone TEXT NOT NULL,
+        registration_number TEXT NOT NULL
+    );
+    CREATE TABLE businesses (
+        id TEXT PRIMARY KEY,
+        name TEXT NOT NULL,
+        street TEXT NOT NULL,
+        city TEXT NOT NULL,
+        country TEXT NOT NULL,
+        vat TEXT NOT NULL,
+        bank TEXT NOT NULL,
+        iban TEXT NOT NULL,
+        bic TEXT NOT NULL,
+        postal_code TEXT NOT NULL,
+        address_line2 TEXT NOT NULL,
+        region TEXT NOT NULL,
+        contact_name TEXT NOT NULL,
+        email TEXT NOT NULL,
+        phone TEXT NOT NULL,
+        registration_number TEXT NOT NULL
+    );
+    CREATE TABLE invoices (
+        year INTEGER NOT NULL,
+        number INTEGER NOT NULL,
+        business TEXT NOT NULL,
+        client TEXT NOT NULL,
+        date_year INTEGER NOT NULL,
+        date_month INTEGER NOT NULL,
+        date_day INTEGER NOT NULL,
+        currency TEXT,
+        discount TEXT,
+        PRIMARY KEY (year, number)
+    );
+    CREATE TABLE line_items (
+        year INTEGER NOT NULL,
+        number INTEGER NOT NULL,
+        position INTEGER NOT NULL,
+        services TEXT NOT NULL,
+        amount TEXT,
+        quantity TEXT,
+        unit TEXT,
+        unit_price TEXT,
+        vat_rate TEXT,
+        attachment TEXT,
+        discount TEXT,
+        PRIMARY KEY (year, number, position)
+    );
+    CREATE TABLE credit_notes (
+        year INTEGER NOT NULL,
+        number INTEGER NOT NULL,
+        business TEXT NOT NULL,
+        client TEXT NOT NULL,
+        date_year INTEGER NOT NULL,
+        date_month INTEGER NOT NULL,
+        date_day INTEGER NOT NULL,
+        currency TEXT,
+        credits TEXT NOT NULL,
+        discount TEXT,
+        PRIMARY KEY (year, number)
+    );
+    CREATE TABLE credit_note_items (
+        year INTEGER NOT NULL,
+        number INTEGER NOT NULL,
+        position INTEGER NOT NULL,
+        services TEXT NOT NULL,
+        amount TEXT,
+        quantity TEXT,
+        unit TEXT,
+        unit_price TEXT,
+        vat_rate TEXT,
+        attachment TEXT,
+        discount TEXT,
+        PRIMARY KEY (year, number, position)
+    );
+    CREATE TABLE payments (
+        year INTEGER NOT NULL,
+        number INTEGER NOT NULL,
+        position INTEGER NOT NULL,
+        date TEXT NOT NULL,
+        amount TEXT NOT NULL,
+        method TEXT NOT NULL,
+        PRIMARY KEY (year, number, position)
+    );
+";
+
+/// The tables with the documents of `kind` and with their line items.
+fn tables(kind: Kind) -> (&'static str, &'static str) {
+    match kind {
+        Kind::Invoice => ("invoices", "line_items"),
+        Kind::CreditNote => ("credit_notes", "credit_note_items"),
+    }
+}
+
+/// Reads the text in column `index` of `row` with `FromStr`, if it is not
+/// `NULL`.
+fn parse<T>(row: &Row, index: usize) -> rusqlite::Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: ::std::error::Error + Send + Sync + 'static,
+{
+    let text: Option<String> = row.get(index)?;
+    convert(index, text.map(|text| text.parse()).transpose())
+}
+
+/// Reads the discount in column `index` of `row`, if it is not `NULL`.
+fn discount(row: &Row, index: usize) -> rusqlite::Result<Option<Discount<Amount>>> {
+    let text: Option<String> = row.get(index)?;
+    convert(index, text.map(|text| Discount::parse(&text, '.')).transpose())
+}
+
+/// Reports an error from parsing the text in column `index` as a conversion
+/// failure.
+fn convert<T, E>(index: usize, result: ::std::result::Result<T, E>) -> rusqlite::Result<T>
+where
+    E: ::std::error::Error + Send + Sync + 'static,
+{
+    result.map_err(|error| {
+        rusqlite::Error::FromSqlConversionFailure(index, Type::Text, Box::new(error))
+    })
+}
+
+fn text<T: Display>(value: Option<T>) -> Option<String> {
+    value.map(|value| value.to_string())
+}
+
+fn insert_invoice(
+    connection: &Connection,
+    index: InvoiceIndex,
+    invoice: &InvoiceRecord,
+) -> rusqlite::Result<()> {
+    let currency = text(invoice.currency);
+    let discount = text(invoice.discount.as_ref());
+    match index.kind {
+        Kind::Invoice => connection.execute(
+            "INSERT INTO invoices
+             (year, number, business, client, date_year, date_month, date_day, currency,
+              discount)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            rusqlite::params![
+                index.year,
+                index.index,
+                invoice.business,
+                invoice.client,
+                invoice.year,
+                invoice.month,
+                invoice.day,
+                currency,
+                discount,
+            ],
+        )?,
+        Kind::CreditNote => connection.execute(
+            "INSERT INTO credit_notes
+             (year, number, business, client, date_year, date_month, date_day, currency,
+              credits, discount)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            rusqlite::params![
+                index.year,
+                index.index,
+                invoice.business,
+                invoice.client,
+                invoice.year,
+                invoice.month,
+                invoice.day,
+                currency,
+                text(invoice.credits),
+                discount,
+            ],
+        )?,
+    };
+    Ok(())
+}
+
+fn insert_line_items(
+    connection: &Connection,
+    index: InvoiceIndex,
+    items: &[LineItemRecord],
+) -> rusqlite::Result<()> {
+    let query = format!(
+        "INSERT INTO {} VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        tables(index.kind).1
+    );
+    for (position, item) in items.iter().enumerate() {
+        connection.execute(
+            &query,
+            rusqlite::params![
+                index.year,
+                index.index,
+                position as i64,
+                item.description,
+                text(item.amount.as_ref()),
+                text(item.quantity),
+                item.unit,
+                text(item.unit_price.as_ref()),
+                text(item.vat_rate),
+                item.attachment,
+                text(item.discount.as_ref()),
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+/// A SQLite database, `invoices.sqlite` in the data directory.
+pub struct Database {
+    path: PathBuf,
+    connection: Connection,
+}
+
+impl Database {
+    /// The database in the data directory at `path`, if there is one.
+    pub fn find(path: &Path) -> Option<PathBuf> {
+        Some(path.join("invoices.sqlite")).filter(|file| file.is_file())
+    }
+
+    pub fn open(path: PathBuf) -> Result<Self> {
+        trace!("Database::open");
+
+        let flags = OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+        let connection = Connection::open_with_flags(&path, flags)
+            .map_err(|error| Error::sqlite(&path, error))?;
+        Ok(Self {
+            path,
+            connection,
+        })
+    }
+
+    /// Creates a database at `path` with everything in `source`. Refuses to
+    /// overwrite an existing file.
+    pub fn import(path: &Path, source: &dyn DataSource) -> Result<()> {
+        trace!("Database::import");
+
+        if path.exists() {
+            let error = io::Error::new(io::ErrorKind::AlreadyExists, "database already exists");
+            return Err(Error::io(path, error));
+        }
+        let result = Self::write(path, source);
+        if result.is_err() {
+            let _ = fs::remove_file(path);
+        }
+        result
+    }
+
+    fn write(path: &Path, source: &dyn DataSource) -> Result<()> {
+        let sqlite = |error| Error::sqlite(path, error);
+        let mut connection = Connection::open(path).map_err(sqlite)?;
+        let transaction = connection.transaction().map_err(sqlite)?;
+        transaction.execute_batch(SCHEMA).map_err(sqlite)?;
+
+        for (id, client) in source.clients()? {
+            let details = &client.details;
+            transaction
+                .execute(
+                    "INSERT INTO clients VALUES
+                     (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)",
+                    rusqlite::params![
+                        id,
+                        client.name,
+                        client.street,
+                        client.city,
+                        client.country,
+                        client.vat,
+                        client.vatpolicy,
+                        client.partyid,
+                        text(client.currency),
+                        details.postal_code,
+                        details.address_line2,
+                        details.region,
+                        details.contact_name,
+                        details.email,
+                        details.phone,
+                        details.registration_number,
+                    ],
+                )
+                .map_err(sqlite)?;
+        }
+
+        for (id, business) in source.businesses()? {
+            let details = &business.details;
+            transaction
+                .execute(
+                    "INSERT INTO businesses VALUES
+                     (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)",
+                    rusqlite::params![
+                        id,
+                        business.name,
+                        business.street,
+                        business.city,
+                        business.country,
+                        business.vat,
+                        business.bank,
+                        business.iban,
+                        business.bic,
+                        details.postal_code,
+                        details.address_line2,
+                        details.region,
+                        details.contact_name,
+                        details.email,
+                        details.phone,
+                        details.registration_number,
+                    ],
+                )
+                .map_err(sqlite)?;
+        }
+
+        for year in source.years()? {
+            for &kind in &Kind::ALL {
+                let index = |index| InvoiceIndex {
+                    kind,
+                    year,
+                    index,
+                };
+                for (number, invoice) in source.invoices(kind, year)? {
+                    insert_invoice(&transaction, index(number), &invoice).map_err(sqlite)?;
+                }
+                for (number, items) in source.line_items(kind, year)? {
+                    insert_line_items(&transaction, index(number), &items).map_err(sqlite)?;
+                }
+            }
+            for (number, payments) in source.payments(year)? {
+                for (position, payment) in payments.iter().enumerate() {
+                    transaction
+                        .execute(
+                            "INSERT INTO payments VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                            rusqlite::params![
+                                year,
+                                number,
+                                position as i64,
+                                payment.date.to_string(),
+                                payment.amount.to_string(),
+                                payment.method,
+                            ],
+                        )
+                        .map_err(sqlite)?;
+                }
+            }
+        }
+
+        transaction.commit().map_err(sqlite)
+    }
+
+    /// Runs `query` with `params` and collects the rows converted by `row`.
+    fn select<T, P, F>(&self, query: &str, params: P, row: F) -> Result<Vec<T>>
+    where
+        P: rusqlite::Params,
+        F: FnMut(&Row) -> rusqlite::Result<T>,
+    {
+        let sqlite = |error| Error::sqlite(&self.path, error);
+        let mut statement = self.connection.prepare(query).map_err(sqlite)?;
+        let rows = statement.query_map(params, row).map_err(sqlite)?;
+        rows.collect::<rusqlite::Result<_>>().map_err(sqlite)
+    }
+}
+
+impl DataSource for Database {
+    fn clients(&self) -> Result<HashMap<String, Client>> {
+        let clients = self.select("SELECT * FROM clients", [], |row| {
+            let client = Client {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                street: row.get(2)?,
+                city: row.get(3)?,
+                country: row.get(4)?,
+                vat: row.get(5)?,
+                vatpolicy: row.get(6)?,
+                partyid: row.get(7)?,
+                currency: parse::<Currency>(row, 8)?,
+                details: ContactDetails {
+                    postal_code: row.get(9)?,
+                    address_line2: row.get(10)?,
+                    region: row.get(11)?,
+                    contact_name: row.get(12)?,
+                    email: row.get(13)?,
+                    phone: row.get(14)?,
+                    registration_number: row.get(15)?,
+                },
+            };
+            Ok((client.id.clone(), client))
+        })?;
+        Ok(clients.into_iter().collect())
+    }
+
+    fn businesses(&self) -> Result<HashMap<String, Business>> {
+        let businesses = self.select("SELECT * FROM businesses", [], |row| {
+            let business = Business {
+                name: row.get(1)?,
+                street: row.get(2)?,
+                city: row.get(3)?,
+                country: row.get(4)?,
+                vat: row.get(5)?,
+                bank: row.get(6)?,
+                iban: row.get(7)?,
+                bic: row.get(8)?,
+                details: ContactDetails {
+                    postal_code: row.get(9)?,
+                    address_line2: row.get(10)?,
+                    region: row.get(11)?,
+                    contact_name: row.get(12)?,
+                    email: row.get(13)?,
+                    phone: row.get(14)?,
+                    registration_number: row.get(15)?,
+                },
+            };
+            Ok((row.get(0)?, business))
+        })?;
+        Ok(businesses.into_iter().collect())
+    }
+
+    fn years(&self) -> Result<Vec<u16>> {
+        let query = "SELECT year FROM invoices UNION SELECT year FROM credit_notes ORDER BY year";
+        self.select(query, [], |row| row.get(0))
+    }
+
+    fn invoices(&self, kind: Kind, year: u16) -> Result<HashMap<u8, InvoiceRecord>> {
+        let (table, credits) = match kind {
+            Kind::Invoice => ("invoices", "NULL"),
+            Kind::CreditNote => ("credit_notes", "credits"),
+        };
+        let query = format!(
+            "SELECT number, business, client, date_year, date_month, date_day, currency, {},
+                    discount
+             FROM {} WHERE year = ?1",
+            credits, table
+        );
+        let invoices = self.select(&query, [year], |row| {
+            let invoice = InvoiceRecord {
+                business: row.get(1)?,
+                client: row.get(2)?,
+                year: row.get(3)?,
+                month: row.get(4)?,
+                day: row.get(5)?,
+                currency: parse::<Currency>(row, 6)?,
+                credits: parse::<InvoiceIndex>(row, 7)?,
+                discount: discount(row, 8)?,
+            };
+            Ok((row.get(0)?, invoice))
+        })?;
+        Ok(invoices.into_iter().collect())
+    }
+
+    fn line_items(&self, kind: Kind, year: u16) -> Result<HashMap<u8, Vec<LineItemRecord>>> {
+        let query = format!(
+            "SELECT number, services, amount, quantity, unit, unit_price, vat_rate, attachment,
+                    discount
+             FROM {} WHERE year = ?1 ORDER BY number, position",
+            tables(kind).1
+        );
+        let rows = self.select(&query, [year], |row| {
+            let amount = |index| -> rusqlite::Result<Option<Amount>> {
+                let text: Option<String> = row.get(index)?;
+                convert(index, text.map(|text| Amount::parse(&text, '.')).transpose())
+            };
+            let item = LineItemRecord {
+                path: self.path.clone(),
+                line: None,
+                description: row.get(1)?,
+                amount: amount(2)?,
+                quantity: parse(row, 3)?,
+                unit: row.get(4)?,
+                unit_price: amount(5)?,
+                vat_rate: parse(row, 6)?,
+                discount: discount(row, 8)?,
+                attachment: row.get(7)?,
+            };
+            Ok((row.get(0)?, item))
+        })?;
+
+        let mut items = HashMap::new();
+        for (number, item) in rows {
+            items.entry(number).or_insert_with(Vec::new).push(item);
+        }
+        Ok(items)
+    }
+
+    fn payments(&self, year: u16) -> Result<HashMap<u8, Vec<PaymentRecord>>> {
+        let query = "SELECT number, date, amount, method FROM payments
+                     WHERE year = ?1 ORDER BY number, position";
+        let rows = self.select(query, [year], |row| {
+            let payment = PaymentRecord {
+                path: self.path.clone(),
+                line: None,
+                date: convert(1, row.get::<_, String>(1)?.parse())?,
+                amount: convert(2, Amount::parse(&row.get::<_, String>(2)?, '.'))?,
+                method: row.get(3)?,
+            };
+            Ok((row.get(0)?, payment))
+        })?;
+
+        let mut payments = HashMap::new();
+        for (number, payment) in rows {
+            payments.entry(number).or_insert_with(Vec::new).push(payment);
+        }
+        Ok(payments)
+    }
+
+    fn add_invoice(
+        &self,
+        index: InvoiceIndex,
+        invoice: &InvoiceRecord,
+        items: &[LineItemRecord],
+    ) -> Result<()> {
+        let sqlite = |error| Error::sqlite(&self.path, error);
+        let transaction = self.connection.unchecked_transaction().map_err(sqlite)?;
+        insert_invoice(&transaction, index, invoice).map_err(sqlite)?;
+        insert_line_items(&transaction, index, items).map_err(sqlite)?;
+        transaction.commit().map_err(sqlite)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::Config;
+    use csv::tests::tree;
+    use csv::CsvTree;
+    use source::import_csv;
+
+    /// Everything in `source` but the files and lines records were read from,
+    /// one line per record in a stable order.
+    fn contents(source: &dyn DataSource) -> Vec<String> {
+        let mut contents = vec![];
+        for (id, client) in source.clients().unwrap() {
+            contents.push(format!("client {}: {:?}", id, client));
+        }
+        for (id, business) in source.businesses().unwrap() {
+            contents.push(format!("business {}: {:?}", id, business));
+        }
+        for year in source.years().unwrap() {
+            for &kind in &Kind::ALL {
+                for (number, invoice) in source.invoices(kind, year).unwrap() {
+                    contents.push(format!("{} {}-{}: {:?}", kind, year, number, invoice));
+                }
+                for (number, items) in source.line_items(kind, year).unwrap() {
+                    for (position, item) in items.into_iter().enumerate() {
+                        let item = (
+                            item.description,
+                            item.amount,
+                            item.quantity,
+                            item.unit,
+                            item.unit_price,
+                            item.vat_rate,
+                            item.discount,
+                            item.attachment,
+                        );
+                        contents.push(format!(
+                            "{} {}-{} item {}: {:?}",
+                            kind, year, number, position, item
+                        ));
+                    }
+                }
+            }
+            for (number, payments) in source.payments(year).unwrap() {
+                for (position, payment) in payments.into_iter().enumerate() {
+                    let payment = (payment.date, payment.amount, payment.method);
+                    contents
+                        .push(format!("payment {}-{} {}: {:?}", year, number, position, payment));
+                }
+            }
+        }
+        contents.sort();
+        contents
+    }
+
+    #[test]
+    fn import_round_trip() {
+        let clients = "id,name,street,city,country,vat,vatpolicy,partyid,currency,postal_code,email
+acme,Acme Corp,1 Main Street,Brussels,Belgium,BE0123456749,,1,,1000,billing@acme.example
+globex,Globex,3 Elm Street,Springfield,USA,,,2,USD,,
+";
+        let invoices = "index,business,client,year,month,day,currency,discount
+1,me,acme,2018,1,31,,10%
+2,me,globex,2018,2,28,,\"25,00\"
+3,me,acme,2018,3,31,GBP,
+";
+        let data =
+            "index,amount,price,quantity,unit,unit_price,vat_rate,discount,services,attachment
+1,,,42.5,h,\"80,00\",21%,5%,Development,timesheet.pdf
+1,1500,,,,,,,Hosting,
+2,,\"1.000,50\",,,,0%,\"0,50\",Licence,
+3,,\"99,99\",,,,,,Support,receipts/march 1.pdf
+";
+        let credit_notes = "index,business,client,year,month,day,credits
+1,me,acme,2018,4,1,2018-01
+";
+        let credit_note_data = "index,price,services,attachment
+1,\"-100,00\",Development,
+";
+        let payments = "index,date,amount,method
+1,2018-02-15,\"1000,00\",transfer
+1,2018-02-20,\"2617,05\",
+3,2018-04-10,\"99,99\",card
+";
+        let path = tree(
+            "import",
+            &[
+                ("invoices.toml", "decimal-separator = \",\"\n"),
+                ("clients.csv", clients),
+                ("2018/invoices.csv", invoices),
+                ("2018/data.csv", data),
+                ("2018/credit-notes.csv", credit_notes),
+                ("2018/credit-note-data.csv", credit_note_data),
+                ("2018/payments.csv", payments),
+            ],
+        );
+        let config = Config::read(&path).unwrap();
+
+        let database = import_csv(&path).unwrap();
+        let expected = contents(&CsvTree::new(&path, &config));
+        let actual = contents(&Database::open(database.clone()).unwrap());
+        let again = Database::import(&database, &CsvTree::new(&path, &config));
+        let _ = fs::remove_dir_all(&path);
+
+        assert_eq!(actual, expected);
+        assert_eq!(expected.len(), 2 + 1 + 3 + 4 + 1 + 1 + 3);
+        assert!(expected.iter().any(|line| line.contains("credits: Some(")));
+        assert!(again.is_err());
+    }
+}
@@ -0,0 +1,342 @@
+use chrono::{Datelike, NaiveDate};
+use config::Config;
+use csv_parser::{Reader, StringRecord};
+use error::{Error, Result};
+use invoice::{Date, InvoiceIndex, Kind, Quantity};
+use source::{self, InvoiceRecord, LineItemRecord};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// How a time log writes durations.
+#[derive(Copy, Clone, Debug)]
+enum Duration {
+    /// Decimal hours, such as `1.5`.
+    Hours,
+    /// Hours, minutes and seconds, such as `01:30:00`.
+    Clock,
+}
+
+/// The columns of one kind of time log.
+struct Format {
+    date: &'static str,
+    client: &'static str,
+    project: &'static str,
+    duration: &'static str,
+    kind: Duration,
+}
+
+/// Our own time log, followed by the detailed CSV exports of Clockify and
+/// Toggl.
+const FORMATS: [Format; 3] = [
+    Format {
+        date: "date",
+        client: "client",
+        project: "project",
+        duration: "hours",
+        kind: Duration::Hours,
+    },
+    Format {
+        date: "Start Date",
+        client: "Client",
+        project: "Project",
+        duration: "Duration (decimal)",
+        kind: Duration::Hours,
+    },
+    Format {
+        date: "Start date",
+        client: "Client",
+        project: "Project",
+        duration: "Duration",
+        kind: Duration::Clock,
+    },
+];
+
+/// A single entry of a time log.
+struct Entry {
+    line: Option<u64>,
+    date: Date,
+    client: String,
+    project: String,
+    /// The time worked in milliseconds, which hold both thousandths of an
+    /// hour and whole seconds exactly.
+    milliseconds: i64,
+}
+
+/// Parses `2018-03-31` or, as Clockify writes it, `03/31/2018`.
+fn parse_date(text: &str) -> Option<Date> {
+    text.parse().ok().or_else(|| {
+        let date = NaiveDate::parse_from_str(text.trim(), "%m/%d/%Y").ok()?;
+        Date::new(date.year() as u16, date.month() as u8, date.day() as u8)
+    })
+}
+
+/// Parses a duration written as `kind` into milliseconds.
+fn parse_milliseconds(text: &str, kind: Duration) -> Option<i64> {
+    let text = text.trim();
+    match kind {
+        Duration::Hours => {
+            let hours = text.replace(',', ".").parse::<Quantity>().ok()?;
+            hours.0.checked_mul(3600).filter(|&milliseconds| milliseconds >= 0)
+        },
+        Duration::Clock => {
+            let parts = text.split(':').map(|part| part.parse::<u32>().ok()).collect::<Vec<_>>();
+            match parts[..] {
+                [Some(hours), Some(minutes), Some(seconds)] if minutes < 60 && seconds < 60 => {
+                    let seconds = i64::from(hours) * 3600 + i64::from(minutes * 60 + seconds);
+                    Some(seconds * 1000)
+                },
+                _ => None,
+            }
+        },
+    }
+}
+
+/// Reads the time log at `path`, recognizing its format by its columns.
+fn read_log(path: &Path) -> Result<Vec<Entry>> {
+    trace!("read_log");
+
+    let invalid = |line, message| Error::InvalidRecord {
+        path: path.to_owned(),
+        line,
+        message,
+    };
+
+    let mut reader = Reader::from_path(path).map_err(|error| Error::csv(path, error))?;
+    let headers = reader.headers().map_err(|error| Error::csv(path, error))?.clone();
+    let column = |name| headers.iter().position(|header| header == name);
+    let (format, columns) = FORMATS
+        .iter()
+        .filter_map(|format| {
+            let columns = [
+                column(format.date)?,
+                column(format.client)?,
+                column(format.project)?,
+                column(format.duration)?,
+            ];
+            Some((format, columns))
+        })
+        .next()
+        .ok_or_else(|| {
+            let message = "expected the columns date, client, project and hours, \
+                           or a Clockify or Toggl export";
+            invalid(Some(1), message.to_string())
+        })?;
+
+    let mut entries = vec![];
+    for record in reader.records() {
+        let record: StringRecord = record.map_err(|error| Error::csv(path, error))?;
+        let line = record.position().map(|position| position.line());
+        let field = |index: usize| record.get(columns[index]).unwrap_or("");
+
+        let date = parse_date(field(0))
+            .ok_or_else(|| invalid(line, format!("invalid date `{}`", field(0))))?;
+        let milliseconds = parse_milliseconds(field(3), format.kind)
+            .ok_or_else(|| invalid(line, format!("invalid duration `{}`", field(3))))?;
+        entries.push(Entry {
+            line,
+            date,
+            client: field(1).trim().to_string(),
+            project: field(2).trim().to_string(),
+            milliseconds,
+        });
+    }
+    Ok(entries)
+}
+
+/// Turns the hours in the time log at `log` into new invoices dated `date`,
+/// one per client, with a line item per project and month. Each invoice gets
+/// the next free index of its year. Returns the indices of the new invoices.
+pub fn import_hours(
+    path: &Path,
+    log: &Path,
+    business: Option<&str>,
+    date: Date,
+) -> Result<Vec<InvoiceIndex>> {
+    trace!("import_hours");
+
+    let config = Config::read(path)?;
+    let source = source::open(path, &config)?;
+    let clients = source.clients()?;
+    let businesses = source.businesses()?;
+
+    // Without --business, there must be only one to choose from.
+    let ids = businesses.keys().map(String::as_str).collect::<Vec<_>>();
+    let business = match (business, &ids[..]) {
+        (Some(business), _) if businesses.contains_key(business) => business.to_string(),
+        (Some(business), _) => {
+            return Err(Error::Arguments(format!("unknown business `{}`", business)))
+        },
+        (None, &[business]) => business.to_string(),
+        (None, _) => return Err(Error::Arguments("missing --business".to_string())),
+    };
+
+    // The milliseconds worked per client, and per month and project, along with a
+    // date in that month.
+    let mut hours = BTreeMap::new();
+    for entry in read_log(log)? {
+        let name = entry.client.to_lowercase();
+        let client = clients
+            .keys()
+            .find(|&id| *id == entry.client)
+            .or_else(|| {
+                clients
+                    .iter()
+                    .find(|(_, client)| client.name.to_lowercase() == name)
+                    .map(|(id, _)| id)
+            })
+            .ok_or_else(|| Error::InvalidRecord {
+                path: log.to_owned(),
+                line: entry.line,
+                message: format!("unknown client `{}`", entry.client),
+            })?;
+        let key = (entry.date.year(), entry.date.month(), entry.project);
+        let months = hours.entry(client.clone()).or_insert_with(BTreeMap::new);
+        months.entry(key).or_insert((entry.date, 0)).1 += entry.milliseconds;
+    }
+
+    let year = date.year();
+    let mut next = Some(source::next_index(&*source, Kind::Invoice, year)?);
+
+    let mut invoices = vec![];
+    for (client, months) in hours {
+        let policy = config.policy(&business, &client);
+        let rate = policy.hourly_rate.ok_or_else(|| Error::InvalidRecord {
+            path: path.join("invoices.toml"),
+            line: None,
+            message: format!("no hourly-rate for client `{}`", client),
+        })?;
+        let items = months
+            .into_iter()
+            .map(|((year, _, project), (date, milliseconds))| {
+                let quantity = Quantity((milliseconds + 1800) / 3600);
+                let period = format!("{} {} ({} h)", date.month_name(), year, quantity);
+                LineItemRecord {
+                    path: log.to_owned(),
+                    line: None,
+                    description: if project.is_empty() {
+                        period
+                    } else {
+                        format!("{}, {}", project, period)
+                    },
+                    amount: None,
+                    quantity: Some(quantity),
+                    unit: Some("h".to_string()),
+                    unit_price: Some(rate.clone()),
+                    vat_rate: None,
+                    discount: None,
+                    attachment: None,
+                }
+            })
+            .collect::<Vec<_>>();
+        let index =
+            next.ok_or_else(|| Error::Arguments(format!("no invoice index left in {}", year)))?;
+        next = index.index.checked_add(1).map(|next| InvoiceIndex {
+            index: next,
+            ..index
+        });
+
+        let record = InvoiceRecord {
+            business: business.clone(),
+            client,
+            year,
+            month: date.month(),
+            day: date.day(),
+            currency: None,
+            credits: None,
+            discount: None,
+        };
+        invoices.push((index, record, items));
+    }
+
+    // Only write once every invoice could be built.
+    let mut indices = vec![];
+    for (index, record, items) in invoices {
+        source.add_invoice(index, &record, &items)?;
+        indices.push(index);
+    }
+    Ok(indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use csv::tests::tree;
+    use std::fs;
+
+    /// The date, client, project and milliseconds of each entry in `log`.
+    fn entries(name: &str, log: &str) -> Result<Vec<(String, String, String, i64)>> {
+        let path = tree(name, &[("log.csv", log)]);
+        let entries = read_log(&path.join("log.csv"));
+        let _ = fs::remove_dir_all(&path);
+        Ok(entries?
+            .into_iter()
+            .map(|entry| (entry.date.to_string(), entry.client, entry.project, entry.milliseconds))
+            .collect())
+    }
+
+    fn entry(
+        date: &str,
+        client: &str,
+        project: &str,
+        milliseconds: i64,
+    ) -> (String, String, String, i64) {
+        (date.to_string(), client.to_string(), project.to_string(), milliseconds)
+    }
+
+    #[test]
+    fn log_formats() {
+        let log = "date,client,project,hours,note
+2018-03-01,acme,Development,1.5,Planning
+2018-03-02, Acme Corp ,,\"0,25\",
+";
+        assert_eq!(
+            entries("log-own", log).unwrap(),
+            [
+                entry("2018-03-01", "acme", "Development", 5_400_000),
+                entry("2018-03-02", "Acme Corp", "", 900_000),
+            ]
+        );
+
+        let log = "Project,Client,Description,Start Date,Duration (h),Duration (decimal)
+Development,Acme Corp,Planning,03/31/2018,01:30:00,1.50
+";
+        assert_eq!(
+            entries("log-clockify", log).unwrap(),
+            [entry("2018-03-31", "Acme Corp", "Development", 5_400_000)]
+        );
+
+        let log = "User,Client,Project,Description,Start date,Start time,Duration
+Jane,Acme Corp,Development,Planning,2018-03-31,09:00:00,01:30:15
+Jane,Acme Corp,Development,Review,2018-03-31,14:00:00,100:00:00
+";
+        assert_eq!(
+            entries("log-toggl", log).unwrap(),
+            [
+                entry("2018-03-31", "Acme Corp", "Development", 5_415_000),
+                entry("2018-03-31", "Acme Corp", "Development", 360_000_000),
+            ]
+        );
+
+        let error = entries("log-unknown", "day,customer,hours\n").err().unwrap().to_string();
+        assert!(error.contains("log.csv:1: expected the columns date, client"), "{}", error);
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_milliseconds("0.001", Duration::Hours), Some(3600));
+        assert_eq!(parse_milliseconds(" 42,5 ", Duration::Hours), Some(153_000_000));
+        assert_eq!(parse_milliseconds("00:00:59", Duration::Clock), Some(59_000));
+        for hours in &["-1", "1.0001", "1e3", "", "NaN", "inf"] {
+            assert_eq!(parse_milliseconds(hours, Duration::Hours), None, "{}", hours);
+        }
+        for clock in &["01:60:00", "01:00:60", "-01:00:00", "01:-5:00", "01:30", "1.5"] {
+            assert_eq!(parse_milliseconds(clock, Duration::Clock), None, "{}", clock);
+        }
+
+        let log = "date,client,project,hours,note
+2018-03-01,acme,Development,1.2345,
+";
+        let error = entries("log-invalid", log).err().unwrap().to_string();
+        assert!(error.ends_with("log.csv:2: invalid duration `1.2345`"), "{}", error);
+    }
+}
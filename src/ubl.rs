@@ -0,0 +1,404 @@
+use en16931::{Allowance, Document, Line, Party, TaxCategory};
+use error::{Error, Result};
+use invoice::{Invoice, Kind, Money};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use xml::XmlWriter;
+
+const CUSTOMIZATION_ID: &str =
+    "urn:cen.eu:en16931:2017#compliant#urn:fdc:peppol.eu:2017:poacc:billing:3.0";
+const PROFILE_ID: &str = "urn:fdc:peppol.eu:2017:poacc:billing:01:1.0";
+const CAC: &str = "urn:oasis:names:specification:ubl:schema:xsd:CommonAggregateComponents-2";
+const CBC: &str = "urn:oasis:names:specification:ubl:schema:xsd:CommonBasicComponents-2";
+
+/// The MIME types of the files Peppol allows to be embedded, by extension.
+const MIME_TYPES: &[(&str, &str)] = &[
+    ("csv", "text/csv"),
+    ("jpeg", "image/jpeg"),
+    ("jpg", "image/jpeg"),
+    ("ods", "application/vnd.oasis.opendocument.spreadsheet"),
+    ("pdf", "application/pdf"),
+    ("png", "image/png"),
+    ("xlsx", "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"),
+];
+
+/// The electronic address of `party`, which Peppol requires.
+fn endpoint<'p>(party: &'p Party) -> ::std::result::Result<&'p (&'static str, String), String> {
+    party.endpoint.as_ref().ok_or_else(|| {
+        format!("{} `{}` needs a European VAT number or an email address", party.role, party.name)
+    })
+}
+
+fn write_party(w: &mut XmlWriter, party: &Party, endpoint: &(&str, String)) {
+    w.element("cac:Party", &[], |w| {
+        w.text("cbc:EndpointID", &[("schemeID", endpoint.0)], &endpoint.1);
+        w.element("cac:PartyName", &[], |w| w.text("cbc:Name", &[], party.name));
+        w.element("cac:PostalAddress", &[], |w| {
+            w.optional("cbc:StreetName", party.street);
+            w.optional("cbc:AdditionalStreetName", &party.details.address_line2);
+            w.optional("cbc:CityName", party.city);
+            w.optional("cbc:PostalZone", &party.details.postal_code);
+            w.optional("cbc:CountrySubentity", &party.details.region);
+            w.element("cac:Country", &[], |w| w.text("cbc:IdentificationCode", &[], party.country));
+        });
+        if !party.vat.is_empty() {
+            w.element("cac:PartyTaxScheme", &[], |w| {
+                w.text("cbc:CompanyID", &[], &party.vat);
+                tax_scheme(w);
+            });
+        }
+        w.element("cac:PartyLegalEntity", &[], |w| {
+            w.text("cbc:RegistrationName", &[], party.name);
+            w.optional("cbc:CompanyID", &party.details.registration_number);
+        });
+        if party.has_contact() {
+            let details = party.details;
+            w.element("cac:Contact", &[], |w| {
+                w.optional("cbc:Name", &details.contact_name);
+                w.optional("cbc:Telephone", &details.phone);
+                w.optional("cbc:ElectronicMail", &details.email);
+            });
+        }
+    });
+}
+
+fn write_category(w: &mut XmlWriter, name: &str, category: &TaxCategory) {
+    w.element(name, &[], |w| {
+        w.text("cbc:ID", &[], category.code);
+        w.text("cbc:Percent", &[], &category.percent());
+        if let Some((code, reason)) = category.exemption {
+            w.text("cbc:TaxExemptionReasonCode", &[], code);
+            w.text("cbc:TaxExemptionReason", &[], reason);
+        }
+        tax_scheme(w);
+    });
+}
+
+fn tax_scheme(w: &mut XmlWriter) {
+    w.element("cac:TaxScheme", &[], |w| w.text("cbc:ID", &[], "VAT"));
+}
+
+fn amount(w: &mut XmlWriter, name: &str, money: Money) {
+    w.text(name, &[("currencyID", money.currency.code())], &money.decimal());
+}
+
+fn write_allowance(w: &mut XmlWriter, allowance: &Allowance) {
+    w.element("cac:AllowanceCharge", &[], |w| {
+        w.text("cbc:ChargeIndicator", &[], "false");
+        w.text("cbc:AllowanceChargeReasonCode", &[], Allowance::DISCOUNT);
+        w.text("cbc:AllowanceChargeReason", &[], &allowance.reason);
+        amount(w, "cbc:Amount", allowance.amount);
+        if let Some(ref category) = allowance.category {
+            write_category(w, "cac:TaxCategory", category);
+        }
+    });
+}
+
+/// A file embedded in the invoice.
+struct Attachment {
+    filename: String,
+    mime_type: &'static str,
+    content: String,
+}
+
+impl Attachment {
+    fn read(path: &Path) -> Result<::std::result::Result<Self, String>> {
+        let filename = path.file_name().map(|name| name.to_string_lossy().into_owned());
+        let filename = filename.unwrap_or_default();
+        let extension = path.extension().map(|extension| extension.to_string_lossy());
+        let extension = extension.unwrap_or_default().to_lowercase();
+        let mime_type = match MIME_TYPES.iter().find(|&&(known, _)| known == extension) {
+            Some(&(_, mime_type)) => mime_type,
+            None => return Ok(Err(format!("cannot embed `{}` in an e-invoice", filename))),
+        };
+        let mut content = vec![];
+        File::open(path)
+            .and_then(|mut file| file.read_to_end(&mut content))
+            .map_err(|error| Error::io(path, error))?;
+        Ok(Ok(Self {
+            filename,
+            mime_type,
+            content: base64::encode(content),
+        }))
+    }
+
+    fn write(&self, w: &mut XmlWriter) {
+        w.element("cac:AdditionalDocumentReference", &[], |w| {
+            w.text("cbc:ID", &[], &self.filename);
+            w.element("cac:Attachment", &[], |w| {
+                let attributes = [("mimeCode", self.mime_type), ("filename", &*self.filename)];
+                w.text("cbc:EmbeddedDocumentBinaryObject", &attributes, &self.content);
+            });
+        });
+    }
+}
+
+fn write_line(w: &mut XmlWriter, kind: Kind, line: &Line) {
+    let (name, quantity_name) = match kind {
+        Kind::Invoice => ("cac:InvoiceLine", "cbc:InvoicedQuantity"),
+        Kind::CreditNote => ("cac:CreditNoteLine", "cbc:CreditedQuantity"),
+    };
+    w.element(name, &[], |w| {
+        w.text("cbc:ID", &[], &line.id);
+        w.text(quantity_name, &[("unitCode", line.unit)], &line.quantity);
+        amount(w, "cbc:LineExtensionAmount", line.net_amount);
+        if let Some(ref allowance) = line.allowance {
+            write_allowance(w, allowance);
+        }
+        w.element("cac:Item", &[], |w| {
+            w.text("cbc:Name", &[], &line.item.description);
+            write_category(w, "cac:ClassifiedTaxCategory", &line.category);
+        });
+        w.element("cac:Price", &[], |w| {
+            amount(w, "cbc:PriceAmount", line.price);
+            if let Some(ref base_quantity) = line.base_quantity {
+                w.text("cbc:BaseQuantity", &[("unitCode", line.unit)], base_quantity);
+            }
+        });
+    });
+}
+
+/// Writes `invoice` as a Peppol BIS Billing 3.0 UBL invoice or credit note,
+/// embedding the PDF at `pdf` and the files in `attachments`.
+pub fn generate_ubl(invoice: &Invoice, pdf: &Path, attachments: &[PathBuf]) -> Result<String> {
+    trace!("generate_ubl");
+
+    let invalid = |message| Error::EInvoice {
+        invoice: invoice.index,
+        message,
+    };
+
+    let document = Document::new(invoice)?;
+    let mut embedded = vec![];
+    for path in Some(pdf).into_iter().chain(attachments.iter().map(PathBuf::as_path)) {
+        embedded.push(Attachment::read(path)?.map_err(invalid)?);
+    }
+
+    let (root, namespace, type_code_name) = match document.kind() {
+        Kind::Invoice => (
+            "Invoice",
+            "urn:oasis:names:specification:ubl:schema:xsd:Invoice-2",
+            "cbc:InvoiceTypeCode",
+        ),
+        Kind::CreditNote => (
+            "CreditNote",
+            "urn:oasis:names:specification:ubl:schema:xsd:CreditNote-2",
+            "cbc:CreditNoteTypeCode",
+        ),
+    };
+
+    let seller_endpoint = endpoint(&document.seller).map_err(invalid)?;
+    let buyer_endpoint = endpoint(&document.buyer).map_err(invalid)?;
+
+    let mut w = XmlWriter::new();
+    let attributes = [("xmlns", namespace), ("xmlns:cac", CAC), ("xmlns:cbc", CBC)];
+    w.element(root, &attributes, |w| {
+        w.text("cbc:CustomizationID", &[], CUSTOMIZATION_ID);
+        w.text("cbc:ProfileID", &[], PROFILE_ID);
+        w.text("cbc:ID", &[], &document.number);
+        w.text("cbc:IssueDate", &[], &document.issue_date().to_string());
+        if let Some(ref payment) = document.payment {
+            w.text("cbc:DueDate", &[], &payment.due_date.to_string());
+        }
+        w.text(type_code_name, &[], document.type_code);
+        w.text("cbc:DocumentCurrencyCode", &[], document.currency());
+        w.text("cbc:BuyerReference", &[], &document.buyer_reference);
+        if let Some(ref preceding) = document.preceding_invoice {
+            w.element("cac:BillingReference", &[], |w| {
+                w.element("cac:InvoiceDocumentReference", &[], |w| {
+                    w.text("cbc:ID", &[], preceding)
+                });
+            });
+        }
+        for attachment in &embedded {
+            attachment.write(w);
+        }
+        w.element("cac:AccountingSupplierParty", &[], |w| {
+            write_party(w, &document.seller, seller_endpoint)
+        });
+        w.element("cac:AccountingCustomerParty", &[], |w| {
+            write_party(w, &document.buyer, buyer_endpoint)
+        });
+        if let Some(ref payment) = document.payment {
+            w.element("cac:PaymentMeans", &[], |w| {
+                w.text("cbc:PaymentMeansCode", &[("name", "Credit transfer")], payment.means_code);
+                w.text("cbc:PaymentID", &[], &payment.reference);
+                w.element("cac:PayeeFinancialAccount", &[], |w| {
+                    w.text("cbc:ID", &[], &payment.iban);
+                    w.text("cbc:Name", &[], payment.account_name);
+                    if !payment.bic.is_empty() {
+                        w.element("cac:FinancialInstitutionBranch", &[], |w| {
+                            w.text("cbc:ID", &[], payment.bic)
+                        });
+                    }
+                });
+            });
+            w.element("cac:PaymentTerms", &[], |w| w.text("cbc:Note", &[], &payment.terms));
+        }
+        for allowance in &document.allowances {
+            write_allowance(w, allowance);
+        }
+        w.element("cac:TaxTotal", &[], |w| {
+            amount(w, "cbc:TaxAmount", document.tax_total);
+            for tax in &document.taxes {
+                w.element("cac:TaxSubtotal", &[], |w| {
+                    amount(w, "cbc:TaxableAmount", tax.base);
+                    amount(w, "cbc:TaxAmount", tax.amount);
+                    write_category(w, "cac:TaxCategory", &tax.category);
+                });
+            }
+        });
+        w.element("cac:LegalMonetaryTotal", &[], |w| {
+            amount(w, "cbc:LineExtensionAmount", document.line_total);
+            amount(w, "cbc:TaxExclusiveAmount", document.tax_basis_total);
+            amount(w, "cbc:TaxInclusiveAmount", document.grand_total);
+            if let Some(allowance_total) = document.allowance_total {
+                amount(w, "cbc:AllowanceTotalAmount", allowance_total);
+            }
+            amount(w, "cbc:PayableAmount", document.grand_total);
+        });
+        for line in &document.lines {
+            write_line(w, document.kind(), line);
+        }
+    });
+    Ok(w.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use en16931::tests::invoice;
+    use invoice::{Currency, Discount};
+    use std::{env, fs, process};
+
+    /// The UBL of `invoice`, embedding a PDF, without the indentation.
+    fn ubl(invoice: &Invoice, attachments: &[PathBuf]) -> Result<String> {
+        let pdf = env::temp_dir().join(format!("ubl-{}.pdf", process::id()));
+        fs::write(&pdf, b"%PDF").unwrap();
+        let ubl = generate_ubl(invoice, &pdf, attachments);
+        let _ = fs::remove_file(&pdf);
+        Ok(ubl?.lines().map(str::trim).collect())
+    }
+
+    fn category(code: &str, percent: &str) -> String {
+        format!(
+            "<cbc:ID>{}</cbc:ID><cbc:Percent>{}</cbc:Percent>\
+<cac:TaxScheme><cbc:ID>VAT</cbc:ID></cac:TaxScheme>",
+            code, percent
+        )
+    }
+
+    #[test]
+    fn invoice_with_two_rates() {
+        let ubl = ubl(&invoice(&[(10000, "21"), (5000, "6")], None), &[]).unwrap();
+        let expected = [
+            "<Invoice xmlns=\"urn:oasis:names:specification:ubl:schema:xsd:Invoice-2\"",
+            "<cbc:ID>2018-01</cbc:ID><cbc:IssueDate>2018-01-31</cbc:IssueDate>\
+<cbc:DueDate>2018-03-02</cbc:DueDate><cbc:InvoiceTypeCode>380</cbc:InvoiceTypeCode>\
+<cbc:DocumentCurrencyCode>EUR</cbc:DocumentCurrencyCode>",
+            "<cbc:EmbeddedDocumentBinaryObject mimeCode=\"application/pdf\" \
+filename=\"ubl-",
+            ">JVBERg==</cbc:EmbeddedDocumentBinaryObject>",
+            "<cac:AccountingSupplierParty><cac:Party>\
+<cbc:EndpointID schemeID=\"9925\">BE0987654321</cbc:EndpointID>",
+            "<cac:PartyTaxScheme><cbc:CompanyID>BE0987654321</cbc:CompanyID>",
+            "<cac:AccountingCustomerParty><cac:Party>\
+<cbc:EndpointID schemeID=\"9925\">BE0123456749</cbc:EndpointID>",
+            "<cac:PaymentMeans><cbc:PaymentMeansCode name=\"Credit transfer\">30\
+</cbc:PaymentMeansCode><cbc:PaymentID>2018-01</cbc:PaymentID><cac:PayeeFinancialAccount>\
+<cbc:ID>BE68539007547034</cbc:ID>",
+            "<cac:PaymentTerms><cbc:Note>net 30 days</cbc:Note></cac:PaymentTerms>",
+            &format!(
+                "<cac:TaxTotal><cbc:TaxAmount currencyID=\"EUR\">24.00</cbc:TaxAmount>\
+<cac:TaxSubtotal><cbc:TaxableAmount currencyID=\"EUR\">50.00</cbc:TaxableAmount>\
+<cbc:TaxAmount currencyID=\"EUR\">3.00</cbc:TaxAmount><cac:TaxCategory>{}</cac:TaxCategory>\
+</cac:TaxSubtotal>\
+<cac:TaxSubtotal><cbc:TaxableAmount currencyID=\"EUR\">100.00</cbc:TaxableAmount>\
+<cbc:TaxAmount currencyID=\"EUR\">21.00</cbc:TaxAmount><cac:TaxCategory>{}</cac:TaxCategory>\
+</cac:TaxSubtotal></cac:TaxTotal>",
+                category("S", "6"),
+                category("S", "21")
+            ),
+            "<cac:LegalMonetaryTotal>\
+<cbc:LineExtensionAmount currencyID=\"EUR\">150.00</cbc:LineExtensionAmount>\
+<cbc:TaxExclusiveAmount currencyID=\"EUR\">150.00</cbc:TaxExclusiveAmount>\
+<cbc:TaxInclusiveAmount currencyID=\"EUR\">174.00</cbc:TaxInclusiveAmount>\
+<cbc:PayableAmount currencyID=\"EUR\">174.00</cbc:PayableAmount></cac:LegalMonetaryTotal>",
+            &format!(
+                "<cac:InvoiceLine><cbc:ID>2</cbc:ID>\
+<cbc:InvoicedQuantity unitCode=\"C62\">1</cbc:InvoicedQuantity>\
+<cbc:LineExtensionAmount currencyID=\"EUR\">50.00</cbc:LineExtensionAmount>\
+<cac:Item><cbc:Name>Work</cbc:Name><cac:ClassifiedTaxCategory>{}</cac:ClassifiedTaxCategory>\
+</cac:Item><cac:Price><cbc:PriceAmount currencyID=\"EUR\">50.00</cbc:PriceAmount></cac:Price>\
+</cac:InvoiceLine></Invoice>",
+                category("S", "6")
+            ),
+        ];
+        for expected in &expected {
+            assert!(ubl.contains(expected), "{} not in {}", expected, ubl);
+        }
+        assert_eq!(ubl.matches("<cac:InvoiceLine>").count(), 2);
+    }
+
+    #[test]
+    fn credit_note_with_discount() {
+        let discount = Discount::Fixed(Money::new(1000, Currency::Eur));
+        let mut credit_note = invoice(&[(10000, "21")], Some(discount));
+        credit_note.index.kind = Kind::CreditNote;
+        credit_note.metadata.credits = Some("2018-01".parse().unwrap());
+        let ubl = ubl(&credit_note, &[]).unwrap();
+        let expected = [
+            "<CreditNote xmlns=\"urn:oasis:names:specification:ubl:schema:xsd:CreditNote-2\"",
+            "<cbc:CreditNoteTypeCode>381</cbc:CreditNoteTypeCode>",
+            "<cac:BillingReference><cac:InvoiceDocumentReference><cbc:ID>2018-01</cbc:ID>\
+</cac:InvoiceDocumentReference></cac:BillingReference>",
+            &format!(
+                "<cac:AllowanceCharge><cbc:ChargeIndicator>false</cbc:ChargeIndicator>\
+<cbc:AllowanceChargeReasonCode>95</cbc:AllowanceChargeReasonCode>\
+<cbc:AllowanceChargeReason>Discount</cbc:AllowanceChargeReason>\
+<cbc:Amount currencyID=\"EUR\">10.00</cbc:Amount><cac:TaxCategory>{}</cac:TaxCategory>\
+</cac:AllowanceCharge>",
+                category("S", "21")
+            ),
+            "<cbc:TaxExclusiveAmount currencyID=\"EUR\">90.00</cbc:TaxExclusiveAmount>\
+<cbc:TaxInclusiveAmount currencyID=\"EUR\">108.90</cbc:TaxInclusiveAmount>\
+<cbc:AllowanceTotalAmount currencyID=\"EUR\">10.00</cbc:AllowanceTotalAmount>",
+            "<cac:CreditNoteLine><cbc:ID>1</cbc:ID>\
+<cbc:CreditedQuantity unitCode=\"C62\">1</cbc:CreditedQuantity>",
+        ];
+        for expected in &expected {
+            assert!(ubl.contains(expected), "{} not in {}", expected, ubl);
+        }
+        assert!(!ubl.contains("<cbc:DueDate>"));
+        assert!(!ubl.contains("<cac:PaymentMeans>"));
+    }
+
+    #[test]
+    fn not_subject_to_vat() {
+        let mut unregistered = invoice(&[(10000, "0")], None);
+        unregistered.metadata.business.vat.clear();
+        unregistered.metadata.business.details.email = "me@example.com".to_string();
+        let ubl = ubl(&unregistered, &[]).unwrap();
+        let category = "<cbc:ID>O</cbc:ID><cbc:Percent>0</cbc:Percent>\
+<cbc:TaxExemptionReasonCode>VATEX-EU-O</cbc:TaxExemptionReasonCode>\
+<cbc:TaxExemptionReason>Not subject to VAT</cbc:TaxExemptionReason>";
+        assert_eq!(ubl.matches(category).count(), 2, "{}", ubl);
+        assert!(ubl.contains("<cbc:EndpointID schemeID=\"EM\">me@example.com</cbc:EndpointID>"));
+        // Neither party's VAT number is given (BR-O-02).
+        assert!(!ubl.contains("<cac:PartyTaxScheme>"), "{}", ubl);
+        assert!(!ubl.contains("<cbc:ID>Z</cbc:ID>"));
+    }
+
+    #[test]
+    fn unusable_documents() {
+        let notes = env::temp_dir().join(format!("ubl-notes-{}.txt", process::id()));
+        let error = ubl(&invoice(&[(10000, "21")], None), &[notes]).err().unwrap().to_string();
+        assert!(error.contains("cannot embed `ubl-notes-"), "{}", error);
+
+        let mut no_address = invoice(&[(10000, "21")], None);
+        no_address.metadata.client.vat = "US123".to_string();
+        let error = ubl(&no_address, &[]).err().unwrap().to_string();
+        assert!(error.contains("client `Acme` needs a European VAT number"), "{}", error);
+    }
+}
@@ -0,0 +1,697 @@
+use config::Config;
+use csv::{files, years, CsvTree};
+use csv_parser::{Reader, StringRecord};
+use error::{Error, Result};
+use invoice::{Amount, Currency, Date, Discount, Invoice, InvoiceIndex, Kind, Quantity, VatRate};
+use ledger::Ledger;
+use recurring;
+use source::{CreditNotes, DataSource, Year};
+use sqlite::Database;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// A mistake found in the data tree.
+#[derive(Debug)]
+pub struct Problem {
+    pub path: PathBuf,
+    /// The line of the offending row, if the problem concerns a single row.
+    pub line: Option<u64>,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.path.display(), line, self.message),
+            None => write!(f, "{}: {}", self.path.display(), self.message),
+        }
+    }
+}
+
+/// The rows of a CSV file that has every expected column.
+struct Table {
+    path: PathBuf,
+    headers: StringRecord,
+    rows: Vec<StringRecord>,
+}
+
+struct Row<'a> {
+    table: &'a Table,
+    record: &'a StringRecord,
+}
+
+impl<'a> Row<'a> {
+    fn line(&self) -> Option<u64> {
+        self.record.position().map(|position| position.line())
+    }
+
+    fn get(&self, column: &str) -> &'a str {
+        let index = self.table.headers.iter().position(|header| header == column);
+        index.and_then(|index| self.record.get(index)).unwrap_or("")
+    }
+}
+
+impl Table {
+    fn rows(&self) -> impl Iterator<Item = Row<'_>> {
+        self.rows.iter().map(move |record| Row {
+            table: self,
+            record,
+        })
+    }
+}
+
+#[derive(Default)]
+struct Validator {
+    problems: Vec<Problem>,
+    /// The invoices that credit notes refer to, with where they do.
+    credited: Vec<(PathBuf, Option<u64>, InvoiceIndex)>,
+}
+
+impl Validator {
+    fn report(&mut self, path: &Path, line: Option<u64>, message: String) {
+        self.problems.push(Problem {
+            path: path.to_owned(),
+            line,
+            message,
+        });
+    }
+
+    fn report_row(&mut self, row: &Row, message: String) {
+        self.report(&row.table.path, row.line(), message);
+    }
+
+    /// Reads the CSV file at `path`, reporting unreadable rows and missing
+    /// columns. Returns `None` if the file cannot be used at all.
+    fn read(&mut self, path: PathBuf, columns: &[&str]) -> Option<Table> {
+        let mut reader = match Reader::from_path(&path) {
+            Ok(reader) => reader,
+            Err(error) => {
+                self.report(&path, None, error.to_string());
+                return None;
+            },
+        };
+
+        let headers = match reader.headers() {
+            Ok(headers) => headers.clone(),
+            Err(error) => {
+                self.report(&path, Some(1), error.to_string());
+                return None;
+            },
+        };
+
+        let missing = columns
+            .iter()
+            .filter(|column| !headers.iter().any(|header| header == **column))
+            .collect::<Vec<_>>();
+        for column in &missing {
+            self.report(&path, Some(1), format!("missing column `{}`", column));
+        }
+        if !missing.is_empty() {
+            return None;
+        }
+
+        let mut rows = vec![];
+        for record in reader.records() {
+            match record {
+                Ok(record) => rows.push(record),
+                Err(error) => {
+                    let line = error.position().map(|position| position.line());
+                    self.report(&path, line, error.to_string());
+                },
+            }
+        }
+
+        Some(Table {
+            path,
+            headers,
+            rows,
+        })
+    }
+
+    fn mandatory(&mut self, row: &Row, columns: &[&str]) {
+        for column in columns {
+            if row.get(column).trim().is_empty() {
+                self.report_row(row, format!("empty `{}`", column));
+            }
+        }
+    }
+
+    /// Parses the `column` field of `row` if it is present and not empty,
+    /// reporting a problem if it is invalid.
+    fn parse_optional<T: FromStr>(&mut self, row: &Row, column: &str) -> Option<T> {
+        if row.get(column).is_empty() {
+            return None;
+        }
+        self.parse(row, column)
+    }
+
+    /// Parses the `column` field of `row` as an amount if it is present and
+    /// not empty, reporting a problem if it is invalid.
+    fn parse_amount(&mut self, row: &Row, column: &str, separator: char) {
+        let value = row.get(column);
+        if value.is_empty() {
+            return;
+        }
+        if let Err(error) = Amount::parse(value, separator) {
+            self.report_row(row, format!("`{}`: {}", column, error));
+        }
+    }
+
+    /// Parses the `discount` field of `row` if it is present and not empty,
+    /// reporting a problem if it is invalid.
+    fn parse_discount(&mut self, row: &Row, separator: char) {
+        let value = row.get("discount");
+        if value.is_empty() {
+            return;
+        }
+        if let Err(error) = Discount::parse(value, separator) {
+            self.report_row(row, format!("`discount`: {}", error));
+        }
+    }
+
+    fn duplicate(&mut self, row: &Row, what: String, first: Option<u64>) {
+        let message = match first {
+            Some(first) => format!("duplicate {}, first used on line {}", what, first),
+            None => format!("duplicate {}", what),
+        };
+        self.report_row(row, message);
+    }
+
+    /// Parses the `column` field of `row`, reporting a problem if it is empty
+    /// or invalid.
+    fn parse<T: FromStr>(&mut self, row: &Row, column: &str) -> Option<T> {
+        let value = row.get(column);
+        if value.trim().is_empty() {
+            self.report_row(row, format!("empty `{}`", column));
+            return None;
+        }
+        let parsed = value.parse().ok();
+        if parsed.is_none() {
+            self.report_row(row, format!("invalid `{}` `{}`", column, value));
+        }
+        parsed
+    }
+
+    /// Reads a table of records identified by an `id` column, reporting
+    /// duplicate ids, and returns the set of ids.
+    fn read_ids(
+        &mut self,
+        path: PathBuf,
+        columns: &[&str],
+        mandatory: &[&str],
+        numeric: &[&str],
+    ) -> HashSet<String> {
+        let mut ids = HashMap::new();
+        let table = match self.read(path, columns) {
+            Some(table) => table,
+            None => return HashSet::new(),
+        };
+
+        for row in table.rows() {
+            self.mandatory(&row, mandatory);
+            for column in numeric {
+                self.parse::<u32>(&row, column);
+            }
+            self.parse_optional::<Currency>(&row, "currency");
+
+            let id = row.get("id");
+            if id.is_empty() {
+                continue;
+            }
+            match ids.get(id) {
+                Some(&first) => self.duplicate(&row, format!("id `{}`", id), first),
+                None => {
+                    ids.insert(id.to_string(), row.line());
+                },
+            }
+        }
+
+        ids.into_keys().collect()
+    }
+
+    /// Reports `error`, attributing it to `path` unless it concerns a single
+    /// record.
+    fn report_error(&mut self, path: &Path, error: Error) {
+        match error {
+            Error::InvalidRecord {
+                path,
+                line,
+                message,
+            } => self.report(&path, line, message),
+            error => self.report(path, None, error.to_string()),
+        }
+    }
+
+    /// Builds every document of `kind` in `year` in `source` as `generate`
+    /// does, reporting those that cannot be built against `path`, and returns
+    /// the others. Credit notes are built on their own, so they are not
+    /// applied to the invoices they credit.
+    fn build(
+        &mut self,
+        source: &dyn DataSource,
+        config: &Config,
+        kind: Kind,
+        year: u16,
+        path: &Path,
+    ) -> Vec<Invoice> {
+        let data = match Year::from_source(source, config.clone(), kind, year, &CreditNotes::new())
+        {
+            Ok(data) => data,
+            Err(error) => {
+                self.report_error(path, error);
+                return vec![];
+            },
+        };
+        let mut invoices = vec![];
+        for index in data.indices() {
+            match data.invoice(index) {
+                Ok(invoice) => invoices.push(invoice),
+                Err(error) => self.report_error(path, error),
+            }
+        }
+        invoices
+    }
+
+    /// Reports payments in `source` for invoices of `year` that do not exist.
+    fn check_payments(&mut self, source: &dyn DataSource, year: u16, path: &Path) {
+        // Unreadable payments and invoices are reported when the invoices are
+        // built.
+        let (payments, invoices) =
+            match (source.payments(year), source.invoices(Kind::Invoice, year)) {
+                (Ok(payments), Ok(invoices)) => (payments, invoices),
+                _ => return,
+            };
+        let mut unknown =
+            payments.keys().filter(|index| !invoices.contains_key(index)).collect::<Vec<_>>();
+        unknown.sort();
+        for &index in unknown {
+            let index = InvoiceIndex {
+                kind: Kind::Invoice,
+                year,
+                index,
+            };
+            self.report(path, None, format!("payment for unknown invoice {}", index));
+        }
+    }
+
+    /// Checks `recurring.csv` in the data directory at `path`, if there is
+    /// one, as `generate-invoice recurring` reads it.
+    fn validate_recurring(&mut self, path: &Path, source: &dyn DataSource, config: &Config) {
+        let file = path.join("recurring.csv");
+        if !file.exists() {
+            return;
+        }
+        if let Err(error) = recurring::read_schedules(path, config, source) {
+            self.report_error(&file, error);
+        }
+    }
+
+    /// Reports credit notes that refer to an invoice not in `invoices`.
+    fn check_credited(&mut self, invoices: &HashSet<InvoiceIndex>) {
+        for (path, line, credited) in ::std::mem::take(&mut self.credited) {
+            if credited.kind != Kind::Invoice {
+                self.report(&path, line, format!("`credits` {} is not an invoice", credited));
+            } else if !invoices.contains(&credited) {
+                self.report(&path, line, format!("credits unknown invoice {}", credited));
+            }
+        }
+    }
+
+    /// Checks the documents of `kind` in a year folder and returns their
+    /// indices.
+    fn validate_year(
+        &mut self,
+        folder: &Path,
+        kind: Kind,
+        clients: &HashSet<String>,
+        businesses: &HashSet<String>,
+        separator: char,
+    ) -> HashSet<u8> {
+        let (invoices_file, data_file) = files(kind);
+        if kind == Kind::CreditNote && !folder.join(invoices_file).exists() {
+            return HashSet::new();
+        }
+
+        let mut invoices = HashMap::new();
+        let path = folder.join(invoices_file);
+        let mut columns = vec!["index", "business", "client", "year", "month", "day"];
+        if kind == Kind::CreditNote {
+            columns.push("credits");
+        }
+        if let Some(table) = self.read(path, &columns) {
+            for row in table.rows() {
+                let index = self.parse::<u8>(&row, "index");
+
+                let business = row.get("business");
+                if business.is_empty() {
+                    self.report_row(&row, "empty `business`".to_string());
+                } else if !businesses.contains(business) {
+                    self.report_row(&row, format!("unknown business `{}`", business));
+                }
+
+                let client = row.get("client");
+                if client.is_empty() {
+                    self.report_row(&row, "empty `client`".to_string());
+                } else if !clients.contains(client) {
+                    self.report_row(&row, format!("unknown client `{}`", client));
+                }
+
+                let year = self.parse::<u16>(&row, "year");
+                let month = self.parse::<u8>(&row, "month");
+                let day = self.parse::<u8>(&row, "day");
+                if let (Some(year), Some(month), Some(day)) = (year, month, day) {
+                    if Date::new(year, month, day).is_none() {
+                        let message = format!("invalid date {:04}-{:02}-{:02}", year, month, day);
+                        self.report_row(&row, message);
+                    }
+                }
+                self.parse_optional::<Currency>(&row, "currency");
+                self.parse_discount(&row, separator);
+                if kind == Kind::CreditNote {
+                    if let Some(credited) = self.parse::<InvoiceIndex>(&row, "credits") {
+                        self.credited.push((table.path.clone(), row.line(), credited));
+                    }
+                }
+
+                if let Some(index) = index {
+                    match invoices.get(&index) {
+                        Some(&first) => self.duplicate(&row, format!("index {}", index), first),
+                        None => {
+                            invoices.insert(index, row.line());
+                        },
+                    }
+                }
+            }
+        }
+
+        let mut items = HashSet::new();
+        let path = folder.join(data_file);
+        let columns = ["index", "services", "attachment"];
+        if let Some(table) = self.read(path, &columns) {
+            for row in table.rows() {
+                if let Some(index) = self.parse::<u8>(&row, "index") {
+                    if !invoices.contains_key(&index) {
+                        let message = format!("line item for unknown {} {}", kind, index);
+                        self.report_row(&row, message);
+                    }
+                    items.insert(index);
+                }
+
+                self.parse_amount(&row, "price", separator);
+                self.parse_optional::<i64>(&row, "amount");
+                self.parse_optional::<Quantity>(&row, "quantity");
+                self.parse_amount(&row, "unit_price", separator);
+                self.parse_optional::<VatRate>(&row, "vat_rate");
+                self.parse_discount(&row, separator);
+                let present = |column| !row.get(column).is_empty();
+                if present("price") && present("amount") {
+                    self.report_row(&row, "both `price` and `amount` in cents".to_string());
+                }
+                let priced = present("price")
+                    || present("amount")
+                    || (present("quantity") && present("unit_price"));
+                if !priced {
+                    let message = "missing `price`, or `quantity` and `unit_price`".to_string();
+                    self.report_row(&row, message);
+                }
+
+                self.mandatory(&row, &["services"]);
+
+                let attachment = row.get("attachment");
+                if !attachment.is_empty() && !folder.join(attachment).is_file() {
+                    self.report_row(&row, format!("missing attachment `{}`", attachment));
+                }
+            }
+        }
+
+        let mut invoices = invoices.into_iter().collect::<Vec<_>>();
+        invoices.sort();
+        for &(index, line) in &invoices {
+            if !items.contains(&index) {
+                let message = format!("{} {} has no line items", kind, index);
+                self.report(&folder.join(invoices_file), line, message);
+            }
+        }
+        invoices.into_iter().map(|(index, _)| index).collect()
+    }
+
+    /// Checks `payments.csv` in a year folder, if there is one, against the
+    /// indices of the invoices of that year.
+    fn validate_payments(&mut self, folder: &Path, invoices: &HashSet<u8>, separator: char) {
+        let path = folder.join("payments.csv");
+        if !path.exists() {
+            return;
+        }
+        let table = match self.read(path, &["index", "date", "amount"]) {
+            Some(table) => table,
+            None => return,
+        };
+        for row in table.rows() {
+            if let Some(index) = self.parse::<u8>(&row, "index") {
+                if !invoices.contains(&index) {
+                    self.report_row(&row, format!("payment for unknown invoice {}", index));
+                }
+            }
+            self.parse::<Date>(&row, "date");
+            self.mandatory(&row, &["amount"]);
+            self.parse_amount(&row, "amount", separator);
+        }
+    }
+}
+
+/// Builds every invoice in `source`, reporting those that cannot be built.
+/// Errors that do not concern a single file are attributed to `path`.
+fn validate_source(source: &dyn DataSource, config: &Config, path: &Path) -> Vec<Problem> {
+    let mut validator = Validator::default();
+
+    // Attachments are relative to the year folders next to the ledger file or
+    // the database.
+    let data_dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let years = match source.years() {
+        Ok(years) => years,
+        Err(error) => {
+            validator.report_error(path, error);
+            return validator.problems;
+        },
+    };
+    let mut invoices = HashSet::new();
+    let mut references = vec![];
+    for year in years {
+        validator.check_payments(source, year, path);
+        for &kind in &Kind::ALL {
+            for invoice in validator.build(source, config, kind, year, path) {
+                if let Some(credited) = invoice.metadata.credits {
+                    references.push((path.to_owned(), None, credited));
+                }
+                let folder = data_dir.join(year.to_string());
+                for item in &invoice.items {
+                    match item.attachment {
+                        Some(ref attachment) if !folder.join(attachment).is_file() => {
+                            let message = format!(
+                                "{} {}: missing attachment `{}`",
+                                kind, invoice.index, attachment
+                            );
+                            validator.report(path, None, message);
+                        },
+                        _ => {},
+                    }
+                }
+                invoices.insert(invoice.index);
+            }
+        }
+    }
+    validator.credited = references;
+    validator.check_credited(&invoices);
+    validator.validate_recurring(data_dir, source, config);
+    validator.problems
+}
+
+/// Checks every CSV file in the data tree at `path` and returns all
+/// problems found.
+pub fn validate(path: &Path) -> Result<Vec<Problem>> {
+    trace!("validate");
+
+    let config = Config::read(path)?;
+    if let Some((file, format)) = Ledger::find(path) {
+        return Ok(match Ledger::check(file.clone(), format, &config) {
+            Ok((ledger, errors)) => {
+                let mut validator = Validator::default();
+                for error in errors {
+                    validator.report_error(&file, error);
+                }
+                validator.problems.extend(validate_source(&ledger, &config, &file));
+                validator.problems
+            },
+            Err(Error::InvalidRecord {
+                path,
+                line,
+                message,
+            }) => vec![Problem {
+                path,
+                line,
+                message,
+            }],
+            Err(error) => return Err(error),
+        });
+    }
+
+    if let Some(file) = Database::find(path) {
+        let database = Database::open(file.clone())?;
+        return Ok(validate_source(&database, &config, &file));
+    }
+
+    let mut validator = Validator::default();
+
+    let clients = validator.read_ids(
+        path.join("clients.csv"),
+        &["id", "name", "street", "city", "country", "vat", "vatpolicy", "partyid"],
+        &["id", "name", "street", "city", "country"],
+        &["partyid"],
+    );
+    let businesses = validator.read_ids(
+        path.join("businesses.csv"),
+        &["id", "name", "street", "city", "country", "vat", "bank", "iban", "bic"],
+        &["id", "name", "street", "city", "country", "iban"],
+        &[],
+    );
+    let parties_valid = validator.problems.is_empty();
+
+    let mut invoices = HashSet::new();
+    let mut valid_years = vec![];
+    for year in years(path)? {
+        let problems = validator.problems.len();
+        let folder = path.join(year.to_string());
+        for &kind in &Kind::ALL {
+            let indices = validator.validate_year(
+                &folder,
+                kind,
+                &clients,
+                &businesses,
+                config.decimal_separator,
+            );
+            if kind == Kind::Invoice {
+                validator.validate_payments(&folder, &indices, config.decimal_separator);
+                invoices.extend(indices.into_iter().map(|index| InvoiceIndex {
+                    kind,
+                    year,
+                    index,
+                }));
+            }
+        }
+        if validator.problems.len() == problems {
+            valid_years.push(year);
+        }
+    }
+    validator.check_credited(&invoices);
+
+    // The checks above point at the offending rows; building the invoices as
+    // `generate` does applies every other rule. Files with problems already
+    // would only fail again.
+    if parties_valid {
+        let source = CsvTree::new(path, &config);
+        for year in valid_years {
+            for &kind in &Kind::ALL {
+                let file = path.join(year.to_string()).join(files(kind).0);
+                validator.build(&source, &config, kind, year, &file);
+            }
+        }
+        validator.validate_recurring(path, &source, &config);
+    }
+
+    Ok(validator.problems)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use csv::tests::tree;
+    use std::fs;
+
+    const INVOICES: &str = "index,business,client,year,month,day,discount
+1,me,acme,2018,1,31,
+";
+    const DATA: &str = "index,price,services,attachment
+1,100.00,Consulting,
+";
+
+    /// The problems found in a tree with `files` next to an invoice, as
+    /// lines of their file names and messages.
+    fn problems(name: &str, files: &[(&str, &str)]) -> Vec<String> {
+        let mut all = vec![("2018/invoices.csv", INVOICES), ("2018/data.csv", DATA)];
+        all.retain(|&(file, _)| !files.iter().any(|&(other, _)| other == file));
+        all.extend(files);
+        let path = tree(name, &all);
+        let problems = validate(&path);
+        let _ = fs::remove_dir_all(&path);
+        problems
+            .unwrap()
+            .into_iter()
+            .map(|problem| {
+                let file = problem.path.strip_prefix(&path).unwrap().display().to_string();
+                match problem.line {
+                    Some(line) => format!("{}:{}: {}", file, line, problem.message),
+                    None => format!("{}: {}", file, problem.message),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn valid_tree() {
+        assert_eq!(problems("valid", &[]), Vec::<String>::new());
+    }
+
+    #[test]
+    fn business_without_vat_or_bic() {
+        let businesses = "id,name,street,city,country,vat,bank,iban,bic
+me,My Business,2 High Street,Ghent,Belgium,,,BE68539007547034,
+";
+        assert_eq!(problems("optional", &[("businesses.csv", businesses)]), Vec::<String>::new());
+    }
+
+    #[test]
+    fn row_problems() {
+        let invoices = "index,business,client,year,month,day
+1,me,nobody,2018,2,30
+";
+        assert_eq!(
+            problems("rows", &[("2018/invoices.csv", invoices)]),
+            [
+                "2018/invoices.csv:2: unknown client `nobody`",
+                "2018/invoices.csv:2: invalid date 2018-02-30"
+            ]
+        );
+    }
+
+    #[test]
+    fn rules_of_the_loader() {
+        let data = "index,price,services,attachment
+1,100.001,Consulting,
+";
+        assert_eq!(
+            problems("decimals", &[("2018/data.csv", data)]),
+            ["2018/data.csv:2: more than 2 decimals for an amount in EUR"]
+        );
+
+        let invoices = "index,business,client,year,month,day,discount
+1,me,acme,2018,1,31,150.00
+";
+        assert_eq!(
+            problems("discount", &[("2018/invoices.csv", invoices)]),
+            ["2018/invoices.csv: invoice 2018-01: discount €150.00 exceeds the subtotal"]
+        );
+    }
+
+    #[test]
+    fn recurring_schedules() {
+        let recurring = "id,business,client,frequency,start,price,services,attachment
+hosting,me,nobody,monthly,2018-01-01,10.00,Hosting,
+";
+        assert_eq!(
+            problems("recurring", &[("recurring.csv", recurring)]),
+            ["recurring.csv: schedule `hosting`: unknown client `nobody`"]
+        );
+    }
+}
@@ -0,0 +1,64 @@
+/// Writes an indented XML document, one element at a time.
+pub struct XmlWriter {
+    output: String,
+    depth: usize,
+}
+
+impl XmlWriter {
+    pub fn new() -> Self {
+        Self {
+            output: "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n".to_string(),
+            depth: 0,
+        }
+    }
+
+    fn open(&mut self, name: &str, attributes: &[(&str, &str)]) {
+        for _ in 0..self.depth {
+            self.output += "  ";
+        }
+        self.output += "<";
+        self.output += name;
+        for &(name, value) in attributes {
+            self.output += &format!(" {}=\"{}\"", name, escape(value));
+        }
+        self.output += ">";
+    }
+
+    /// Writes an element whose children are written by `content`.
+    pub fn element<F>(&mut self, name: &str, attributes: &[(&str, &str)], content: F)
+    where
+        F: FnOnce(&mut Self),
+    {
+        self.open(name, attributes);
+        self.output += "\n";
+        self.depth += 1;
+        content(self);
+        self.depth -= 1;
+        for _ in 0..self.depth {
+            self.output += "  ";
+        }
+        self.output += &format!("</{}>\n", name);
+    }
+
+    /// Writes an element containing only `text`.
+    pub fn text(&mut self, name: &str, attributes: &[(&str, &str)], text: &str) {
+        self.open(name, attributes);
+        self.output += &escape(text);
+        self.output += &format!("</{}>\n", name);
+    }
+
+    /// Writes an element containing only `text`, unless `text` is empty.
+    pub fn optional(&mut self, name: &str, text: &str) {
+        if !text.is_empty() {
+            self.text(name, &[], text);
+        }
+    }
+
+    pub fn finish(self) -> String {
+        self.output
+    }
+}
+
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}